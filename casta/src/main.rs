@@ -1,38 +1,57 @@
+mod offline;
 mod sasta;
 mod websocket;
 
 use std::fs::File;
 use std::io::BufReader;
+use std::path::PathBuf;
 use std::process;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{env, fmt::Debug};
 
 use actix::Addr;
-use actix_files::Files;
+use actix_files::{Files, NamedFile};
 use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws::WsResponseBuilder;
 use adler::adler32;
 use casta_protocol::uuid::Uuid;
 use casta_protocol::ResponsePayload as SastaPayload;
-use casta_protocol::{DisplayPayload, WebsitePayload};
+use casta_protocol::{Command, DisplayPayload, DisplaySettings, Transition, WebsitePayload};
 use dotenv::dotenv;
 use lazy_static::lazy_static;
+use offline::{FileCache, Recorder};
 use sasta::Sasta;
 use serde::Serialize;
+use tokio::process::Command as Process;
 use tokio::signal;
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 
 //Other solutions not including a shared mutable state are welcome
 lazy_static! {
     static ref HANDLE: Mutex<Option<Addr<websocket::Websocket>>> = Mutex::new(None);
+    // Latest transition from Sasta, sent again to the view when it reconnects
+    static ref TRANSITION: Mutex<Transition> = Mutex::new(Transition::default());
+    // Latest display settings from Sasta, sent again to the view when it reconnects
+    static ref SETTINGS: Mutex<DisplaySettings> = Mutex::new(DisplaySettings::default());
 }
 
+const SETTINGS_FILE: &str = "settings.json";
+
 #[derive(Serialize, Debug, Clone)]
 pub enum ClientPayload {
     Display(DisplayPayload),
+    /// Load the payload off-screen, so it can be shown without delay by a following Display
+    Prepare(DisplayPayload),
+    Transition(Transition),
+    Settings(DisplaySettings),
     Disconnected(),
     Hash(String),
+    Reload(),
+    /// Show the name of the display on top of the content
+    Identify(String),
 }
 
 async fn send_to_view(payload: ClientPayload) -> String {
@@ -80,6 +99,52 @@ async fn get_ws(
     resp
 }
 
+///Returns a file from Sasta, cached to be available while Sasta cannot be reached
+#[get("/files/{path:.*}")]
+async fn get_file(
+    path: web::Path<String>,
+    file_cache: web::Data<FileCache>,
+    req: HttpRequest,
+) -> HttpResponse {
+    match file_cache.get(&path).await {
+        Ok(local) => match NamedFile::open_async(local).await {
+            Ok(file) => file.into_response(&req),
+            Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+        },
+        Err(e) => {
+            println!("[Offline] Could not get file {path:?}: {e}");
+            HttpResponse::NotFound().body(e)
+        }
+    }
+}
+
+/// Runs a command configured in the environment through the shell, returning its output
+async fn run_configured(var: &str) -> Result<Vec<u8>, String> {
+    let command = env::var(var).map_err(|_| format!("{var} is not set"))?;
+    let output = tokio::time::timeout(
+        Duration::from_secs(30),
+        Process::new("sh").arg("-c").arg(&command).output(),
+    )
+    .await
+    .map_err(|_| format!("{command:?} timed out"))?
+    .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!(
+            "{command:?} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
 async fn send_disconnected_to_view(cached_display_req: Arc<Mutex<Option<ClientPayload>>>) {
     let api = ClientPayload::Disconnected();
     send_to_view(api.clone()).await;
@@ -95,6 +160,8 @@ async fn main() -> std::io::Result<()> {
     let casta_port = env::var("CASTA_PORT").unwrap_or("3000".to_string());
     let address = env::var("ADDRESS").expect("Must provide an address");
     let hostname = env::var("HOSTNAME").unwrap_or("Casta Client".to_string());
+    let cache_dir = PathBuf::from(env::var("CACHE_DIR").unwrap_or("./cache".to_string()));
+    std::fs::create_dir_all(&cache_dir).expect("Could not create cache directory");
 
     let file = File::open("./static/target/index.js")
         .expect("Error frontend does not seem to be built, run \"npm run build\"");
@@ -110,6 +177,8 @@ async fn main() -> std::io::Result<()> {
 
     let cached_display_req = Arc::new(Mutex::new(None::<ClientPayload>));
     let cached_display_req_2 = cached_display_req.clone();
+    let file_cache = FileCache::new(&cache_dir, format!("http://{address}:{port}"));
+    let file_cache_2 = file_cache.clone();
 
     tokio::task::spawn(async {
         match signal::ctrl_c().await {
@@ -121,20 +190,148 @@ async fn main() -> std::io::Result<()> {
         process::exit(0);
     });
 
+    // Settings from the last connection, so the content is presented the same way while Sasta cannot be reached
+    let settings_path = cache_dir.join(SETTINGS_FILE);
+    if let Some(settings) = std::fs::read(&settings_path)
+        .ok()
+        .and_then(|s| serde_json::from_slice(&s).ok())
+    {
+        *SETTINGS.lock().await = settings;
+    }
+
     tokio::task::spawn(async move {
-        send_disconnected_to_view(cached_display_req.clone()).await;
-        let mut sasta = Sasta::new(address, port, uuid).await;
+        let mut recorder = Recorder::load(&cache_dir);
+        let rotate = |recorder: &Recorder| {
+            let rotation = recorder.rotation();
+            let cached_display_req = cached_display_req.clone();
+            async move {
+                if rotation.items.is_empty() {
+                    send_disconnected_to_view(cached_display_req).await;
+                    None
+                } else {
+                    Some(tokio::task::spawn(offline::play(rotation, cached_display_req)))
+                }
+            }
+        };
+
+        // Rotates on its own through the Playlist sent by Sasta,
+        // or through what was recorded while Sasta cannot be reached
+        let mut rotating: Option<JoinHandle<()>> = rotate(&recorder).await;
+        let mut sasta = Sasta::new(address, port, uuid, hostname).await;
+        // Offsets between the clock of Sasta and this one, the largest one had the least network delay
+        let mut clock_offsets: Vec<i64> = Vec::new();
+        let mut scheduled: Option<JoinHandle<()>> = None;
 
         loop {
             match sasta.read_message().await {
                 Some(resp) => {
                     let mut api: Option<ClientPayload> = None;
+                    // Anything new from Sasta replaces what was waiting to be shown
+                    if !matches!(resp, SastaPayload::Command(_)) {
+                        if let Some(handle) = scheduled.take() {
+                            handle.abort();
+                        }
+                    }
+                    let keeps_rotation = matches!(
+                        resp,
+                        SastaPayload::Welcome { .. }
+                            | SastaPayload::Prepare(_)
+                            | SastaPayload::Playlist { .. }
+                            | SastaPayload::Command(_)
+                    );
+                    if !keeps_rotation {
+                        if let Some(handle) = rotating.take() {
+                            handle.abort();
+                        }
+                    }
                     match resp {
-                        SastaPayload::Welcome { name, .. } => {
+                        SastaPayload::Welcome {
+                            name,
+                            transition,
+                            version,
+                            capabilities,
+                            settings,
+                            ..
+                        } => {
                             println!("Handshake done, received name {name:?}");
+                            println!(
+                                "Sasta uses protocol version {version} with capabilities {capabilities:?}"
+                            );
+                            *TRANSITION.lock().await = transition;
+                            send_to_view(ClientPayload::Transition(transition)).await;
+                            if *SETTINGS.lock().await != settings {
+                                println!("[Settings] {:?}", settings);
+                                let result = serde_json::to_vec(&settings)
+                                    .map_err(|e| e.to_string())
+                                    .and_then(|s| std::fs::write(&settings_path, s).map_err(|e| e.to_string()));
+                                if let Err(e) = result {
+                                    println!("[Settings] Could not persist settings to {:?}: {e}", settings_path);
+                                }
+                                *SETTINGS.lock().await = settings.clone();
+                                send_to_view(ClientPayload::Settings(settings)).await;
+                            }
+                        }
+                        SastaPayload::Playlist {
+                            version,
+                            items,
+                            shuffle,
+                        } => {
+                            println!("[Playlist] Version {version} with {} items", items.len());
+                            items.iter().for_each(|i| file_cache.cache(&i.payload));
+                            // Keep rotating if the Playlist is the same, like after a reconnect
+                            let unchanged = recorder.rotation().version == Some(version);
+                            recorder.set_playlist(version, items, shuffle);
+                            file_cache.prune(recorder.rotation().items.iter().map(|i| &i.payload));
+                            if !unchanged || rotating.is_none() {
+                                if let Some(handle) = rotating.take() {
+                                    handle.abort();
+                                }
+                                rotating = rotate(&recorder).await;
+                            }
+                        }
+                        SastaPayload::Command(command) => {
+                            println!("[Command] {:?}", command);
+                            match command {
+                                Command::Reload => {
+                                    send_to_view(ClientPayload::Reload()).await;
+                                }
+                                Command::Identify { name } => {
+                                    send_to_view(ClientPayload::Identify(name)).await;
+                                }
+                                Command::Screenshot => {
+                                    let result = match run_configured("SCREENSHOT_COMMAND").await {
+                                        Ok(png) => sasta.send_binary(png).await,
+                                        Err(e) => Err(e),
+                                    };
+                                    if let Err(e) = result {
+                                        println!("[Command] Could not send screenshot: {e}");
+                                    }
+                                }
+                                Command::ClearCache => {
+                                    recorder.clear();
+                                    if let Err(e) = file_cache.clear().await {
+                                        println!("[Command] Could not clear cache: {e}");
+                                    }
+                                }
+                                Command::Restart => {
+                                    if env::var("RESTART_COMMAND").is_err() {
+                                        println!("[Command] Exiting, expecting to be restarted");
+                                        process::exit(0);
+                                    }
+                                    if let Err(e) = run_configured("RESTART_COMMAND").await {
+                                        println!("[Command] Could not restart: {e}");
+                                    }
+                                }
+                            }
+                        }
+                        SastaPayload::Prepare(display) => {
+                            println!("[Prepare] {:?}", display);
+                            file_cache.cache(&display);
+                            send_to_view(ClientPayload::Prepare(display)).await;
                         }
                         SastaPayload::Pending(pending) => {
                             println!("[Pending {}] Waiting to be defined in Sasta", pending);
+                            recorder.clear();
                             let send =
                                 ClientPayload::Display(DisplayPayload::Text(WebsitePayload {
                                     content: format!(
@@ -147,10 +344,40 @@ async fn main() -> std::io::Result<()> {
                         }
                         SastaPayload::Display(display) => {
                             println!("[Message] {:?}", display);
+                            file_cache.cache(&display);
+                            if recorder.record(display.clone()) {
+                                file_cache.prune(recorder.rotation().items.iter().map(|i| &i.payload));
+                            }
                             let send = ClientPayload::Display(display);
                             send_to_view(send.clone()).await;
                             api = Some(send);
                         }
+                        SastaPayload::ScheduledDisplay {
+                            payload,
+                            show_at,
+                            server_time,
+                        } => {
+                            println!("[Message] {:?} at {}", payload, show_at);
+                            file_cache.cache(&payload);
+                            if recorder.record(payload.clone()) {
+                                file_cache.prune(recorder.rotation().items.iter().map(|i| &i.payload));
+                            }
+                            send_to_view(ClientPayload::Prepare(payload.clone())).await;
+                            clock_offsets.push(server_time as i64 - unix_millis());
+                            if clock_offsets.len() > 8 {
+                                clock_offsets.remove(0);
+                            }
+                            let offset = clock_offsets.iter().max().copied().unwrap_or_default();
+                            let wait = (show_at as i64 - offset - unix_millis()).max(0) as u64;
+
+                            let cached_display_req = cached_display_req.clone();
+                            scheduled = Some(tokio::task::spawn(async move {
+                                tokio::time::sleep(Duration::from_millis(wait)).await;
+                                let send = ClientPayload::Display(payload);
+                                send_to_view(send.clone()).await;
+                                *cached_display_req.lock().await = Some(send);
+                            }));
+                        }
                     }
 
                     // Updates mutex cache with new latest display request form Sasta
@@ -159,7 +386,13 @@ async fn main() -> std::io::Result<()> {
                     }
                 }
                 None => {
-                    send_disconnected_to_view(cached_display_req.clone()).await;
+                    if let Some(handle) = scheduled.take() {
+                        handle.abort();
+                    }
+                    if rotating.is_none() {
+                        rotating = rotate(&recorder).await;
+                    }
+                    recorder.interrupt();
                     sasta.reconnect().await
                 }
             }
@@ -170,7 +403,9 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .app_data(hash)
             .app_data(web::Data::from(cached_display_req_2.clone()))
+            .app_data(web::Data::new(file_cache_2.clone()))
             .service(get_ws)
+            .service(get_file)
             .service(Files::new("/", "./static").index_file("index.html"))
     })
     .bind(format!("0.0.0.0:{casta_port}"))?
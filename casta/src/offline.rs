@@ -0,0 +1,337 @@
+use std::collections::HashSet;
+use std::fs;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use casta_protocol::{DisplayPayload, PlaylistEntry};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{send_to_view, ClientPayload};
+
+/// Rotations longer than this are cut off, in case a playlist never seems to repeat
+const MAX_RECORDED: usize = 256;
+const ROTATION_FILE: &str = "rotation.json";
+const FILES_PREFIX: &str = "/files/";
+/// Directory in the cache downloads are written to before they are moved into place
+const DOWNLOAD_DIR: &str = "downloads";
+/// How long before the next payload is shown it is sent to the view to be loaded off-screen
+const PREPARE_LEAD: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedItem {
+    pub payload: DisplayPayload,
+    /// How long the payload was shown, None if it was still shown when the recording ended
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Rotation {
+    pub items: Vec<RecordedItem>,
+    /// Reshuffle the items every time the rotation starts over
+    #[serde(default)]
+    pub shuffle: bool,
+    /// Version of the Playlist sent by Sasta, None if the rotation was recorded
+    #[serde(default)]
+    pub version: Option<u64>,
+}
+
+/// Records payloads received from Sasta, so they can keep rotating while Sasta cannot be reached.
+///
+/// A rotation is complete once the first recorded payload is received again,
+/// or right away when Sasta sends the whole Playlist.
+pub struct Recorder {
+    path: PathBuf,
+    recording: Vec<RecordedItem>,
+    started: Option<Instant>,
+    rotation: Rotation,
+}
+
+impl Recorder {
+    /// Loads the last persisted rotation from the cache directory, if any
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = cache_dir.join(ROTATION_FILE);
+        let rotation = fs::read(&path)
+            .ok()
+            .and_then(|r| serde_json::from_slice(&r).ok())
+            .unwrap_or_default();
+        Recorder {
+            path,
+            recording: vec![],
+            started: None,
+            rotation,
+        }
+    }
+
+    /// Records the payload, returning whether it completed a rotation
+    pub fn record(&mut self, payload: DisplayPayload) -> bool {
+        let now = Instant::now();
+        if let (Some(last), Some(started)) = (self.recording.last_mut(), self.started) {
+            last.duration_ms = Some(now.duration_since(started).as_millis() as u64);
+        }
+        let repeated = self
+            .recording
+            .first()
+            .is_some_and(|first| first.payload == payload);
+        let completed = repeated || self.recording.len() >= MAX_RECORDED;
+        if completed {
+            self.rotation = Rotation {
+                items: mem::take(&mut self.recording),
+                ..Default::default()
+            };
+            self.persist();
+        }
+        self.recording.push(RecordedItem {
+            payload,
+            duration_ms: None,
+        });
+        self.started = Some(now);
+
+        // Persist what is known so far, so something can be shown before a full rotation was seen
+        if self.rotation.items.is_empty() {
+            self.persist();
+        }
+        completed
+    }
+
+    /// Replaces the rotation with the Playlist sent by Sasta
+    pub fn set_playlist(&mut self, version: u64, items: Vec<PlaylistEntry>, shuffle: bool) {
+        self.recording.clear();
+        self.started = None;
+        self.rotation = Rotation {
+            items: items
+                .into_iter()
+                .map(|i| RecordedItem {
+                    payload: i.payload,
+                    duration_ms: (i.duration != 0).then_some(i.duration * 1000),
+                })
+                .collect(),
+            shuffle,
+            version: Some(version),
+        };
+        self.persist();
+    }
+
+    /// Drops the rotation, used when the display is no longer defined in Sasta
+    pub fn clear(&mut self) {
+        self.recording.clear();
+        self.rotation = Rotation::default();
+        self.started = None;
+        self.persist();
+    }
+
+    /// Stops the current recording, since the shown payload lasts for an unknown time after a disconnect
+    pub fn interrupt(&mut self) {
+        self.recording.clear();
+        self.started = None;
+    }
+
+    /// The last complete rotation, or what was recorded so far if no rotation was completed
+    pub fn rotation(&self) -> Rotation {
+        if self.rotation.items.is_empty() {
+            Rotation {
+                items: self.recording.clone(),
+                ..Default::default()
+            }
+        } else {
+            self.rotation.clone()
+        }
+    }
+
+    fn persist(&self) {
+        let result = serde_json::to_vec(&self.rotation())
+            .map_err(|e| e.to_string())
+            .and_then(|r| fs::write(&self.path, r).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            println!(
+                "[Offline] Could not persist rotation to {:?}: {e}",
+                self.path
+            );
+        }
+    }
+}
+
+/// Shows the rotation over and over, until the task is aborted
+pub async fn play(rotation: Rotation, cached_display_req: Arc<Mutex<Option<ClientPayload>>>) {
+    if rotation.items.is_empty() {
+        return;
+    }
+    println!("[Rotation] Rotating {} payloads", rotation.items.len());
+    let order = || {
+        let mut items = rotation.items.clone();
+        if rotation.shuffle {
+            items.shuffle(&mut rand::thread_rng());
+        }
+        items
+    };
+
+    let mut items = order();
+    loop {
+        // Known ahead of time, so the first payload of the next round can be prepared
+        let next_items = order();
+        for (i, item) in items.iter().enumerate() {
+            let send = ClientPayload::Display(item.payload.clone());
+            send_to_view(send.clone()).await;
+            *cached_display_req.lock().await = Some(send);
+
+            let Some(ms) = item.duration_ms else {
+                return std::future::pending::<()>().await;
+            };
+            let duration = Duration::from_millis(ms.max(1000));
+            let next = items.get(i + 1).unwrap_or(&next_items[0]);
+            if duration > PREPARE_LEAD {
+                tokio::time::sleep(duration - PREPARE_LEAD).await;
+                send_to_view(ClientPayload::Prepare(next.payload.clone())).await;
+                tokio::time::sleep(PREPARE_LEAD).await;
+            } else {
+                tokio::time::sleep(duration).await;
+            }
+        }
+        items = next_items;
+    }
+}
+
+/// Files from Sasta referenced by payloads, kept on disk to be available while Sasta cannot be reached
+#[derive(Clone)]
+pub struct FileCache {
+    dir: PathBuf,
+    download_dir: PathBuf,
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl FileCache {
+    pub fn new(cache_dir: &Path, base_url: String) -> Self {
+        // Downloads cut off when Casta stopped are of no use
+        let download_dir = cache_dir.join(DOWNLOAD_DIR);
+        let _ = fs::remove_dir_all(&download_dir);
+        FileCache {
+            dir: cache_dir.join("files"),
+            download_dir,
+            base_url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Local path of a file below /files/, None if the path would point outside the cache
+    fn local_path(&self, path: &str) -> Option<PathBuf> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+            .then(|| self.dir.join(relative))
+    }
+
+    /// Returns the cached file, downloading it from Sasta if it is not cached yet
+    pub async fn get(&self, path: &str) -> Result<PathBuf, String> {
+        let local = self
+            .local_path(path)
+            .ok_or(format!("Invalid path {path:?}"))?;
+        if local.is_file() {
+            return Ok(local);
+        }
+        self.download(path).await
+    }
+
+    /// Downloads the file from Sasta, replacing any cached copy
+    pub async fn download(&self, path: &str) -> Result<PathBuf, String> {
+        let local = self
+            .local_path(path)
+            .ok_or(format!("Invalid path {path:?}"))?;
+        let url = format!(
+            "{}{FILES_PREFIX}{}",
+            self.base_url,
+            path.trim_start_matches('/')
+        );
+        let bytes = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?
+            .bytes()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // Write to a temporary file first, so a partial download never replaces a working copy.
+        // Every download gets its own, since the same file may be downloaded twice at once.
+        let tmp = self
+            .download_dir
+            .join(format!("{:016x}", rand::random::<u64>()));
+        for dir in [local.parent(), Some(self.download_dir.as_path())]
+            .into_iter()
+            .flatten()
+        {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        tokio::fs::write(&tmp, &bytes)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Err(e) = tokio::fs::rename(&tmp, &local).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e.to_string());
+        }
+        Ok(local)
+    }
+
+    /// Removes cached files none of the payloads refer to, so the cache only holds the current rotation
+    pub fn prune<'a>(&self, payloads: impl IntoIterator<Item = &'a DisplayPayload>) {
+        let keep = payloads
+            .into_iter()
+            .filter_map(|p| self.local_path(Self::file_path(p)?))
+            .collect::<HashSet<_>>();
+        let mut dirs = vec![self.dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for path in entries.flatten().map(|e| e.path()) {
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if !keep.contains(&path) {
+                    match fs::remove_file(&path) {
+                        Ok(_) => println!("[Offline] Removed {path:?} from the cache"),
+                        Err(e) => println!("[Offline] Could not remove {path:?}: {e}"),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Path below /files/ of the file the payload refers to, if any
+    fn file_path(payload: &DisplayPayload) -> Option<&str> {
+        match payload {
+            DisplayPayload::Website(p)
+            | DisplayPayload::Image(p)
+            | DisplayPayload::PortableDocumentFormat(p) => p.content.strip_prefix(FILES_PREFIX),
+            DisplayPayload::Text(_) => None,
+        }
+    }
+
+    /// Removes every cached file
+    pub async fn clear(&self) -> Result<(), String> {
+        match tokio::fs::remove_dir_all(&self.dir).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Caches the file referenced by the payload in the background, if any
+    pub fn cache(&self, payload: &DisplayPayload) {
+        if let Some(path) = Self::file_path(payload) {
+            let cache = self.clone();
+            let path = path.to_string();
+            tokio::task::spawn(async move {
+                if let Err(e) = cache.get(&path).await {
+                    println!("[Offline] Could not cache {path:?}: {e}");
+                }
+            });
+        }
+    }
+}
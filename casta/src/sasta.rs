@@ -1,4 +1,9 @@
-use casta_protocol::{uuid::Uuid, RequestPayload, ResponsePayload as SastaPayload};
+use std::collections::HashSet;
+use std::env;
+
+use casta_protocol::{
+    uuid::Uuid, Capability, RequestPayload, ResponsePayload as SastaPayload, PROTOCOL_VERSION,
+};
 use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
@@ -9,10 +14,32 @@ use url::Url;
 
 type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
+fn capabilities() -> HashSet<Capability> {
+    // PDFs are not supported by the view
+    let mut capabilities = HashSet::from([
+        Capability::Website,
+        Capability::Text,
+        Capability::Image,
+        Capability::ScheduledDisplay,
+        Capability::Prepare,
+        Capability::PlaylistSync,
+        Capability::Reload,
+        Capability::Identify,
+        Capability::ClearCache,
+        Capability::Restart,
+    ]);
+    // Taking a screenshot depends on the kiosk, so it must be configured
+    if env::var("SCREENSHOT_COMMAND").is_ok() {
+        capabilities.insert(Capability::Screenshot);
+    }
+    capabilities
+}
+
 pub struct Sasta {
     address: String,
     port: String,
     uuid: Uuid,
+    hostname: String,
     ws_sender: SplitSink<Socket, Message>,
     ws_receiver: SplitStream<Socket>,
 }
@@ -22,6 +49,7 @@ impl Sasta {
         address: String,
         port: String,
         uuid: Uuid,
+        hostname: String,
     ) -> (SplitSink<Socket, Message>, SplitStream<Socket>) {
         let wait_sec = 5;
         let mut interval = time::interval(time::Duration::from_secs(wait_sec));
@@ -42,7 +70,15 @@ impl Sasta {
             };
         }
 
-        let hello = RequestPayload::Hello { uuid, htmx: false };
+        let hello = RequestPayload::Hello {
+            uuid,
+            htmx: false,
+            playlist_sync: true,
+            version: PROTOCOL_VERSION,
+            capabilities: Some(capabilities()),
+            hostname: Some(hostname),
+            resolution: None,
+        };
         let (mut w, r) = socket.unwrap().split();
 
         w.send(Message::Text(serde_json::to_string(&hello).unwrap()))
@@ -53,14 +89,28 @@ impl Sasta {
         (w, r)
     }
 
-    pub async fn new(address: String, port: String, uuid: Uuid) -> Self {
-        let (ws_sender, ws_receiver) =
-            Self::connect(address.clone(), port.clone(), uuid.clone()).await;
+    /// Sends a binary message, which Sasta takes as the screenshot it asked for
+    pub async fn send_binary(&mut self, bytes: Vec<u8>) -> Result<(), String> {
+        self.ws_sender
+            .send(Message::Binary(bytes))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn new(address: String, port: String, uuid: Uuid, hostname: String) -> Self {
+        let (ws_sender, ws_receiver) = Self::connect(
+            address.clone(),
+            port.clone(),
+            uuid.clone(),
+            hostname.clone(),
+        )
+        .await;
 
         Sasta {
             address,
             port,
             uuid,
+            hostname,
             ws_sender,
             ws_receiver,
         }
@@ -72,8 +122,13 @@ impl Sasta {
             Err(e) => println!("Could not close Sasta connection: {e:?}"),
         }
         println!("Connection closed, attempting reconnect");
-        let (s, r) =
-            Self::connect(self.address.clone(), self.port.clone(), self.uuid.clone()).await;
+        let (s, r) = Self::connect(
+            self.address.clone(),
+            self.port.clone(),
+            self.uuid.clone(),
+            self.hostname.clone(),
+        )
+        .await;
 
         self.ws_sender = s;
         self.ws_receiver = r;
@@ -105,8 +160,11 @@ impl Sasta {
             match msg {
                 Message::Text(s) => {
                     // println!("{:#?}", s);
-                    return serde_json::from_str(&s)
-                        .expect(&format!("Cannot parse string: {:?}", s));
+                    // Skip messages from a newer protocol version instead of giving up on the connection
+                    match serde_json::from_str(&s) {
+                        Ok(payload) => return Some(payload),
+                        Err(e) => println!("[Error] Skipping message {s:?} that could not be parsed: {e}"),
+                    }
                 }
                 Message::Ping(_) => {
                     println!("[Ping]");
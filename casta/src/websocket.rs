@@ -5,7 +5,7 @@ use actix_web_actors::ws;
 use serde::Serialize;
 use tokio::sync::Mutex;
 
-use crate::{send_cached_to_view, ClientPayload, send_to_view};
+use crate::{send_cached_to_view, ClientPayload, send_to_view, SETTINGS, TRANSITION};
 
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
@@ -84,6 +84,10 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Websocket {
         //This is dumb, find better method of calling async method in sync block
         tokio::task::spawn(async move {
             send_to_view(ClientPayload::Hash(hash.to_string())).await;
+            let transition = *TRANSITION.lock().await;
+            send_to_view(ClientPayload::Transition(transition)).await;
+            let settings = SETTINGS.lock().await.clone();
+            send_to_view(ClientPayload::Settings(settings)).await;
             send_cached_to_view(cached_ref).await;
         });
     }
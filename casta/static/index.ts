@@ -5,8 +5,14 @@ import ReconnectingWebSocket from "reconnecting-websocket"
 
 interface Payload {
     Display?: Content<ContentData>,
+    Prepare?: Content<ContentData>,
+    Transition?: Transition,
+    Settings?: Settings,
     Disconnected?: any,
-    Hash?: string
+    Hash?: string,
+    Reload?: any,
+    /** Name of the display to show on top of the content */
+    Identify?: string
 }
 
 interface Content<T> {
@@ -18,64 +24,208 @@ interface ContentData {
     content: string
 }
 
+interface Transition {
+    type: "Cut" | "Crossfade",
+    /** Duration of the crossfade in milliseconds */
+    data?: number
+}
+
+interface Settings {
+    /** Clockwise rotation in degrees, one of 0, 90, 180 and 270 */
+    rotation: number,
+    scaling: "Contain" | "Cover",
+    /** Volume of media in percent */
+    volume: number,
+    background: string,
+    /** Margin around the content in percent of the shorter side of the screen */
+    overscan: number,
+    night_mode?: {
+        /** Formatted as HH:MM */
+        start: string,
+        /** Formatted as HH:MM, before start if the night passes midnight */
+        end: string,
+        /** Brightness in percent */
+        brightness: number
+    }
+}
+
+const pad = (n: number) => `0${n}`.slice(-2)
+
+/** Current value of an element rendered by Sasta from a template variable, formatted the same way as by Sasta */
+const format_template = (element: HTMLElement, now: Date): string | null => {
+    switch (element.dataset.template) {
+        case "date":
+            return `${now.getFullYear()}-${pad(now.getMonth() + 1)}-${pad(now.getDate())}`
+        case "time":
+            return `${pad(now.getHours())}:${pad(now.getMinutes())}`
+        case "countdown": {
+            const left = Math.max(0, Math.floor((Number(element.dataset.until) - now.getTime()) / 1000))
+            const time = `${pad(Math.floor(left / 3600) % 24)}:${pad(Math.floor(left / 60) % 60)}:${pad(left % 60)}`
+            const days = Math.floor(left / 86400)
+            return days > 0 ? `${days}d ${time}` : time
+        }
+        default:
+            return element.textContent
+    }
+}
+
 window.onload = () => {
-    const website: HTMLIFrameElement = document.getElementById("website") as HTMLIFrameElement
+    // Text holds the values from when it was sent by Sasta, which are kept up to date here
+    setInterval(() => {
+        const now = new Date()
+        document.querySelectorAll<HTMLElement>("[data-template]").forEach(element => {
+            element.textContent = format_template(element, now)
+        })
+    }, 1000)
 
-    const image: HTMLImageElement = document.getElementById("image") as HTMLImageElement
-    const text: HTMLDivElement = document.getElementById("text") as HTMLDivElement
-    // const background_audio: HTMLIFrameElement = document.getElementById("background_audio") as HTMLIFrameElement
-    const all_elements = [website, image, text]
-    
     let socket = new ReconnectingWebSocket(`ws://${location.host}/ws`)
 
     socket.onopen = () => {
         console.log("connected to socket")
     }
 
-    const assign_src = (element: HTMLElement, src?: string) => element.setAttribute("src", src ?? "")
+    let transition: Transition = { type: "Cut" }
+    let settings: Settings | undefined
+    let night_mode_interval: number | undefined
+
+    /** Settings are applied through CSS variables, see #screen in style.css */
+    const apply_settings = (new_settings: Settings) => {
+        settings = new_settings
+        const root = document.documentElement.style
+        root.setProperty("--rotation", `${settings.rotation}deg`)
+        root.setProperty("--scaling", settings.scaling.toLowerCase())
+        root.setProperty("--background", settings.background)
+        root.setProperty("--overscan", `${settings.overscan}`)
+        document.getElementById("screen")!.classList.toggle("sideways", settings.rotation % 180 === 90)
+        document.querySelectorAll<HTMLElement>(".layer").forEach(apply_volume)
+        apply_night_mode()
+        clearInterval(night_mode_interval)
+        night_mode_interval = setInterval(apply_night_mode, 30000)
+    }
 
-    /** Hide all elements in all_elements except for given element element */
-    const hide_other = (element: HTMLElement) =>
-        all_elements.forEach(e => e.id === element.id ? e.classList.remove("hidden") : e.classList.add("hidden"))
+    const apply_night_mode = () => {
+        const night = settings?.night_mode
+        let brightness = 1
+        if (night) {
+            // Times are formatted as HH:MM, so they can be compared as strings
+            const now = new Date().toTimeString().slice(0, 5)
+            // The night ends before it starts when it passes midnight
+            const active = night.start <= night.end
+                ? night.start <= now && now < night.end
+                : night.start <= now || now < night.end
+            if (active) {
+                brightness = night.brightness / 100
+            }
+        }
+        document.documentElement.style.setProperty("--brightness", `${brightness}`)
+    }
 
-    const display_image = (src: string) => {
-        hide_other(image)
-        assign_src(image, src)
+    const apply_volume = (element: HTMLElement) => {
+        if (!settings) {
+            return
+        }
+        const volume = settings.volume / 100
+        if (element instanceof HTMLMediaElement) {
+            element.volume = volume
+        }
+        element.querySelectorAll("video, audio").forEach(media => (media as HTMLMediaElement).volume = volume)
     }
+    /** Layer currently shown */
+    let current: HTMLElement | undefined
+    /** Layer loaded off-screen, with the content it was created from */
+    let prepared: { key: string, element: HTMLElement } | undefined
 
-    const display_website = (href: string) => {
-        console.log("Website with data: " +href +" received")
-        hide_other(website)
-        assign_src(website, href)
+    const create_layer = (content: Content<ContentData>): HTMLElement | undefined => {
+        let element: HTMLElement
+        if (content.type === "Website") {
+            element = document.createElement("iframe")
+            element.setAttribute("frameborder", "0")
+            element.setAttribute("allow", "autoplay; encrypted-media")
+            element.setAttribute("allowfullscreen", "")
+            element.setAttribute("src", content.data.content)
+        } else if (content.type === "Image") {
+            element = document.createElement("img")
+            element.setAttribute("src", content.data.content)
+        } else if (content.type === "Text") {
+            element = document.createElement("div")
+            element.classList.add("text")
+            element.innerHTML = content.data.content
+        } else {
+            return undefined
+        }
+        element.classList.add("layer", "offscreen")
+        document.getElementById("screen")!.appendChild(element)
+        apply_volume(element)
+        return element
     }
 
-    const display_text = (content: string) => {
-        hide_other(text)
-        text.innerHTML = content
+    /** Loads the content off-screen, replacing anything prepared before */
+    const prepare = (content: Content<ContentData>) => {
+        prepared?.element.remove()
+        const element = create_layer(content)
+        prepared = element && { key: JSON.stringify(content), element }
     }
 
-    const display_disconnected_sasta = () => {
-        display_image("/disconnected.png")
+    /** Shows the content, using the prepared layer if it was made for the same content */
+    const display = (content: Content<ContentData>) => {
+        const key = JSON.stringify(content)
+        let element: HTMLElement | undefined
+        if (prepared?.key === key) {
+            element = prepared.element
+        } else {
+            prepared?.element.remove()
+            element = create_layer(content)
+        }
+        prepared = undefined
+        if (!element) {
+            return
+        }
+
+        const old = current
+        current = element
+        // Keep the new layer on top, layers are not moved since that would reload iframes
+        old?.style.setProperty("z-index", "0")
+        element.style.setProperty("z-index", "1")
+        if (transition.type === "Crossfade" && old) {
+            element.style.transition = `opacity ${transition.data}ms`
+            element.classList.add("transparent")
+            element.classList.remove("offscreen")
+            // Let the browser render the transparent state before fading in
+            requestAnimationFrame(() => requestAnimationFrame(() => element?.classList.remove("transparent")))
+            setTimeout(() => old.remove(), transition.data)
+        } else {
+            element.classList.remove("offscreen")
+            old?.remove()
+        }
     }
 
-    const display_disconnected_casta = () => {
-        display_image("/disconnected.png")
+    const display_disconnected = () => {
+        display({ type: "Image", data: { content: "/disconnected.png" } })
     }
 
     socket.onclose = () => {
-        display_disconnected_casta()
+        display_disconnected()
         console.log("disconnected to socket")
     }
     
 
     let version_hash: string
+    let identify_timeout: number | undefined
+
+    const identify = (name: string) => {
+        const element = document.getElementById("identify")!
+        element.textContent = name
+        element.classList.remove("hidden")
+        clearTimeout(identify_timeout)
+        identify_timeout = setTimeout(() => element.classList.add("hidden"), 10000)
+    }
 
     socket.onmessage = event => {
         console.log(`[Debug] ${event.data}`)
         let payload: Payload = JSON.parse(event.data)
         
         if (payload.Disconnected) {
-            display_disconnected_sasta()
+            display_disconnected()
         } else if (payload.Hash) {
             if (!version_hash) {
                 console.log(`[Hash] New hash received: "${payload.Hash}"`)
@@ -88,19 +238,18 @@ window.onload = () => {
                     window.location.reload()
                 }
             }
+        } else if (payload.Reload) {
+            window.location.reload()
+        } else if (payload.Identify) {
+            identify(payload.Identify)
+        } else if (payload.Transition) {
+            transition = payload.Transition
+        } else if (payload.Settings) {
+            apply_settings(payload.Settings)
+        } else if (payload.Prepare) {
+            prepare(payload.Prepare)
         } else if (payload.Display) {
-            const type = payload.Display.type
-
-            if (type === "Website") {
-                const data = payload.Display.data as ContentData
-                display_website(data.content)
-            } else if (type === "Text") {
-                const data = payload.Display.data as ContentData
-                display_text(data.content)
-            } else if (type === "Image") {
-                const data = payload.Display.data as ContentData
-                display_image(data.content)
-            }
+            display(payload.Display)
         }
 
             // if (data.background_audio != undefined && data.background_audio !== current_background_audio) {
@@ -1,16 +1,85 @@
+use std::collections::HashSet;
+
 use serde::{Deserialize, Serialize};
 pub use uuid;
 
+/// Version of the protocol implemented by this crate
+///
+/// Clients from before the protocol was versioned do not send a version, which is read as 0.
+pub const PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(tag = "type", content = "data")]
 pub enum ResponsePayload {
     Display(DisplayPayload),
+    /// Display payload to be shown at a given time, used to flip a group of displays at the same moment
+    ScheduledDisplay {
+        payload: DisplayPayload,
+        /// Unix time in milliseconds at which the payload should be shown
+        show_at: u64,
+        /// Unix time in milliseconds on the server when the message was sent,
+        /// used by the client to estimate the offset between its clock and the server's
+        server_time: u64,
+    },
+    /// Display payload that will be shown next, sent ahead of time so the client
+    /// can load it off-screen before the following `Display` with the same payload
+    Prepare(DisplayPayload),
     Welcome {
         name: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         htmx_hash: Option<String>,
+        /// How the client should switch from one payload to the next
+        #[serde(default)]
+        transition: Transition,
+        /// Protocol version of the server
+        #[serde(default)]
+        version: u32,
+        /// Capabilities supported by both the client and the server, only these are used
+        #[serde(default)]
+        capabilities: HashSet<Capability>,
+        /// How the client should present the payloads, sent again whenever it changes
+        #[serde(default)]
+        settings: DisplaySettings,
     },
     Pending(bool),
+    /// Whole Playlist for clients running the rotation themselves, sent again whenever it changes
+    Playlist {
+        /// Changes whenever the content of the Playlist changes
+        version: u64,
+        items: Vec<PlaylistEntry>,
+        /// Reshuffle the items every time the rotation starts over
+        shuffle: bool,
+    },
+    /// Command from an operator, only sent to clients with the matching capability
+    Command(Command),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "data")]
+pub enum Command {
+    /// Reload the view
+    Reload,
+    /// Show the name of the display on top of the content for a while
+    Identify { name: String },
+    /// Answer with a PNG of what is shown, sent as a binary message
+    Screenshot,
+    /// Remove any content kept by the client
+    ClearCache,
+    /// Restart the kiosk the client runs on
+    Restart,
+}
+
+impl Command {
+    /// Capability needed to receive the command
+    pub fn capability(&self) -> Capability {
+        match self {
+            Command::Reload => Capability::Reload,
+            Command::Identify { .. } => Capability::Identify,
+            Command::Screenshot => Capability::Screenshot,
+            Command::ClearCache => Capability::ClearCache,
+            Command::Restart => Capability::Restart,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -20,19 +89,194 @@ pub enum RequestPayload {
         uuid: uuid::Uuid,
         #[serde(default)]
         htmx: bool,
+        /// Receive the whole Playlist to rotate through instead of one item at a time
+        #[serde(default)]
+        playlist_sync: bool,
+        /// Protocol version of the client
+        #[serde(default)]
+        version: u32,
+        /// Capabilities supported by the client, None for clients from before the protocol was versioned
+        ///
+        /// Capabilities of newer versions of the protocol are left out, so newer clients can still connect.
+        #[serde(default, deserialize_with = "known_capabilities")]
+        capabilities: Option<HashSet<Capability>>,
+        #[serde(default)]
+        hostname: Option<String>,
+        /// Size of the screen, used to send images in a size fitting it
+        #[serde(default)]
+        resolution: Option<Resolution>,
     },
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+/// Capability of the request, unless it is from a newer version of the protocol
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MaybeCapability {
+    Known(Capability),
+    Unknown(serde::de::IgnoredAny),
+}
+
+fn known_capabilities<'de, D>(deserializer: D) -> Result<Option<HashSet<Capability>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let capabilities = Option::<Vec<MaybeCapability>>::deserialize(deserializer)?;
+    Ok(capabilities.map(|c| {
+        c.into_iter()
+            .filter_map(|c| match c {
+                MaybeCapability::Known(c) => Some(c),
+                MaybeCapability::Unknown(_) => None,
+            })
+            .collect()
+    }))
+}
+
+/// Size of a screen in physical pixels
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(tag = "type", content = "data")]
 pub enum DisplayPayload {
     Website(WebsitePayload),
+    /// HTML rendered by the server from plain text or Markdown, where elements with a
+    /// `data-template` attribute of `date`, `time` or `countdown` are meant to be kept up to date
     Text(WebsitePayload),
     Image(WebsitePayload),
     PortableDocumentFormat(WebsitePayload),
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+/// Something a client is able to handle, sent in Hello and confirmed in Welcome
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// `DisplayPayload::Website`
+    Website,
+    /// `DisplayPayload::Text`
+    Text,
+    /// `DisplayPayload::Image`
+    Image,
+    /// `DisplayPayload::PortableDocumentFormat`
+    PortableDocumentFormat,
+    /// `ResponsePayload::ScheduledDisplay`
+    ScheduledDisplay,
+    /// `ResponsePayload::Prepare`
+    Prepare,
+    /// `ResponsePayload::Playlist`
+    PlaylistSync,
+    /// `Command::Reload`
+    Reload,
+    /// `Command::Identify`
+    Identify,
+    /// `Command::Screenshot`
+    Screenshot,
+    /// `Command::ClearCache`
+    ClearCache,
+    /// `Command::Restart`
+    Restart,
+}
+
+impl Capability {
+    /// Every capability of this version of the protocol
+    pub fn all() -> HashSet<Capability> {
+        HashSet::from([
+            Capability::Website,
+            Capability::Text,
+            Capability::Image,
+            Capability::PortableDocumentFormat,
+            Capability::ScheduledDisplay,
+            Capability::Prepare,
+            Capability::PlaylistSync,
+            Capability::Reload,
+            Capability::Identify,
+            Capability::Screenshot,
+            Capability::ClearCache,
+            Capability::Restart,
+        ])
+    }
+
+    /// Capabilities of clients from before the protocol was versioned
+    pub fn legacy() -> HashSet<Capability> {
+        HashSet::from([
+            Capability::Website,
+            Capability::Text,
+            Capability::Image,
+            Capability::PortableDocumentFormat,
+        ])
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub id: String,
+    pub payload: DisplayPayload,
+    /// Seconds to show the payload, 0 shows it until the Playlist changes
+    pub duration: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(tag = "type", content = "data")]
+pub enum Transition {
+    #[default]
+    Cut,
+    /// Fade the next payload in over the given amount of milliseconds
+    Crossfade(u32),
+}
+
+/// How a display presents its payloads, independent of what it shows
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DisplaySettings {
+    /// Clockwise rotation of the content in degrees, one of 0, 90, 180 and 270
+    pub rotation: u16,
+    pub scaling: Scaling,
+    /// Volume of media in percent
+    pub volume: u8,
+    /// CSS colour shown behind the content
+    pub background: String,
+    /// Margin kept around the content for screens cutting off the edges,
+    /// in percent of the shorter side of the screen
+    pub overscan: u8,
+    pub night_mode: Option<NightMode>,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            rotation: 0,
+            scaling: Scaling::default(),
+            volume: 100,
+            background: "#1e1e1e".to_string(),
+            overscan: 0,
+            night_mode: None,
+        }
+    }
+}
+
+/// How images are scaled to the screen
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Scaling {
+    /// Show the whole image, leaving bars where the aspect ratio differs
+    #[default]
+    Contain,
+    /// Fill the screen, cutting off what does not fit
+    Cover,
+}
+
+/// Dims the display during a part of the day
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct NightMode {
+    /// Local time of the display formatted as HH:MM at which night mode starts
+    pub start: String,
+    /// Local time of the display formatted as HH:MM at which night mode ends, may be before `start`
+    pub end: String,
+    /// Brightness in percent during night mode
+    pub brightness: u8,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct WebsitePayload {
     pub content: String,
 }
@@ -6,7 +6,22 @@ document.addEventListener("htmx:wsOpen", (e) => {
     data: {
       uuid,
       hostname: "htmx-client",
+      resolution: {
+        width: Math.round(screen.width * devicePixelRatio),
+        height: Math.round(screen.height * devicePixelRatio),
+      },
       htmx: true,
+      version: 1,
+      capabilities: [
+        "Website",
+        "Text",
+        "Image",
+        "PortableDocumentFormat",
+        "ScheduledDisplay",
+        "Prepare",
+        "Reload",
+        "Identify",
+      ],
     },
   };
   ws.send(JSON.stringify(test));
@@ -22,11 +37,16 @@ htmx.config.wsReconnectDelay = (_) => {
 };
 
 let hash;
+let transition = { type: "Cut" };
 document.addEventListener("htmx:wsAfterMessage", (e) => {
   try {
     const message = JSON.parse(e.detail.message);
     if (message.type == "Welcome") {
       const welcome = message.data;
+      transition = welcome.transition ?? transition;
+      if (welcome.settings) {
+        applySettings(welcome.settings);
+      }
       if (hash) {
         if (hash !== welcome.htmx_hash) {
           console.log("Hashes were not identical, reloading...");
@@ -41,21 +61,182 @@ document.addEventListener("htmx:wsAfterMessage", (e) => {
         console.log("Saving hash " + welcome.htmx_hash);
         hash = welcome.htmx_hash;
       }
+    } else if (message.type == "Command") {
+      runCommand(message.data);
     }
   } catch {}
 });
 
+// Display settings
+// Applied through CSS variables, see #screen in style.css
+let settings;
+let nightModeInterval;
+function applySettings(newSettings) {
+  settings = newSettings;
+  const root = document.documentElement.style;
+  root.setProperty("--rotation", `${settings.rotation}deg`);
+  root.setProperty("--scaling", settings.scaling.toLowerCase());
+  root.setProperty("--background", settings.background);
+  root.setProperty("--overscan", settings.overscan);
+  htmx
+    .find("#screen")
+    .classList.toggle("sideways", settings.rotation % 180 == 90);
+  applyVolume();
+  applyNightMode();
+  clearInterval(nightModeInterval);
+  nightModeInterval = setInterval(applyNightMode, 30000);
+}
+
+function applyNightMode() {
+  const night = settings.night_mode;
+  let brightness = 1;
+  if (night) {
+    // Times are formatted as HH:MM, so they can be compared as strings
+    const now = new Date().toTimeString().slice(0, 5);
+    // The night ends before it starts when it passes midnight
+    const active =
+      night.start <= night.end
+        ? night.start <= now && now < night.end
+        : night.start <= now || now < night.end;
+    if (active) {
+      brightness = night.brightness / 100;
+    }
+  }
+  document.documentElement.style.setProperty("--brightness", brightness);
+}
+
+function applyVolume() {
+  if (!settings) {
+    return;
+  }
+  document.querySelectorAll("video, audio").forEach((media) => {
+    media.volume = settings.volume / 100;
+  });
+}
+document.addEventListener("htmx:wsAfterMessage", (_) => applyVolume());
+
+// Template variables
+// Text is rendered by Sasta with the values from when it was sent, which are kept up to date here
+function pad(n) {
+  return String(n).padStart(2, "0");
+}
+
+function formatTemplate(element, now) {
+  switch (element.dataset.template) {
+    case "date":
+      return `${now.getFullYear()}-${pad(now.getMonth() + 1)}-${pad(now.getDate())}`;
+    case "time":
+      return `${pad(now.getHours())}:${pad(now.getMinutes())}`;
+    case "countdown": {
+      // Formatted the same way as by Sasta
+      const left = Math.max(
+        0,
+        Math.floor((Number(element.dataset.until) - now.getTime()) / 1000),
+      );
+      const time = `${pad(Math.floor(left / 3600) % 24)}:${pad(Math.floor(left / 60) % 60)}:${pad(left % 60)}`;
+      const days = Math.floor(left / 86400);
+      return days > 0 ? `${days}d ${time}` : time;
+    }
+    default:
+      return element.textContent;
+  }
+}
+
+setInterval(() => {
+  const now = new Date();
+  document.querySelectorAll("[data-template]").forEach((element) => {
+    element.textContent = formatTemplate(element, now);
+  });
+}, 1000);
+
+// Remote commands
+let identifyTimeout;
+function runCommand(command) {
+  if (command.type == "Reload") {
+    window.location.reload(true);
+  } else if (command.type == "Identify") {
+    const identify = htmx.find("#identify");
+    identify.textContent = command.data.name;
+    htmx.removeClass(identify, "hidden");
+    clearTimeout(identifyTimeout);
+    identifyTimeout = setTimeout(() => htmx.addClass(identify, "hidden"), 10000);
+  }
+}
+
+// Scheduled content
+// Content is loaded off-screen in #scheduled, both for synchronised playback and to prepare the next item.
+// Offsets between the server and this clock, the largest one had the least network delay
+let clockOffsets = [];
+let showTimeout;
+document.addEventListener("htmx:wsAfterMessage", (_) => {
+  const pending = htmx.find("#scheduled [data-server-time]");
+  if (!pending) {
+    return;
+  }
+  clockOffsets.push(Number(pending.dataset.serverTime) - Date.now());
+  clockOffsets = clockOffsets.slice(-8);
+  delete pending.dataset.serverTime;
+  const offset = Math.max(...clockOffsets);
+
+  clearTimeout(showTimeout);
+  showTimeout = setTimeout(
+    () => {
+      // Content sent to be shown right away replaces what was scheduled
+      if (!pending.isConnected) {
+        return;
+      }
+      showScheduled();
+    },
+    Number(pending.dataset.showAt) - offset - Date.now(),
+  );
+});
+
+function showScheduled() {
+  const content = htmx.find("#content");
+  const scheduled = htmx.find("#scheduled");
+  content.id = "scheduled";
+  scheduled.id = "content";
+  // Keep the new content on top, elements are not moved since that would reload iframes
+  content.style.zIndex = 0;
+  scheduled.style.zIndex = 1;
+
+  const hideOld = () => {
+    htmx.addClass(content, "offscreen");
+    content.innerHTML = "";
+  };
+  if (transition.type === "Crossfade") {
+    scheduled.style.transition = `opacity ${transition.data}ms`;
+    htmx.addClass(scheduled, "transparent");
+    htmx.removeClass(scheduled, "offscreen");
+    // Let the browser render the transparent state before fading in
+    requestAnimationFrame(() =>
+      requestAnimationFrame(() => htmx.removeClass(scheduled, "transparent")),
+    );
+    setTimeout(hideOld, transition.data);
+  } else {
+    scheduled.style.transition = "";
+    htmx.removeClass(scheduled, "offscreen");
+    hideOld();
+  }
+}
+
 // PDF
 pdfjsLib.GlobalWorkerOptions.workerSrc = "/assets/pdf.worker@3.11.174.min.js";
 
-async function renderFirstPage(canvas, url) {
-  const pdf = await pdfjsLib.getDocument(url).promise;
-  const page = await pdf.getPage(1);
+// Renders the page given by `#page=N` in the url, or the first page
+async function renderPage(canvas, url) {
+  const match = url.match(/#page=(\d+)$/);
+  const pdf = await pdfjsLib.getDocument(url.replace(/#.*$/, "")).promise;
+  const number = match ? Math.min(Math.max(Number(match[1]), 1), pdf.numPages) : 1;
+  const page = await pdf.getPage(number);
 
+  // The screen is rotated and shrunk by the display settings
+  const screen = htmx.find("#screen");
+  const fit = settings?.scaling === "Cover" ? Math.max : Math.min;
   const baseViewport = page.getViewport({ scale: 1 });
-  const fitScale = Math.min(
-    window.innerWidth / baseViewport.width,
-    window.innerHeight / baseViewport.height,
+  const fitScale = fit(
+    screen.clientWidth / baseViewport.width,
+    screen.clientHeight / baseViewport.height,
   );
   const viewport = page.getViewport({ scale: fitScale });
 
@@ -1,4 +1,6 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { DisplayMaterial } from "./DisplayMaterial";
+import type { DisplaySettings } from "./DisplaySettings";
+import type { Transition } from "./Transition";
 
-export type CreateDisplay = { uuid?: string, name: string, display_material: DisplayMaterial, };
+export type CreateDisplay = { uuid?: string, name: string, display_material: DisplayMaterial, sync_group?: string, transition?: Transition, settings?: DisplaySettings, };
@@ -0,0 +1,25 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { NightMode } from "./NightMode";
+import type { Scaling } from "./Scaling";
+
+/**
+ * How a display presents its content, independent of what it shows
+ */
+export type DisplaySettings = { 
+/**
+ * Clockwise rotation of the content in degrees, one of 0, 90, 180 and 270
+ */
+rotation: number, scaling: Scaling, 
+/**
+ * Volume of media in percent
+ */
+volume: number, 
+/**
+ * CSS colour shown behind the content
+ */
+background: string, 
+/**
+ * Margin kept around the content for screens cutting off the edges,
+ * in percent of the shorter side of the screen
+ */
+overscan: number, night_mode?: NightMode, };
@@ -0,0 +1,18 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Dims the display during a part of the day
+ */
+export type NightMode = { 
+/**
+ * Local time of the display formatted as HH:MM at which night mode starts
+ */
+start: string, 
+/**
+ * Local time of the display formatted as HH:MM at which night mode ends, may be before `start`
+ */
+end: string, 
+/**
+ * Brightness in percent during night mode
+ */
+brightness: number, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * How a display switches from one item to the next
+ */
+export type Transition = { "type": "cut" } | { "type": "crossfade", "duration": number };
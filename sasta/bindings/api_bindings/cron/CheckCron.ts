@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type CheckCron = { 
+/**
+ * Start expression of a scheduled playlist, or any expression to check on its own
+ */
+start: string, 
+/**
+ * End expression of the scheduled playlist
+ */
+end?: string, 
+/**
+ * Amount of upcoming fire times to list, 5 if not set and at most 100
+ */
+count?: number, };
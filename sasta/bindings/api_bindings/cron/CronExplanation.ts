@@ -0,0 +1,16 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CronWarning } from "./CronWarning";
+
+export type CronExplanation = { expression: string, valid: boolean, 
+/**
+ * Why the expression could not be parsed
+ */
+error?: string, 
+/**
+ * When the expression fires, in plain language
+ */
+description?: string, 
+/**
+ * Upcoming fire times in RFC 3339
+ */
+next: Array<string>, warnings: Array<CronWarning>, };
@@ -0,0 +1,9 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CronExplanation } from "./CronExplanation";
+import type { CronWarning } from "./CronWarning";
+
+export type CronValidation = { start: CronExplanation, end?: CronExplanation, 
+/**
+ * Mistakes in how the start and end expressions work together
+ */
+warnings: Array<CronWarning>, };
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CronWarningKind } from "./CronWarningKind";
+
+export type CronWarning = { kind: CronWarningKind, message: string, 
+/**
+ * Expression likely meant instead
+ */
+suggestion?: string, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Problem found with the blob of a file by the scrub job
+ */
+export type BlobDamage = "missing" | "corrupted";
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type CreateUploadRequest = { 
+/**
+ * Path of the file once uploaded
+ */
+path: string, 
+/**
+ * Size of the file in bytes
+ */
+size: bigint, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Smaller copy of an uploaded image, so displays and the file browser need not load the original
+ */
+export type ImageVariant = "thumbnail" | "1080p" | "4k";
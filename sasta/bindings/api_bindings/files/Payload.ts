@@ -1,4 +1,7 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { ListView } from "./ListView";
+import type { SearchResults } from "./SearchResults";
+import type { TrashItem } from "./TrashItem";
+import type { UploadProgress } from "./UploadProgress";
 
-export type Payload = { "type": "FilePaths", "content": ListView } | { "type": "Error", "content": { code: number, message: string, } };
+export type Payload = { "type": "FilePaths", "content": ListView } | { "type": "Search", "content": SearchResults } | { "type": "Upload", "content": UploadProgress } | { "type": "Trash", "content": Array<TrashItem> } | { "type": "Error", "content": { code: number, message: string, } };
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type RestoreRequest = { 
+/**
+ * Path to restore the item to instead of where it was deleted from, directories end with a `'/'`
+ */
+path?: string, };
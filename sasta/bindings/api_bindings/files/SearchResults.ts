@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { TreeFile } from "./TreeFile";
+
+/**
+ * Page of the files found, ordered by path
+ */
+export type SearchResults = { files: Array<TreeFile>, 
+/**
+ * Amount of files found on all pages
+ */
+total: number, page: number, per_page: number, };
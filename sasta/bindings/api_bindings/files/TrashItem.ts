@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ListViewItemType } from "./ListViewItemType";
+
+/**
+ * Item in the trash, restored or purged by its id
+ */
+export type TrashItem = { id: string, 
+/**
+ * Path the item was deleted from, directories end with a `'/'`
+ */
+path: string, type: ListViewItemType, 
+/**
+ * Sum of the sizes of the files in the item
+ */
+size: number, deleted: string, 
+/**
+ * When the item is purged by the sweeper
+ */
+expires: string, };
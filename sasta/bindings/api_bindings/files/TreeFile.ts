@@ -1,3 +1,20 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { BlobDamage } from "./BlobDamage";
 
-export type TreeFile = { id: string, name: string, size: number, date: string, };
+export type TreeFile = { id: string, name: string, size: number, date: string, 
+/**
+ * Url of a small version of the image, for images only
+ */
+thumbnail?: string, 
+/**
+ * Hex encoded SHA-256 of the content
+ */
+hash?: string, 
+/**
+ * Set when the scrub job found the content missing or changed on disk
+ */
+damage?: BlobDamage, 
+/**
+ * Amount of pages, for PDF documents only
+ */
+pages?: number, tags: Array<string>, description?: string, };
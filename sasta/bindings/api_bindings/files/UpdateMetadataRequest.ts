@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type UpdateMetadataRequest = { 
+/**
+ * Path of the file
+ */
+id: string, 
+/**
+ * Replaces the tags of the file
+ */
+tags: Array<string>, 
+/**
+ * Replaces the description of the file, removing it if not given
+ */
+description?: string, };
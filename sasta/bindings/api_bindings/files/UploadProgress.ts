@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Progress of a resumable upload, the next chunk is sent from `offset`
+ */
+export type UploadProgress = { id: string, path: string, offset: bigint, size: bigint, };
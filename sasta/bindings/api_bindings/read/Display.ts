@@ -1,4 +1,6 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { DisplayMaterial } from "../create/DisplayMaterial";
+import type { DisplaySettings } from "../create/DisplaySettings";
+import type { Transition } from "../create/Transition";
 
-export type Display = { uuid: string, name: string, display_material: DisplayMaterial, };
+export type Display = { uuid: string, name: string, display_material: DisplayMaterial, sync_group: string | null, transition: Transition, settings: DisplaySettings, };
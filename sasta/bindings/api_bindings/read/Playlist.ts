@@ -1,4 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PlaybackMode } from "../update/PlaybackMode";
 import type { PlaylistItem } from "../update/PlaylistItem";
 
-export type Playlist = { uuid: string, name: string, items: Array<PlaylistItem>, };
+export type Playlist = { uuid: string, name: string, items: Array<PlaylistItem>, playback: PlaybackMode, };
@@ -1,4 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CalendarInput } from "../update/CalendarInput";
 import type { ScheduledPlaylistInput } from "../update/ScheduledPlaylistInput";
 
-export type Schedule = { uuid: string, name: string, playlist: string, scheduled: Array<ScheduledPlaylistInput> | null, };
+export type Schedule = { uuid: string, name: string, playlist: string, scheduled: Array<ScheduledPlaylistInput> | null, calendar?: CalendarInput, };
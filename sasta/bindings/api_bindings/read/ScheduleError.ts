@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Reason a Schedule can not be created or stopped deciding its active playlist
+ */
+export type ScheduleError = { "type": "InvalidCron", "content": { expression: string, message: string, } } | { "type": "Conflict", "content": { playlist: string, start: string, end: string, 
+/**
+ * First moment both fire, in RFC 3339
+ */
+time: string, } } | { "type": "InvalidCalendar", "content": string };
@@ -1,4 +1,9 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { NextMoment } from "./NextMoment";
+import type { ScheduleError } from "./ScheduleError";
 
-export type ScheduleInfo = { current: string, next: NextMoment | null, };
+export type ScheduleInfo = { current: string, next: NextMoment | null, 
+/**
+ * Set if the Schedule can not decide when its active playlist changes next
+ */
+error?: ScheduleError, };
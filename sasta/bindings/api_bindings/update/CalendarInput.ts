@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CalendarRule } from "./CalendarRule";
+
+/**
+ * iCalendar source of a Schedule, whose events decide the active playlist while they take place
+ */
+export type CalendarInput = { 
+/**
+ * Url (http, https or webcal) or path of an `.ics` file within `CALENDAR_PATH`, polled for changes
+ */
+source: string, 
+/**
+ * The first rule matching an event decides its playlist, events matching no rule are ignored
+ */
+rules: Array<CalendarRule>, };
@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Maps events to a playlist by category or summary, a rule with neither matches every event
+ */
+export type CalendarRule = { playlist: string, 
+/**
+ * Category of the event, ignoring case
+ */
+category?: string, 
+/**
+ * Regex matched against the summary of the event
+ */
+summary?: string, };
@@ -0,0 +1,32 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { TextFormat } from "./TextFormat";
+
+/**
+ * Slides made from the entries of an RSS, Atom or JSON feed
+ */
+export type FeedData = { url: string, 
+/**
+ * Text of a slide, where `{{entry.title}}`, `{{entry.summary}}`, `{{entry.link}}` and `{{entry.date}}`
+ * are replaced with the values of an entry, besides the variables of a Text item
+ */
+template: string, 
+/**
+ * Plain text if not given
+ */
+format?: TextFormat, 
+/**
+ * Amount of entries shown, the first ones in the feed
+ */
+entries: number, 
+/**
+ * Show the entries on a single slide, separated by newlines, instead of one slide per entry
+ */
+combined: boolean, 
+/**
+ * Seconds each slide is shown
+ */
+duration: bigint, 
+/**
+ * Seconds between fetches of the feed, defaults to 300
+ */
+refresh?: bigint, };
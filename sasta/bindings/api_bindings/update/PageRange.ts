@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Pages of a PDF document, counting from 1
+ */
+export type PageRange = { first: number, 
+/**
+ * The last page of the document if not given
+ */
+last?: number, };
@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PageRange } from "./PageRange";
+
+/**
+ * PDF document showing its first page, or paging through a range of its pages
+ */
+export type PdfData = { src: string, 
+/**
+ * Seconds the document is shown, or each of its pages when paging through them
+ */
+duration: bigint, 
+/**
+ * Pages shown one after another, only the first page is shown if not given
+ */
+pages?: PageRange, 
+/**
+ * Amount of pages in the document, found by Sasta when the Playlist is updated
+ */
+page_count?: number, };
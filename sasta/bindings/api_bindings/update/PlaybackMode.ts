@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Weight } from "./Weight";
+
+/**
+ * Order in which the items of a Playlist are played
+ */
+export type PlaybackMode = { "type": "sequential" } | { "type": "shuffle" } | { "type": "weighted", "weights": { [key in string]?: Weight } };
@@ -1,6 +1,8 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FeedData } from "./FeedData";
 import type { ImageData } from "./ImageData";
+import type { PdfData } from "./PdfData";
 import type { TextData } from "./TextData";
 import type { WebsiteData } from "./WebsiteData";
 
-export type PlaylistItem = { "type": "WEBSITE", id: string, settings: WebsiteData, } | { "type": "TEXT", id: string, settings: TextData, } | { "type": "IMAGE", id: string, settings: ImageData, } | { "type": "BACKGROUND_AUDIO", id: string, settings: ImageData, } | { "type": "PDF", id: string, settings: ImageData, };
+export type PlaylistItem = { "type": "WEBSITE", id: string, settings: WebsiteData, } | { "type": "TEXT", id: string, settings: TextData, } | { "type": "IMAGE", id: string, settings: ImageData, } | { "type": "BACKGROUND_AUDIO", id: string, settings: ImageData, } | { "type": "PDF", id: string, settings: PdfData, } | { "type": "FEED", id: string, settings: FeedData, };
@@ -1,3 +1,13 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { TextFormat } from "./TextFormat";
 
-export type TextData = { text: string, duration: bigint, };
+export type TextData = { 
+/**
+ * May contain the template variables `{{display.name}}`, `{{date}}`, `{{time}}`
+ * and `{{countdown:<RFC 3339 timestamp>}}`
+ */
+text: string, duration: bigint, 
+/**
+ * Plain text if not given
+ */
+format?: TextFormat, };
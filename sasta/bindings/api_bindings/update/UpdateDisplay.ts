@@ -1,4 +1,18 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { DisplayMaterial } from "../create/DisplayMaterial";
+import type { DisplaySettings } from "../create/DisplaySettings";
+import type { Transition } from "../create/Transition";
 
-export type UpdateDisplay = { name: string, display_material: DisplayMaterial, };
+export type UpdateDisplay = { name: string, display_material: DisplayMaterial, 
+/**
+ * Keeps the current sync group if not given, leaves the sync group if `null`
+ */
+sync_group?: string | null, 
+/**
+ * Keeps the current transition if not given
+ */
+transition?: Transition, 
+/**
+ * Keeps the current settings if not given
+ */
+settings?: DisplaySettings, };
@@ -1,4 +1,9 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PlaybackMode } from "./PlaybackMode";
 import type { PlaylistItem } from "./PlaylistItem";
 
-export type UpdatePlaylist = { name: string, items: Array<PlaylistItem>, };
+export type UpdatePlaylist = { name: string, items: Array<PlaylistItem>, 
+/**
+ * Keeps the current playback mode if not given
+ */
+playback?: PlaybackMode, };
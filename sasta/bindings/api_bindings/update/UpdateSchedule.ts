@@ -1,4 +1,9 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { CalendarInput } from "./CalendarInput";
 import type { ScheduledPlaylistInput } from "./ScheduledPlaylistInput";
 
-export type UpdateSchedule = { name: string, playlist: string, scheduled?: Array<ScheduledPlaylistInput>, };
+export type UpdateSchedule = { name: string, playlist: string, scheduled?: Array<ScheduledPlaylistInput>, 
+/**
+ * Calendar whose events decide the active playlist, taking priority over `scheduled`
+ */
+calendar?: CalendarInput, };
@@ -33,7 +33,11 @@ pub async fn casta_index(Path(uuid): Path<Uuid>) -> Markup {
             }
             body {
                 img #disconnected src="/assets/disconnected.png";
-                div #content hx-ext="ws" ws-connect="/ws";
+                div #screen hx-ext="ws" ws-connect="/ws" {
+                    div #content {}
+                    div #scheduled .offscreen {}
+                    div #identify .hidden {}
+                }
             }
         }
     }
@@ -0,0 +1,469 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use casta_protocol::{Capability, Command};
+use chrono::{DateTime, Utc};
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    RwLock,
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+use tracing::{error, info};
+use ts_rs::TS;
+use utoipa::ToSchema;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use uuid::Uuid;
+
+use crate::{AppState, store::clock::Clock};
+
+/// Largest screenshot accepted from a client
+const MAX_SCREENSHOT_SIZE: usize = 16 * 1024 * 1024;
+
+pub fn command_api_router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(send_command))
+        .routes(routes!(get_screenshot))
+}
+
+#[derive(Serialize, ToSchema, TS)]
+#[serde(tag = "type", content = "content")]
+#[ts(export, export_to = "api_bindings/command/")]
+pub enum Payload {
+    /// Amount of connected clients the command was sent to
+    Sent(usize),
+    Error {
+        code: u8,
+        message: String,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/command/")]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayCommand {
+    /// Reload the view
+    Reload,
+    /// Show the name of the display on top of the content for a while
+    Identify,
+    /// Take a screenshot, available from the screenshot endpoint once received
+    Screenshot,
+    /// Remove any content kept by the client
+    ClearCache,
+    /// Restart the kiosk the client runs on
+    Restart,
+}
+
+#[derive(Deserialize, Debug, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/command/", rename = "SendCommand")]
+pub struct CommandRequest {
+    pub command: DisplayCommand,
+}
+
+struct Client {
+    capabilities: HashSet<Capability>,
+    sender: UnboundedSender<Command>,
+}
+
+pub struct Screenshot {
+    pub taken: DateTime<Utc>,
+    pub png: Bytes,
+}
+
+/// Clients currently connected, used to route commands to their connection
+pub struct Connections {
+    next_id: AtomicU64,
+    clients: RwLock<HashMap<Uuid, HashMap<u64, Client>>>,
+    screenshots: RwLock<HashMap<Uuid, Screenshot>>,
+    /// Displays sent a Screenshot command whose screenshot has not been received yet
+    pending_screenshots: RwLock<HashSet<Uuid>>,
+    /// Time screenshots are taken at, the same clock as the Store's
+    clock: Arc<dyn Clock>,
+}
+
+impl Connections {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Connections {
+            next_id: AtomicU64::new(0),
+            clients: RwLock::new(HashMap::new()),
+            screenshots: RwLock::new(HashMap::new()),
+            pending_screenshots: RwLock::new(HashSet::new()),
+            clock,
+        }
+    }
+
+    /// Registers a connected client of the display, returning its id and the commands sent to it
+    pub async fn register(
+        &self,
+        uuid: Uuid,
+        capabilities: HashSet<Capability>,
+    ) -> (u64, UnboundedReceiver<Command>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.clients.write().await.entry(uuid).or_default().insert(
+            id,
+            Client {
+                capabilities,
+                sender,
+            },
+        );
+        (id, receiver)
+    }
+
+    pub async fn unregister(&self, uuid: Uuid, id: u64) {
+        let mut clients = self.clients.write().await;
+        if let Some(c) = clients.get_mut(&uuid) {
+            c.remove(&id);
+            if c.is_empty() {
+                clients.remove(&uuid);
+            }
+        }
+    }
+
+    /// Sends the command to every connected client of the display supporting it
+    ///
+    /// Returns the amount of clients it was sent to
+    pub async fn send(&self, uuid: Uuid, command: Command) -> Result<usize, (u8, String)> {
+        let clients = self.clients.read().await;
+        let Some(clients) = clients.get(&uuid) else {
+            return Err((2, format!("Display {uuid} is not connected")));
+        };
+        let sent = clients
+            .values()
+            .filter(|c| c.capabilities.contains(&command.capability()))
+            .filter(|c| c.sender.send(command.clone()).is_ok())
+            .count();
+        if sent == 0 {
+            return Err((
+                3,
+                format!("No connected client of Display {uuid} supports {command:?}"),
+            ));
+        }
+        if command == Command::Screenshot {
+            self.pending_screenshots.write().await.insert(uuid);
+        }
+        Ok(sent)
+    }
+
+    /// Stores a screenshot received from a client of the display
+    ///
+    /// Only accepted while a requested screenshot is pending and if it is not too large
+    pub async fn set_screenshot(&self, uuid: Uuid, png: Bytes) -> Result<(), String> {
+        if png.len() > MAX_SCREENSHOT_SIZE {
+            return Err(format!(
+                "Screenshot of {} bytes is larger than the limit of {MAX_SCREENSHOT_SIZE} bytes",
+                png.len()
+            ));
+        }
+        if !self.pending_screenshots.write().await.remove(&uuid) {
+            return Err("No screenshot was requested".to_string());
+        }
+        self.screenshots.write().await.insert(
+            uuid,
+            Screenshot {
+                taken: self.clock.now().to_utc(),
+                png,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/display/{uuid}/command",
+    tag = "display",
+    request_body = CommandRequest,
+    responses(
+        (status = 200, description = "Command sent to the connected clients of the Display", body = Payload,
+            example = json!(Payload::Sent(1))
+        ),
+        (status = BAD_REQUEST, body = Payload,
+            description = "No Display exists with given Uuid, it is not connected or no client supports the command",
+            example = json!(
+                Payload::Error { code: 2, message: "Display <uuid> is not connected".to_string() }
+            )
+        )
+    ),
+    params(
+        ("uuid" = Uuid, Path, description = "Uuid of Display to send the command to")
+    )
+)]
+async fn send_command(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Json(request): Json<CommandRequest>,
+) -> Result<Json<Payload>, (StatusCode, Json<Payload>)> {
+    info!("[Api] Sending {:?} to Display {uuid}", request.command);
+    let Some(name) = state
+        .store
+        .read()
+        .await
+        .displays
+        .get(&uuid)
+        .map(|d| d.name.clone())
+    else {
+        error!("[Api] No display with {uuid} was found");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(Payload::Error {
+                code: 1,
+                message: format!("No Display with the Uuid {uuid} was found"),
+            }),
+        ));
+    };
+
+    let command = match request.command {
+        DisplayCommand::Reload => Command::Reload,
+        DisplayCommand::Identify => Command::Identify { name },
+        DisplayCommand::Screenshot => Command::Screenshot,
+        DisplayCommand::ClearCache => Command::ClearCache,
+        DisplayCommand::Restart => Command::Restart,
+    };
+    match state.connections.send(uuid, command).await {
+        Ok(sent) => Ok(Json(Payload::Sent(sent))),
+        Err((code, message)) => {
+            error!("[Api] {message}");
+            Err((
+                StatusCode::BAD_REQUEST,
+                Json(Payload::Error { code, message }),
+            ))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/display/{uuid}/screenshot",
+    tag = "display",
+    responses(
+        (status = 200, description = "Latest screenshot received from the Display", content_type = "image/png", body = Vec<u8>),
+        (status = NOT_FOUND, body = Payload,
+            description = "No screenshot has been received from the Display",
+            example = json!(
+                Payload::Error { code: 4, message: "No screenshot of Display <uuid> has been received".to_string() }
+            )
+        )
+    ),
+    params(
+        ("uuid" = Uuid, Path, description = "Uuid of Display to get the screenshot of")
+    )
+)]
+async fn get_screenshot(State(state): State<AppState>, Path(uuid): Path<Uuid>) -> Response {
+    match state.connections.screenshots.read().await.get(&uuid) {
+        Some(Screenshot { taken, png }) => (
+            [
+                (header::CONTENT_TYPE, "image/png".to_string()),
+                (
+                    header::LAST_MODIFIED,
+                    taken.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+                ),
+            ],
+            png.clone(),
+        )
+            .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(Payload::Error {
+                code: 4,
+                message: format!("No screenshot of Display {uuid} has been received"),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, path::Path as FilePath, sync::Arc};
+
+    use axum::{
+        Json,
+        body::{Bytes, to_bytes},
+        extract::{Path, State},
+    };
+    use casta_protocol::{Capability, Command};
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use hyper::{StatusCode, header};
+    use tokio::sync::Mutex;
+    use uuid::Uuid;
+
+    use crate::{
+        AppState,
+        file_server::{file_server::FileServer, upload::Quota},
+        store::{
+            clock::{Clock, ManualClock, SystemClock},
+            store::{DisplayMaterial, DisplaySettings, Store, Transition},
+        },
+    };
+
+    use super::{
+        CommandRequest, Connections, DisplayCommand, MAX_SCREENSHOT_SIZE, Payload, get_screenshot,
+        send_command,
+    };
+
+    const DISPLAY: Uuid = Uuid::from_u128(1);
+    const PLAYLIST: Uuid = Uuid::from_u128(2);
+
+    async fn setup_state() -> AppState {
+        setup_state_with(Arc::new(SystemClock)).await
+    }
+
+    async fn setup_state_with(clock: Arc<dyn Clock>) -> AppState {
+        let file_server = FileServer::new(
+            "not used in test environment",
+            FilePath::new("./test_files").join(Uuid::new_v4().to_string()),
+            Quota::default(),
+            TimeDelta::days(30),
+        )
+        .await;
+        let store = Store::new("not used in test environment", clock.clone()).await;
+        store
+            .create_playlist(PLAYLIST, "default".to_string())
+            .await
+            .unwrap();
+        store
+            .create_display(
+                DISPLAY,
+                "Lobby".to_string(),
+                DisplayMaterial::Playlist(PLAYLIST),
+                None,
+                Transition::default(),
+                DisplaySettings::default(),
+            )
+            .await
+            .unwrap();
+        AppState {
+            files: file_server.index(),
+            file_server: Arc::new(Mutex::new(file_server)),
+            htmx_hash: String::new(),
+            store: Arc::new(store),
+            connections: Arc::new(Connections::new(clock)),
+        }
+    }
+
+    async fn send(state: &AppState, uuid: Uuid, command: DisplayCommand) -> Result<usize, u8> {
+        match send_command(
+            State(state.clone()),
+            Path(uuid),
+            Json(CommandRequest { command }),
+        )
+        .await
+        {
+            Ok(Json(Payload::Sent(sent))) => Ok(sent),
+            Err((StatusCode::BAD_REQUEST, Json(Payload::Error { code, .. }))) => Err(code),
+            _ => panic!("Unexpected response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commands_reach_clients_supporting_them() {
+        let state = setup_state().await;
+        assert_eq!(Err(2), send(&state, DISPLAY, DisplayCommand::Reload).await);
+
+        let (_, mut screenshots) = state
+            .connections
+            .register(DISPLAY, HashSet::from([Capability::Screenshot]))
+            .await;
+        let (_, mut reloads) = state
+            .connections
+            .register(
+                DISPLAY,
+                HashSet::from([Capability::Reload, Capability::Identify]),
+            )
+            .await;
+
+        assert_eq!(
+            Ok(1),
+            send(&state, DISPLAY, DisplayCommand::Screenshot).await
+        );
+        assert_eq!(Command::Screenshot, screenshots.try_recv().unwrap());
+        assert!(reloads.try_recv().is_err());
+
+        assert_eq!(Ok(1), send(&state, DISPLAY, DisplayCommand::Identify).await);
+        assert_eq!(
+            Command::Identify {
+                name: "Lobby".to_string()
+            },
+            reloads.try_recv().unwrap()
+        );
+        assert!(screenshots.try_recv().is_err());
+
+        assert_eq!(Err(3), send(&state, DISPLAY, DisplayCommand::Restart).await);
+        assert_eq!(
+            Err(1),
+            send(&state, Uuid::from_u128(3), DisplayCommand::Reload).await
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_screenshot_round_trip() {
+        let taken = Utc.with_ymd_and_hms(2025, 3, 10, 9, 0, 0).unwrap();
+        let state = setup_state_with(Arc::new(ManualClock::new(taken.into()))).await;
+        let (_, _commands) = state
+            .connections
+            .register(DISPLAY, HashSet::from([Capability::Screenshot]))
+            .await;
+        let png = Bytes::from_static(b"not really a png");
+
+        assert!(
+            state
+                .connections
+                .set_screenshot(DISPLAY, png.clone())
+                .await
+                .is_err(),
+            "Accepted a screenshot which was not requested"
+        );
+        assert_eq!(
+            Ok(1),
+            send(&state, DISPLAY, DisplayCommand::Screenshot).await
+        );
+        assert!(
+            state
+                .connections
+                .set_screenshot(DISPLAY, Bytes::from(vec![0; MAX_SCREENSHOT_SIZE + 1]))
+                .await
+                .is_err(),
+            "Accepted a screenshot larger than the limit"
+        );
+        state
+            .connections
+            .set_screenshot(DISPLAY, png.clone())
+            .await
+            .unwrap();
+        assert!(
+            state
+                .connections
+                .set_screenshot(DISPLAY, Bytes::from_static(b"unrequested"))
+                .await
+                .is_err(),
+            "Accepted a second screenshot for one request"
+        );
+
+        let response = get_screenshot(State(state.clone()), Path(DISPLAY)).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "Mon, 10 Mar 2025 09:00:00 GMT",
+            response.headers()[header::LAST_MODIFIED]
+        );
+        assert_eq!(
+            png,
+            to_bytes(response.into_body(), usize::MAX).await.unwrap()
+        );
+
+        let response = get_screenshot(State(state), Path(Uuid::from_u128(3))).await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+}
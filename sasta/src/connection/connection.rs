@@ -1,58 +1,226 @@
 use std::{
+    collections::HashSet,
     fmt::Display,
     net::SocketAddr,
     sync::{self, Arc},
     time::Duration,
 };
 
-use axum::{
-    body::Bytes,
-    extract::ws::{Message, WebSocket},
+use axum::{body::Bytes, extract::ws::Message};
+use casta_protocol::{
+    Capability, DisplayPayload, PROTOCOL_VERSION, PlaylistEntry, RequestPayload, Resolution,
+    ResponsePayload, WebsitePayload,
 };
-use casta_protocol::{DisplayPayload, RequestPayload, ResponsePayload, WebsitePayload};
+use chrono::{DateTime, Local, TimeDelta, Utc};
 use futures_util::{
-    SinkExt, StreamExt,
+    Sink, SinkExt, Stream, StreamExt,
     stream::{SplitSink, SplitStream},
 };
-use maud::{PreEscaped, html};
+use maud::{Markup, PreEscaped, html};
+use sha2::{Digest, Sha256};
 use tokio::{
-    sync::Mutex,
+    sync::{
+        Mutex,
+        broadcast::{Receiver, error::RecvError},
+    },
     time::{Instant, sleep_until, timeout},
 };
 use tracing::{error, info, trace, warn};
+use uuid::Uuid;
 
-use crate::store::store::{Change, ImageData, PlaylistItem, Store, TextData, WebsiteData};
+use super::{commands::Connections, text::render_text};
+use crate::{
+    file_server::file_server::FileIndex,
+    store::{
+        playback::{Cursor, PlaybackMode},
+        store::{Change, ImageData, PdfData, PlaylistItem, Store, TextData, WebsiteData},
+    },
+};
 
-trait IntoHtmx {
-    fn into_htmx(&self) -> String;
+trait ToHtmx {
+    fn to_htmx(&self) -> String;
+    /// Swaps the content into the hidden `#scheduled` element, which the client shows at `show_at`
+    fn to_scheduled_htmx(&self, show_at: u64, server_time: u64) -> String;
 }
 
-impl IntoHtmx for DisplayPayload {
-    fn into_htmx(&self) -> String {
-        html! { div hx-swap-oob="innerHTML:#content" {
-        @match self {
+impl ToHtmx for DisplayPayload {
+    fn to_htmx(&self) -> String {
+        // Also drops anything waiting to be shown, since it would replace this content
+        html! {
+            div hx-swap-oob="innerHTML:#content" { (htmx_content(self)) }
+            div hx-swap-oob="innerHTML:#scheduled" {}
+        }
+        .into_string()
+    }
+
+    fn to_scheduled_htmx(&self, show_at: u64, server_time: u64) -> String {
+        html! { div hx-swap-oob="innerHTML:#scheduled" {
+            div data-show-at=(show_at) data-server-time=(server_time) { (htmx_content(self)) }
+        }}
+        .into_string()
+    }
+}
+
+fn htmx_content(payload: &DisplayPayload) -> Markup {
+    html! {
+        @match payload {
             DisplayPayload::Website(data) => iframe frameborder="0" allow="autoplay; encrypted-media" src=(data.content) allowfullscreen;,
+            // Rendered by Sasta, see `render_text`
             DisplayPayload::Text(data) => {
-                div #text { (data.content) }
+                div #text { (PreEscaped(&data.content)) }
             }
             DisplayPayload::Image(data) => img src=(data.content);,
             DisplayPayload::PortableDocumentFormat(data) => {
                 canvas #pdf-canvas {
                     script {
                         (PreEscaped(format!(
-                            "renderFirstPage(document.getElementById('pdf-canvas'), '{}');",
+                            "renderPage(document.getElementById('pdf-canvas'), '{}');",
                             data.content
                         )))
                     }
                 }
             }
         }
-    }}
-        .into_string()
     }
 }
 
-const ASTA_FLE_PREFIX: &'static str = "ASTA://";
+pub const ASTA_FLE_PREFIX: &str = "ASTA://";
+
+/// How long before the scheduled time an item is sent to displays in a sync group
+const SYNC_LEAD: TimeDelta = TimeDelta::seconds(2);
+
+/// How long before an item is shown it is sent to the client to be loaded off-screen
+const PREPARE_LEAD: Duration = Duration::from_secs(5);
+
+/// Converts the PlaylistItem to what is sent to the client at `now`, together with how long it is shown
+///
+/// Files stored by Sasta are asked for with the hash of their content as `v`, so the client caches them for good,
+/// and images in a size fitting the resolution of the client, if it is known.
+/// Fails for items no client is able to show, which are skipped.
+fn into_display_payload(
+    item: PlaylistItem,
+    display_name: &str,
+    now: DateTime<Local>,
+    resolution: Option<Resolution>,
+    files: &FileIndex,
+) -> Result<(DisplayPayload, u64), String> {
+    let local_path = |src: String, mut query: Vec<String>| {
+        let Some(path) = src.strip_prefix(ASTA_FLE_PREFIX) else {
+            return src;
+        };
+        let (path, fragment) = path.split_at(path.find('#').unwrap_or(path.len()));
+        let path = format!("/{}", path.trim_start_matches('/'));
+        query.extend(files.version(&path).map(|hash| format!("v={hash}")));
+        if query.is_empty() {
+            format!("/files{path}{fragment}")
+        } else {
+            format!("/files{path}?{}{fragment}", query.join("&"))
+        }
+    };
+    Ok(match item {
+        PlaylistItem::Website {
+            settings: WebsiteData { url, duration },
+            ..
+        } => (
+            DisplayPayload::Website(WebsitePayload {
+                content: local_path(url, vec![]),
+            }),
+            duration,
+        ),
+        PlaylistItem::Text { settings, .. } => (
+            DisplayPayload::Text(WebsitePayload {
+                content: render_text(&settings, display_name, now),
+            }),
+            settings.duration,
+        ),
+        PlaylistItem::Image {
+            settings: ImageData { src, duration },
+            ..
+        } => {
+            let sized = match resolution {
+                Some(r) => vec![format!("width={}", r.width), format!("height={}", r.height)],
+                None => vec![],
+            };
+            (
+                DisplayPayload::Image(WebsitePayload {
+                    content: local_path(src, sized),
+                }),
+                duration,
+            )
+        }
+        PlaylistItem::BackgroundAudio { id, .. } => {
+            return Err(format!("Background audio '{id}' is not shown by displays"));
+        }
+        PlaylistItem::Feed { id, .. } => {
+            return Err(format!(
+                "Feed '{id}' was not expanded to the items of its entries"
+            ));
+        }
+        PlaylistItem::PortableDocumentFormat {
+            settings: PdfData { src, duration, .. },
+            ..
+        } => (
+            DisplayPayload::PortableDocumentFormat(WebsitePayload {
+                content: local_path(src, vec![]),
+            }),
+            duration,
+        ),
+    })
+}
+
+/// Version of a Playlist sent to the client, changing whenever its content does
+fn playlist_version(items: &[PlaylistEntry], shuffle: bool) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(serde_json::to_vec(items).unwrap())
+        .chain_update([shuffle as u8])
+        .finalize();
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Waits until the Display, its Playlist or its Schedule changes, returning what changed
+async fn wait_for_change(
+    rx: &mut Receiver<Change>,
+    client_uuid: Uuid,
+    playlist_uuid: Uuid,
+    schedule_uuid: Option<Uuid>,
+) -> Result<String, RecvError> {
+    loop {
+        match rx.recv().await? {
+            Change::Display(d) if d.contains(&client_uuid) => {
+                return Ok(format!("Display {client_uuid}"));
+            }
+            Change::Playlist(p) if p.contains(&playlist_uuid) => {
+                return Ok(format!("Playlist {playlist_uuid}"));
+            }
+            Change::Schedule(s) if schedule_uuid.is_some_and(|u| s.contains(&u)) => {
+                return Ok(format!("Schedule {}", schedule_uuid.unwrap_or_default()));
+            }
+            _ => trace!("Change not related to Display {client_uuid}, skipping"),
+        }
+    }
+}
+
+/// Capability needed by the client to show the PlaylistItem, None if no client is able to show it
+fn item_capability(item: &PlaylistItem) -> Option<Capability> {
+    match item {
+        PlaylistItem::Website { .. } => Some(Capability::Website),
+        PlaylistItem::Text { .. } => Some(Capability::Text),
+        PlaylistItem::Image { .. } => Some(Capability::Image),
+        PlaylistItem::BackgroundAudio { .. } => None,
+        // Only sent once expanded into Text items
+        PlaylistItem::Feed { .. } => None,
+        PlaylistItem::PortableDocumentFormat { .. } => Some(Capability::PortableDocumentFormat),
+    }
+}
+
+fn payload_kind(payload: &DisplayPayload) -> &'static str {
+    match payload {
+        DisplayPayload::Website(_) => "Website",
+        DisplayPayload::Text(_) => "Text",
+        DisplayPayload::Image(_) => "Image",
+        DisplayPayload::PortableDocumentFormat(_) => "PDF",
+    }
+}
 
 /// Websocket connection to the client following the casta protocol
 ///
@@ -60,21 +228,48 @@ const ASTA_FLE_PREFIX: &'static str = "ASTA://";
 /// and should reflect any changes to the htmx client hosted on the server.
 /// The fetched client is expected to refresh to get the latest version if the
 /// hash sent in the welcome response does not match the previously stored hash.
-pub async fn client_connection(
-    socket: WebSocket,
+///
+/// The socket is the upgraded `WebSocket` of the client, or anything else passing its messages.
+pub async fn client_connection<S>(
+    socket: S,
     who: SocketAddr,
     store: Arc<Store>,
+    connections: Arc<Connections>,
+    files: FileIndex,
     htmx_hash: String,
-) {
+) where
+    S: Stream<Item = Result<Message, axum::Error>>
+        + Sink<Message, Error = axum::Error>
+        + Send
+        + 'static,
+{
     let (client_send, mut client_receive) = socket.split();
     let client_send = Arc::new(Mutex::new(client_send));
 
     // Wait for a hello response from connected client to get its UUID
-    let (client_uuid, htmx) = loop {
+    let (client_uuid, htmx, playlist_sync, version, capabilities, hostname, resolution) = loop {
         match client_receive.next().await {
             Some(Ok(Message::Text(msg))) => {
                 match serde_json::from_str::<RequestPayload>(&msg) {
-                    Ok(RequestPayload::Hello { uuid, htmx }) => break (uuid, htmx),
+                    Ok(RequestPayload::Hello {
+                        uuid,
+                        htmx,
+                        playlist_sync,
+                        version,
+                        capabilities,
+                        hostname,
+                        resolution,
+                    }) => {
+                        break (
+                            uuid,
+                            htmx,
+                            playlist_sync,
+                            version,
+                            capabilities,
+                            hostname,
+                            resolution,
+                        );
+                    }
                     _ => error!("[{who}] {msg:?} was not a HelloRequest"),
                 };
             }
@@ -87,15 +282,52 @@ pub async fn client_connection(
 
     let client_name = Arc::new(sync::RwLock::new(None));
 
-    info!("[{who}] Connected with provided Uuid '{client_uuid}' and htmx set to '{htmx}'");
+    info!(
+        "[{who}] Connected with provided Uuid '{client_uuid}', htmx set to '{htmx}' and playlist_sync set to '{playlist_sync}'"
+    );
+    // Only what both sides support is used, clients without a version are assumed to support what they did back then
+    let capabilities: HashSet<Capability> = capabilities
+        .unwrap_or_else(Capability::legacy)
+        .intersection(&Capability::all())
+        .copied()
+        .collect();
+    if version > PROTOCOL_VERSION {
+        warn!(
+            "[{who}] Client uses protocol version {version}, newer than the supported version {PROTOCOL_VERSION}"
+        );
+    }
+    info!(
+        "[{who}] Client on host {hostname:?} with resolution {resolution:?} uses protocol version {version} with capabilities {capabilities:?}"
+    );
 
     let mut heartbeat_handle = tokio::spawn(heartbeat(
         client_send.clone(),
         client_receive,
         who,
         client_name.clone(),
+        connections.clone(),
+        client_uuid,
     ));
 
+    let (connection_id, mut commands) = connections
+        .register(client_uuid, capabilities.clone())
+        .await;
+    let command_send = client_send.clone();
+    let mut command_handle = tokio::spawn(async move {
+        while let Some(command) = commands.recv().await {
+            info!("[{who}] Sending command {command:?}");
+            let msg = Message::Text(
+                serde_json::to_string(&ResponsePayload::Command(command))
+                    .unwrap()
+                    .into(),
+            );
+            if let Err(e) = command_send.lock().await.send(msg).await {
+                error!("[{who}] Could not send command because '{e:?}', exiting");
+                return;
+            }
+        }
+    });
+
     let mut client_handle = tokio::spawn(async move {
         let mut rx = store.receiver();
         loop {
@@ -124,7 +356,7 @@ pub async fn client_connection(
                                     &client_uuid
                                 ),
                             })
-                            .into_htmx()
+                            .to_htmx()
                             .into(),
                         )
                     } else {
@@ -165,29 +397,14 @@ pub async fn client_connection(
             .clone()
             .unwrap_or("Got a None".to_string());
 
-        let msg = Message::Text(
-            serde_json::to_string(&ResponsePayload::Welcome {
-                name: client_name.clone(),
-                htmx_hash: htmx.then(|| htmx_hash),
-            })
-            .unwrap()
-            .into(),
-        );
-
-        if let Err(e) = client_send.lock().await.send(msg).await {
-            error!(
-                "[{who} ({client_name})] Could not send Welcome message because '{e:?}', exiting"
-            );
-            return;
-        };
-
         info!("[{who}] was given the name {client_name}");
 
         // outer loop collects the PlaylistItems(s) before entering the repeating send loop
+        let mut sent_presentation = None;
         'outer_send_loop: loop {
             let (schedule_uuid, playlist_uuid) =
                 store.get_display_uuids(&client_uuid).await.unwrap();
-            let mut playlist = match store.get_display_playlist_items(&client_uuid).await {
+            let mut playlist = match store.get_display_playlist(&client_uuid).await {
                 Some(p) => p,
                 None => {
                     error!("[{who} ({client_name})] Error: Display playlist could not be found");
@@ -195,79 +412,249 @@ pub async fn client_connection(
                 }
             };
 
+            // Skip items the client would not be able to show
+            let defined_items = playlist.items.len();
+            playlist.items.retain(|item| {
+                let supported = item_capability(item).is_some_and(|c| capabilities.contains(&c));
+                if !supported {
+                    warn!(
+                        "[{who} ({client_name})] Skipping '{}', which is not supported by the client",
+                        item.id()
+                    );
+                }
+                supported
+            });
+
             // If playlist is empty, add text stating such to display loop
-            if playlist.is_empty() {
-                playlist.push(PlaylistItem::Text {
+            if playlist.items.is_empty() {
+                let text = if defined_items == 0 {
+                    "No Playlist added"
+                } else {
+                    "No item in the Playlist is supported by this display"
+                };
+                playlist.items.push(PlaylistItem::Text {
                     id: "pending".into(),
                     settings: TextData {
-                        text: "No Playlist added".into(),
+                        text: text.into(),
                         duration: 0,
+                        format: None,
                     },
                 });
             }
 
-            for item in playlist.into_iter().cycle() {
-                let sleep_duration;
-                let payload = match item {
-                    PlaylistItem::Website {
-                        id: name,
-                        settings: WebsiteData { mut url, duration },
-                    } => {
-                        if url.starts_with(ASTA_FLE_PREFIX) {
-                            url = url.replace(ASTA_FLE_PREFIX, "/files/");
+            let (sync_group, transition, settings) = store
+                .read()
+                .await
+                .displays
+                .get(&client_uuid)
+                .map(|d| (d.sync_group.clone(), d.transition, d.settings.clone()))
+                .unwrap_or_default();
+
+            // The transition and settings are part of the Welcome message, so it is sent again whenever they change
+            let presentation = Some((transition, settings.clone()));
+            if sent_presentation != presentation {
+                let msg = Message::Text(
+                    serde_json::to_string(&ResponsePayload::Welcome {
+                        name: client_name.clone(),
+                        htmx_hash: htmx.then(|| htmx_hash.clone()),
+                        transition: transition.into(),
+                        version: PROTOCOL_VERSION,
+                        capabilities: capabilities.clone(),
+                        settings: settings.into(),
+                    })
+                    .unwrap()
+                    .into(),
+                );
+
+                if let Err(e) = client_send.lock().await.send(msg).await {
+                    error!(
+                        "[{who} ({client_name})] Could not send Welcome message because '{e:?}', exiting"
+                    );
+                    return;
+                };
+                sent_presentation = presentation;
+            }
+
+            // The client runs the rotation itself, so the Playlist is only sent again when it changes.
+            // Displays in a sync group are still driven from here, to stay in sync with the group.
+            if playlist_sync
+                && capabilities.contains(&Capability::PlaylistSync)
+                && !htmx
+                && sync_group.is_none()
+            {
+                let items = playlist
+                    .playback
+                    .cycle(playlist.items)
+                    .into_iter()
+                    .flat_map(PlaylistItem::pages)
+                    .filter_map(|item| {
+                        let id = item.id().to_string();
+                        match into_display_payload(
+                            item,
+                            &client_name,
+                            store.now(),
+                            resolution,
+                            &files,
+                        ) {
+                            Ok((payload, duration)) => Some(PlaylistEntry {
+                                id,
+                                payload,
+                                duration,
+                            }),
+                            Err(e) => {
+                                warn!("[{who} ({client_name})] Skipping: {e}");
+                                None
+                            }
                         }
-                        info!("[{who} ({client_name})] Sending Website '{name}'");
-                        sleep_duration = duration;
-                        DisplayPayload::Website(WebsitePayload { content: url })
+                    })
+                    .collect::<Vec<_>>();
+                let shuffle = playlist.playback == PlaybackMode::Shuffle;
+                let version = playlist_version(&items, shuffle);
+
+                info!(
+                    "[{who} ({client_name})] Sending Playlist {playlist_uuid} with {} items (version {version})",
+                    items.len()
+                );
+                let msg = Message::Text(
+                    serde_json::to_string(&ResponsePayload::Playlist {
+                        version,
+                        items,
+                        shuffle,
+                    })
+                    .unwrap()
+                    .into(),
+                );
+                if let Err(e) = client_send.lock().await.send(msg).await {
+                    error!(
+                        "[{who} ({client_name})] Could not send Playlist message because '{e:?}', exiting"
+                    );
+                    return;
+                };
+
+                match wait_for_change(&mut rx, client_uuid, playlist_uuid, schedule_uuid).await {
+                    Ok(change) => {
+                        info!(
+                            "[{who} ({client_name})] {change} has changed, sending Playlist again"
+                        );
+                        continue 'outer_send_loop;
                     }
-                    PlaylistItem::Text {
-                        id: name,
-                        settings: TextData { text, duration },
-                    } => {
-                        info!("[{who} ({client_name})] Sending Text '{name}'");
-                        sleep_duration = duration;
-                        DisplayPayload::Text(WebsitePayload { content: text })
+                    Err(e) => {
+                        error!("[{who} ({client_name})] Exit thread due to error: {e}");
+                        return;
                     }
-                    PlaylistItem::Image {
-                        id: name,
-                        settings: ImageData { mut src, duration },
-                    } => {
-                        if src.starts_with(ASTA_FLE_PREFIX) {
-                            src = src.replace(ASTA_FLE_PREFIX, "/files/");
-                        }
-                        info!("[{who} ({client_name})] Sending Image '{name}'");
-                        sleep_duration = duration;
-                        DisplayPayload::Image(WebsitePayload { content: src })
+                }
+            }
+
+            // Items are paired with the time they should be shown when the display is part of a sync group,
+            // or else with their position in the cycle to resume playback from
+            type Played = (PlaylistItem, Option<DateTime<Utc>>, Option<usize>);
+            let playback: Box<dyn Iterator<Item = Played> + Send> = match sync_group {
+                Some(group) => {
+                    let epoch = store.sync_epoch(&group, playlist_uuid).await;
+                    info!(
+                        "[{who} ({client_name})] Playing in sync group '{group}' started at {epoch}"
+                    );
+                    let joined = store.now().to_utc();
+                    Box::new(
+                        playlist
+                            .playback
+                            .play_synced(playlist.items, epoch, joined)
+                            .flat_map(|(item, show_at)| {
+                                // Pages follow each other within the slot of the document
+                                item.pages().into_iter().scan(show_at, |show_at, page| {
+                                    let page_at = *show_at;
+                                    *show_at += TimeDelta::seconds(page.duration() as i64);
+                                    Some((page, page_at, *show_at))
+                                })
+                            })
+                            // Joining while a document is shown starts at the page shown by the group
+                            .filter(move |(page, _, end)| page.duration() == 0 || *end > joined)
+                            .map(|(page, show_at, _)| (page, Some(show_at), None)),
+                    )
+                }
+                None => {
+                    let mut playback = playlist.playback.play(playlist.items);
+                    if let Some(cursor) = store.get_cursor(&client_uuid).await {
+                        playback = playback.resume_after(&cursor);
                     }
-                    PlaylistItem::BackgroundAudio { .. } => todo!(),
-                    PlaylistItem::PortableDocumentFormat {
-                        id: name,
-                        settings: ImageData { mut src, duration },
-                    } => {
-                        if src.starts_with(ASTA_FLE_PREFIX) {
-                            src = src.replace(ASTA_FLE_PREFIX, "/files/");
+                    Box::new(playback.with_positions().flat_map(|(pos, item)| {
+                        item.pages()
+                            .into_iter()
+                            .map(move |page| (page, None, Some(pos)))
+                    }))
+                }
+            };
+
+            let mut playback = playback.peekable();
+            // Set when the current item was already sent to the htmx client to be shown at its time
+            let mut prepared = false;
+            while let Some((item, show_at, pos)) = playback.next() {
+                let item_id = item.id().to_string();
+                // Items no client is able to show were already removed from the Playlist above
+                let (payload, sleep_duration) =
+                    match into_display_payload(item, &client_name, store.now(), resolution, &files)
+                    {
+                        Ok(converted) => converted,
+                        Err(e) => {
+                            warn!("[{who} ({client_name})] Skipping: {e}");
+                            continue;
                         }
-                        info!("[{who} ({client_name})] Sending PDF '{name}'");
-                        sleep_duration = duration;
-                        DisplayPayload::PortableDocumentFormat(WebsitePayload { content: src })
-                    }
-                };
+                    };
+                let kind = payload_kind(&payload);
 
-                let msg = if htmx {
-                    Message::Text(payload.into_htmx().into())
-                } else {
-                    Message::Text(
+                // Clients unable to show an item at a given time are sent it at that time instead
+                let scheduled = capabilities.contains(&Capability::ScheduledDisplay);
+                if let (Some(show_at), false) = (show_at, scheduled) {
+                    tokio::time::sleep(
+                        (show_at - store.now().to_utc())
+                            .to_std()
+                            .unwrap_or_default(),
+                    )
+                    .await;
+                }
+
+                let msg = match (htmx, show_at.filter(|_| scheduled)) {
+                    (true, None) if prepared => None,
+                    (true, None) => Some(Message::Text(payload.to_htmx().into())),
+                    (true, Some(show_at)) => Some(Message::Text(
+                        payload
+                            .to_scheduled_htmx(
+                                show_at.timestamp_millis() as u64,
+                                store.now().timestamp_millis() as u64,
+                            )
+                            .into(),
+                    )),
+                    (false, None) => Some(Message::Text(
                         serde_json::to_string(&ResponsePayload::Display(payload))
                             .unwrap()
                             .into(),
-                    )
-                };
-                if let Err(e) = client_send.lock().await.send(msg).await {
-                    error!(
-                        "[{who} ({client_name})] Could not send playlist message because '{e:?}', exiting"
-                    );
-                    return;
+                    )),
+                    (false, Some(show_at)) => Some(Message::Text(
+                        serde_json::to_string(&ResponsePayload::ScheduledDisplay {
+                            payload,
+                            show_at: show_at.timestamp_millis() as u64,
+                            server_time: store.now().timestamp_millis() as u64,
+                        })
+                        .unwrap()
+                        .into(),
+                    )),
                 };
+                prepared = false;
+                match msg {
+                    Some(msg) => {
+                        info!("[{who} ({client_name})] Sending {kind} '{item_id}'");
+                        if let Err(e) = client_send.lock().await.send(msg).await {
+                            error!(
+                                "[{who} ({client_name})] Could not send playlist message because '{e:?}', exiting"
+                            );
+                            return;
+                        };
+                    }
+                    None => info!("[{who} ({client_name})] Showing prepared {kind} '{item_id}'"),
+                }
+                store
+                    .set_cursor(client_uuid, Cursor { id: item_id, pos })
+                    .await;
 
                 let now = Instant::now();
                 // Sleep for an infinite time if duration of the PlaylistItem is zero.
@@ -276,40 +663,76 @@ pub async fn client_connection(
                 // A bug that needs to be fixed is that this will stop working in about hundred billion years
                 // and the thread will panic with a overflow error. Note that this will happen for both the
                 // debug and release builds. Truly no one is safe from the catastrophe that will occur...
-                let sleep = now
-                    + Duration::from_secs(if sleep_duration == 0 {
-                        u64::MAX / 10 as u64
-                    } else {
-                        sleep_duration
-                    });
+                let sleep = match show_at {
+                    // Wake up ahead of the next item so the whole group has received it when it should be shown
+                    Some(show_at) if sleep_duration != 0 => {
+                        let next_show_at = show_at + TimeDelta::seconds(sleep_duration as i64);
+                        now + (next_show_at - SYNC_LEAD - store.now().to_utc())
+                            .to_std()
+                            .unwrap_or_default()
+                    }
+                    _ => {
+                        now + Duration::from_secs(if sleep_duration == 0 {
+                            u64::MAX / 10
+                        } else {
+                            sleep_duration
+                        })
+                    }
+                };
+
+                // Send the next item ahead of time, so the client can load it before it is shown.
+                // Displays in a sync group already receive their items ahead of time.
+                let mut prepare_at = (show_at.is_none()
+                    && capabilities.contains(&Capability::Prepare)
+                    && sleep_duration > PREPARE_LEAD.as_secs())
+                .then(|| sleep - PREPARE_LEAD);
 
                 loop {
                     info!(
                         "[{who} ({client_name})] Sleeping for {} seconds",
-                        (sleep - now).as_secs()
+                        (sleep - Instant::now()).as_secs()
                     );
                     tokio::select! {
                         _ = sleep_until(sleep) => break,
-                        notification = rx.recv() => {
-                            match notification {
-                                Ok(c) => {
-                                    match c {
-                                        Change::Display(d) if d.contains(&client_uuid) => {
-                                            info!("[{who} ({client_name})] Display {} has changed, restarting send loop", client_uuid);
-                                            continue 'outer_send_loop
-                                        },
-                                        Change::Playlist(p) if p.contains(&playlist_uuid)  => {
-                                            info!("[{who} ({client_name})] Playlist {} has changed, restarting send loop", playlist_uuid);
-                                            continue 'outer_send_loop
-                                        },
-                                        Change::Schedule(s) if schedule_uuid.is_some_and(|u| s.contains(&u)) => {
-                                            info!("[{who} ({client_name})] Schedule {} has changed, restarting send loop", schedule_uuid.unwrap_or_default());
-                                            continue 'outer_send_loop
-                                        },
-                                        _ => {
-                                            trace!("[{who} ({client_name})] Message received but no Change relate to current client, skipping")
-                                        }
-                                    }
+                        _ = sleep_until(prepare_at.unwrap_or(sleep)), if prepare_at.is_some() => {
+                            prepare_at = None;
+                            let Some((next, None, _)) = playback.peek() else {
+                                continue;
+                            };
+                            let next_id = next.id().to_string();
+                            let Ok((next, _)) = into_display_payload(next.clone(), &client_name, store.now(), resolution, &files) else {
+                                continue;
+                            };
+                            info!("[{who} ({client_name})] Preparing {} '{next_id}'", payload_kind(&next));
+                            let msg = if htmx {
+                                // The htmx client cannot tell which item is shown next, so it is told when instead
+                                prepared = true;
+                                let server_time = store.now();
+                                let show_at = server_time + TimeDelta::from_std(sleep - Instant::now()).unwrap_or_default();
+                                Message::Text(
+                                    next.to_scheduled_htmx(
+                                        show_at.timestamp_millis() as u64,
+                                        server_time.timestamp_millis() as u64,
+                                    )
+                                    .into(),
+                                )
+                            } else {
+                                Message::Text(
+                                    serde_json::to_string(&ResponsePayload::Prepare(next))
+                                        .unwrap()
+                                        .into(),
+                                )
+                            };
+                            if let Err(e) = client_send.lock().await.send(msg).await {
+                                error!("[{who} ({client_name})] Could not send prepare message because '{e:?}', exiting");
+                                return;
+                            };
+                        },
+                        change = wait_for_change(&mut rx, client_uuid, playlist_uuid, schedule_uuid) => {
+                            match change {
+                                Ok(change) => {
+                                    info!("[{who} ({client_name})] {change} has changed, restarting send loop");
+                                    continue 'outer_send_loop
                                 },
                                 Err(e) => {
                                     error!("[{who} ({client_name})] Exit thread due to error: {e}");
@@ -324,19 +747,25 @@ pub async fn client_connection(
     });
 
     tokio::select! {
-        _ = &mut heartbeat_handle  => client_handle.abort(),
-        _ = &mut client_handle     => heartbeat_handle.abort(),
+        _ = &mut heartbeat_handle  => { client_handle.abort(); command_handle.abort() },
+        _ = &mut client_handle     => { heartbeat_handle.abort(); command_handle.abort() },
+        _ = &mut command_handle    => { heartbeat_handle.abort(); client_handle.abort() },
     };
+    connections.unregister(client_uuid, connection_id).await;
 
     info!("[{who}] Disconnected from client!");
 }
 
-async fn heartbeat(
-    sender: Arc<Mutex<SplitSink<WebSocket, Message>>>,
-    mut receiver: SplitStream<WebSocket>,
+async fn heartbeat<S>(
+    sender: Arc<Mutex<SplitSink<S, Message>>>,
+    mut receiver: SplitStream<S>,
     who: SocketAddr,
     client_name: Arc<sync::RwLock<Option<String>>>,
-) {
+    connections: Arc<Connections>,
+    client_uuid: Uuid,
+) where
+    S: Stream<Item = Result<Message, axum::Error>> + Sink<Message, Error = axum::Error>,
+{
     let who = Who { who, client_name };
     let mut interval = tokio::time::interval(Duration::from_secs(8));
     // Must make sure a pong is received before the next ping is sent out.
@@ -369,6 +798,13 @@ async fn heartbeat(
                             error!("{who} Error receiving messages: {e:?}");
                             break Err(());
                         }
+                        // Screenshots requested by Command::Screenshot are the only binary messages
+                        Ok(Message::Binary(png)) => {
+                            match connections.set_screenshot(client_uuid, png).await {
+                                Ok(()) => info!("{who} Received screenshot"),
+                                Err(e) => warn!("{who} Ignoring binary message: {e}"),
+                            }
+                        }
                         Ok(m) => warn!("{who} Received irrelevant message: {m:?}"),
                     },
                     None => error!("{who} Error: receiver is empty"),
@@ -409,3 +845,369 @@ impl Display for Who {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        path::Path,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use axum::extract::ws::Message;
+    use casta_protocol::{
+        Capability, DisplayPayload, PROTOCOL_VERSION, RequestPayload, Resolution, ResponsePayload,
+    };
+    use chrono::{DateTime, Local, TimeDelta, TimeZone};
+    use futures_util::{Sink, Stream};
+    use tokio::sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        connection::commands::Connections,
+        file_server::{
+            file_server::{FileIndex, FileServer},
+            upload::Quota,
+        },
+        store::{
+            clock::{ManualClock, SystemClock},
+            playback::PlaybackMode,
+            schedule::{Schedule, ScheduledPlaylistInput},
+            store::{
+                DisplayMaterial, DisplaySettings, ImageData, PlaylistItem, Store, TextData,
+                Transition,
+            },
+        },
+    };
+
+    use super::{client_connection, into_display_payload};
+
+    const DISPLAY: Uuid = Uuid::from_u128(1);
+    const SCHEDULE: Uuid = Uuid::from_u128(2);
+    const MORNING: Uuid = Uuid::from_u128(3);
+    const DEFAULT: Uuid = Uuid::from_u128(4);
+
+    fn time(h: u32, m: u32, s: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2025, 3, 10, h, m, s).unwrap()
+    }
+
+    /// Socket of a client connected through channels instead of the network
+    struct FakeSocket {
+        incoming: UnboundedReceiver<Message>,
+        outgoing: UnboundedSender<Message>,
+    }
+
+    impl Stream for FakeSocket {
+        type Item = Result<Message, axum::Error>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            self.incoming.poll_recv(cx).map(|m| m.map(Ok))
+        }
+    }
+
+    impl Sink<Message> for FakeSocket {
+        type Error = axum::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+            self.outgoing.send(item).map_err(axum::Error::new)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// File server in its own directory, holding the files shown by displays
+    async fn setup_file_server() -> FileServer {
+        FileServer::new(
+            "not used in test environment",
+            Path::new("./test_files").join(Uuid::new_v4().to_string()),
+            Quota::default(),
+            TimeDelta::days(30),
+        )
+        .await
+    }
+
+    /// Connects a client showing text to the display, returning what it receives along with the time it was received at
+    fn connect(
+        store: Arc<Store>,
+        display: Uuid,
+        files: FileIndex,
+    ) -> UnboundedReceiver<(DateTime<Local>, ResponsePayload)> {
+        let hello = RequestPayload::Hello {
+            uuid: display,
+            htmx: false,
+            playlist_sync: false,
+            version: PROTOCOL_VERSION,
+            capabilities: Some(HashSet::from([Capability::Text])),
+            hostname: None,
+            resolution: None,
+        };
+        connect_with(store, serde_json::to_value(&hello).unwrap(), files)
+    }
+
+    /// Connects a client greeting with the Hello, returning what it receives along with the time it was received at
+    fn connect_with(
+        store: Arc<Store>,
+        hello: serde_json::Value,
+        files: FileIndex,
+    ) -> UnboundedReceiver<(DateTime<Local>, ResponsePayload)> {
+        let (client_send, incoming) = mpsc::unbounded_channel();
+        let (outgoing, mut client_receive) = mpsc::unbounded_channel();
+        tokio::spawn(client_connection(
+            FakeSocket { incoming, outgoing },
+            "127.0.0.1:0".parse().unwrap(),
+            store.clone(),
+            Arc::new(Connections::new(Arc::new(SystemClock))),
+            files,
+            String::new(),
+        ));
+
+        client_send
+            .send(Message::Text(hello.to_string().into()))
+            .unwrap();
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(msg) = client_receive.recv().await {
+                match msg {
+                    Message::Ping(p) => {
+                        let _ = client_send.send(Message::Pong(p));
+                    }
+                    Message::Text(text) => {
+                        let _ = tx.send((store.now(), serde_json::from_str(&text).unwrap()));
+                    }
+                    _ => (),
+                }
+            }
+        });
+        rx
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clients_follow_schedule() {
+        let clock = Arc::new(ManualClock::new(time(9, 59, 0)));
+        let store = Arc::new(Store::new("not used in test environment", clock).await);
+        let text = |id: &str, text: &str, duration| PlaylistItem::Text {
+            id: id.into(),
+            settings: TextData {
+                text: text.into(),
+                duration,
+                format: None,
+            },
+        };
+
+        for (uuid, items) in [
+            (DEFAULT, vec![text("default", "Default at {{time}}", 0)]),
+            (
+                MORNING,
+                vec![text("first", "First", 1500), text("second", "Second", 1500)],
+            ),
+        ] {
+            store.create_playlist(uuid, uuid.to_string()).await.unwrap();
+            store
+                .update_playlist(uuid, uuid.to_string(), items, PlaybackMode::default())
+                .await
+                .unwrap();
+        }
+        store
+            .create_schedule(SCHEDULE, "test".to_string(), DEFAULT)
+            .await
+            .unwrap();
+        let schedule = Schedule::new(
+            "test".to_string(),
+            vec![ScheduledPlaylistInput {
+                playlist: MORNING,
+                start: "0 0 10 * * *".to_string(),
+                end: "0 0 11 * * *".to_string(),
+            }],
+            DEFAULT,
+            &store.now(),
+        )
+        .unwrap();
+        store.update_schedule(SCHEDULE, schedule).await.unwrap();
+        store
+            .create_display(
+                DISPLAY,
+                "Lobby".to_string(),
+                DisplayMaterial::Schedule(SCHEDULE),
+                None,
+                Transition::default(),
+                DisplaySettings::default(),
+            )
+            .await
+            .unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let store_copy = store.clone();
+        tokio::spawn(async move { store_copy.schedule_loop(tx).await });
+        rx.await.unwrap();
+
+        let files = setup_file_server().await.index();
+        let mut received = connect(store.clone(), DISPLAY, files);
+        tokio::time::sleep(Duration::from_secs(2 * 60 * 60)).await;
+
+        let mut shown = vec![];
+        while let Ok((at, payload)) = received.try_recv() {
+            match payload {
+                ResponsePayload::Welcome { name, .. } => {
+                    shown.push((at, format!("Welcome {name}")))
+                }
+                ResponsePayload::Display(DisplayPayload::Text(text)) => {
+                    shown.push((at, text.content))
+                }
+                payload => panic!("Unexpected {payload:?}"),
+            }
+        }
+        let default_at =
+            |time: &str| format!("Default at <time data-template=\"time\">{time}</time>");
+        assert_eq!(
+            vec![
+                (time(9, 59, 0), "Welcome Lobby".to_string()),
+                (time(9, 59, 0), default_at("09:59")),
+                (time(10, 0, 0), "First".to_string()),
+                (time(10, 25, 0), "Second".to_string()),
+                (time(10, 50, 0), "First".to_string()),
+                (time(11, 0, 0), default_at("11:00")),
+            ],
+            shown
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stored_files_are_asked_for_by_version() {
+        let mut file_server = setup_file_server().await;
+        file_server
+            .add_file("/media/photo.png".to_string(), b"not really a png".to_vec())
+            .await
+            .unwrap();
+        let files = file_server.index();
+        let hash = files.version("/media/photo.png").unwrap();
+        let image = |src: &str| PlaylistItem::Image {
+            id: "photo".into(),
+            settings: ImageData {
+                src: src.into(),
+                duration: 10,
+            },
+        };
+        let content = |item, resolution| match into_display_payload(
+            item,
+            "Lobby",
+            time(10, 0, 0),
+            resolution,
+            &files,
+        ) {
+            Ok((DisplayPayload::Image(image), _)) => image.content,
+            other => panic!("Unexpected {other:?}"),
+        };
+
+        assert_eq!(
+            format!("/files/media/photo.png?v={hash}"),
+            content(image("ASTA://media/photo.png"), None)
+        );
+        assert_eq!(
+            format!("/files/media/photo.png?width=1920&height=1080&v={hash}"),
+            content(
+                image("ASTA://media/photo.png"),
+                Some(Resolution {
+                    width: 1920,
+                    height: 1080
+                })
+            )
+        );
+        assert_eq!(
+            "/files/media/missing.png",
+            content(image("ASTA://media/missing.png"), None)
+        );
+        assert_eq!(
+            "https://example.com/photo.png",
+            content(image("https://example.com/photo.png"), None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_background_audio_is_not_converted() {
+        let files = setup_file_server().await.index();
+        let audio = PlaylistItem::BackgroundAudio {
+            id: "music".into(),
+            settings: ImageData {
+                src: "ASTA://music.mp3".into(),
+                duration: 10,
+            },
+        };
+        assert_eq!(
+            Err("Background audio 'music' is not shown by displays".to_string()),
+            into_display_payload(audio, "Lobby", time(10, 0, 0), None, &files).map(|_| ())
+        );
+    }
+
+    /// Capabilities confirmed in the Welcome sent to a client greeting with the capabilities
+    async fn negotiate(capabilities: serde_json::Value) -> HashSet<Capability> {
+        let store = Arc::new(
+            Store::new(
+                "not used in test environment",
+                Arc::new(ManualClock::new(time(10, 0, 0))),
+            )
+            .await,
+        );
+        store
+            .create_playlist(DEFAULT, "default".to_string())
+            .await
+            .unwrap();
+        store
+            .create_display(
+                DISPLAY,
+                "Lobby".to_string(),
+                DisplayMaterial::Playlist(DEFAULT),
+                None,
+                Transition::default(),
+                DisplaySettings::default(),
+            )
+            .await
+            .unwrap();
+        let mut hello = serde_json::json!({
+            "type": "Hello",
+            "data": { "uuid": DISPLAY, "version": PROTOCOL_VERSION }
+        });
+        if !capabilities.is_null() {
+            hello["data"]["capabilities"] = capabilities;
+        }
+        let mut received = connect_with(store, hello, setup_file_server().await.index());
+        loop {
+            match received.recv().await.unwrap().1 {
+                ResponsePayload::Welcome { capabilities, .. } => return capabilities,
+                _ => continue,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_welcome_confirms_known_capabilities() {
+        assert_eq!(
+            HashSet::from([Capability::Text, Capability::Prepare]),
+            negotiate(serde_json::json!(["Text", "Prepare", "Hologram"])).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_legacy_clients_get_legacy_capabilities() {
+        assert_eq!(
+            Capability::legacy(),
+            negotiate(serde_json::Value::Null).await
+        );
+    }
+}
@@ -1 +1,3 @@
+pub mod commands;
 pub mod connection;
+pub mod text;
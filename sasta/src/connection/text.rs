@@ -0,0 +1,240 @@
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Local, TimeDelta, Utc};
+use maud::html;
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd, TextMergeStream};
+use regex::{Captures, Regex};
+
+use crate::store::store::{TextData, TextFormat};
+
+/// Template variables such as `{{display.name}}` or `{{countdown:2025-12-24T18:00:00Z}}`
+static TEMPLATE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*([\w.]+)(?::([^{}]+?))?\s*\}\}").unwrap());
+
+/// Renders the text of a Text item to the HTML sent to clients
+///
+/// Template variables changing over time are wrapped in `time` elements with a `data-template` attribute,
+/// holding their value at `now` until the client updates them. Only text is expanded, not urls or attributes.
+pub fn render_text(text: &TextData, display_name: &str, now: DateTime<Local>) -> String {
+    match text.format.unwrap_or_default() {
+        TextFormat::Plain => expand(&text.text, display_name, now),
+        TextFormat::Markdown => markdown(&text.text, display_name, now),
+    }
+}
+
+fn markdown(text: &str, display_name: &str, now: DateTime<Local>) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    // Alt text of images is written to an attribute, where elements cannot be expanded to
+    let mut in_image = 0;
+    // Raw HTML is shown as text and links may not run scripts, so only what Markdown can express is rendered
+    let parser = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        e => e,
+    });
+    // Text is split into several events around some characters, which could split a template variable
+    let parser = TextMergeStream::new(parser).map(|event| match event {
+        Event::Start(Tag::Image { .. }) => {
+            in_image += 1;
+            event
+        }
+        Event::End(TagEnd::Image) => {
+            in_image -= 1;
+            event
+        }
+        Event::Text(text) if in_image == 0 => {
+            Event::InlineHtml(expand(&text, display_name, now).into())
+        }
+        e => e,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html
+}
+
+/// Keeps relative urls and those using http(s) or mailto, replacing anything else
+fn safe_url(url: CowStr) -> CowStr {
+    let scheme = url.split_once(':').map(|(scheme, _)| scheme).filter(|s| {
+        s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    });
+    match scheme.map(|s| s.to_ascii_lowercase()).as_deref() {
+        None | Some("http") | Some("https") | Some("mailto") => url,
+        Some(_) => CowStr::Borrowed("#"),
+    }
+}
+
+/// Escapes the text to HTML, expanding the template variables in it
+fn expand(text: &str, display_name: &str, now: DateTime<Local>) -> String {
+    let mut html = String::new();
+    let mut last = 0;
+    for c in TEMPLATE_REGEX.captures_iter(text) {
+        let whole = c.get(0).unwrap();
+        html += &html! { (text[last..whole.start()]) }.into_string();
+        html += &variable(&c, display_name, now);
+        last = whole.end();
+    }
+    html += &html! { (text[last..]) }.into_string();
+    html
+}
+
+fn variable(c: &Captures, display_name: &str, now: DateTime<Local>) -> String {
+    let argument = c.get(2).map(|a| a.as_str().trim());
+    match (&c[1], argument) {
+        ("display.name", None) => html! { (display_name) }.into_string(),
+        ("date", None) => html! {
+            time data-template="date" { (now.format("%Y-%m-%d")) }
+        }
+        .into_string(),
+        ("time", None) => html! {
+            time data-template="time" { (now.format("%H:%M")) }
+        }
+        .into_string(),
+        ("countdown", Some(until)) => match DateTime::parse_from_rfc3339(until) {
+            Ok(until) => html! {
+                time data-template="countdown" datetime=(until.to_rfc3339()) data-until=(until.timestamp_millis()) {
+                    (countdown(until.with_timezone(&Utc) - now.with_timezone(&Utc)))
+                }
+            }
+            .into_string(),
+            Err(_) => html! { (c[0]) }.into_string(),
+        },
+        // Left as is, so a mistake shows up on the display
+        _ => html! { (c[0]) }.into_string(),
+    }
+}
+
+/// Formats the time left as days, hours, minutes and seconds, stopping at zero
+///
+/// Clients format countdowns the same way when updating them.
+fn countdown(left: TimeDelta) -> String {
+    let seconds = left.num_seconds().max(0);
+    let (d, h, m, s) = (
+        seconds / 86400,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+    );
+    if d > 0 {
+        format!("{d}d {h:02}:{m:02}:{s:02}")
+    } else {
+        format!("{h:02}:{m:02}:{s:02}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Local, TimeZone};
+
+    use crate::store::store::{TextData, TextFormat};
+
+    use super::render_text;
+
+    fn render(text: &str, format: Option<TextFormat>) -> String {
+        let now = Local.with_ymd_and_hms(2025, 12, 24, 17, 30, 0).unwrap();
+        render_text(
+            &TextData {
+                text: text.to_string(),
+                duration: 10,
+                format,
+            },
+            "Building <A>",
+            now,
+        )
+    }
+
+    #[test]
+    fn test_plain_text_is_escaped() {
+        assert_eq!(
+            "&lt;b&gt;Hello&lt;/b&gt; *world*",
+            render("<b>Hello</b> *world*", None)
+        );
+    }
+
+    #[test]
+    fn test_markdown_is_rendered_without_raw_html() {
+        assert_eq!(
+            "<p><em>Hello</em> &lt;script&gt;alert(1)&lt;/script&gt;</p>\n",
+            render(
+                "*Hello* <script>alert(1)</script>",
+                Some(TextFormat::Markdown)
+            )
+        );
+        assert_eq!(
+            "<p><a href=\"#\">a</a> <a href=\"https://example.com\">b</a></p>\n",
+            render(
+                "[a](javascript:alert(1)) [b](https://example.com)",
+                Some(TextFormat::Markdown)
+            )
+        );
+    }
+
+    #[test]
+    fn test_templates_are_expanded() {
+        assert_eq!(
+            "Welcome to Building &lt;A&gt;",
+            render("Welcome to {{display.name}}", None)
+        );
+        assert_eq!(
+            "<h1>Building &lt;A&gt;</h1>\n",
+            render("# {{ display.name }}", Some(TextFormat::Markdown))
+        );
+        assert_eq!(
+            "<time data-template=\"date\">2025-12-24</time> <time data-template=\"time\">17:30</time>",
+            render("{{date}} {{time}}", None)
+        );
+        assert_eq!(
+            "{{unknown}} {{countdown:not a date}}",
+            render("{{unknown}} {{countdown:not a date}}", None)
+        );
+    }
+
+    #[test]
+    fn test_templates_are_not_expanded_in_attributes() {
+        assert_eq!(
+            "<p><a href=\"https://example.com/%7B%7Btime%7D%7D\" title=\"{{date}}\">at <time data-template=\"time\">17:30</time></a></p>\n",
+            render(
+                "[at {{time}}](https://example.com/{{time}} \"{{date}}\")",
+                Some(TextFormat::Markdown)
+            )
+        );
+        assert_eq!(
+            "<p><img src=\"logo.png\" alt=\"{{display.name}}\" /></p>\n",
+            render("![{{display.name}}](logo.png)", Some(TextFormat::Markdown))
+        );
+    }
+
+    #[test]
+    fn test_countdown() {
+        let until = Local.with_ymd_and_hms(2025, 12, 26, 18, 31, 5).unwrap();
+        let rendered = render(&format!("{{{{countdown:{}}}}}", until.to_rfc3339()), None);
+        assert!(rendered.starts_with("<time data-template=\"countdown\""));
+        assert!(rendered.contains(&format!("data-until=\"{}\"", until.timestamp_millis())));
+        assert!(rendered.ends_with(">2d 01:01:05</time>"));
+
+        let passed = Local.with_ymd_and_hms(2025, 12, 24, 0, 0, 0).unwrap();
+        let rendered = render(&format!("{{{{countdown:{}}}}}", passed.to_rfc3339()), None);
+        assert!(rendered.ends_with(">00:00:00</time>"));
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use super::upload::{FileItem, StagedFile};
+
+/// Most entries unpacked from a single archive
+const MAX_ENTRIES: usize = 10_000;
+
+/// Most bytes unpacked from the archives of one upload, even if storage is unlimited
+pub const MAX_UNPACKED_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, ToSchema)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Format of an uploaded file by its name, uploads in one of the formats are unpacked
+    pub fn of(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::TarGz => "application/gzip",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct ArchiveQuery {
+    /// Directory to download, ending with a `'/'`
+    pub path: String,
+    /// Format of the archive, `zip` if not given
+    #[serde(default)]
+    #[param(value_type = Option<ArchiveFormat>)]
+    pub format: ArchiveFormat,
+}
+
+/// Files and directories unpacked from an archive, with paths relative to the root of the archive
+#[derive(Default)]
+pub struct Unpacked {
+    pub directories: Vec<String>,
+    pub files: Vec<FileItem>,
+    /// Bytes in all the files
+    pub size: u64,
+}
+
+impl Unpacked {
+    fn add(
+        &mut self,
+        name: &str,
+        is_dir: bool,
+        content: impl Read,
+        dir: &Path,
+        max_file_size: Option<u64>,
+        max_size: u64,
+    ) -> Result<(), (u8, String)> {
+        if self.directories.len() + self.files.len() >= MAX_ENTRIES {
+            return Err((
+                9,
+                format!("Archives may not contain more than {MAX_ENTRIES} entries"),
+            ));
+        }
+        let name = name.trim_start_matches("./").trim_matches('/').to_string();
+        // Resource forks added when compressing on macOS
+        if name.is_empty() || name.starts_with("__MACOSX") {
+            return Ok(());
+        }
+        if is_dir {
+            self.directories.push(name);
+        } else {
+            let left = max_size - self.size;
+            let size_limited = max_file_size.is_none_or(|max| left < max);
+            let max_file_size = if size_limited {
+                Some(left)
+            } else {
+                max_file_size
+            };
+            let content = StagedFile::read_from(dir, content, max_file_size)
+                .map_err(|(code, message)| match code {
+                    6 if size_limited => (
+                        6,
+                        format!("Archives may not unpack to more than {max_size} bytes"),
+                    ),
+                    _ => (code, message),
+                })
+                .map_err(|(code, message)| (code, format!("{message} ({name})")))?;
+            self.size += content.size();
+            self.files.push(FileItem { name, content });
+        }
+        Ok(())
+    }
+}
+
+/// Unpacks the archive, writing its files to staged files in `dir`
+///
+/// Paths are not checked here, but by `VirtualPath` when the files are added to the tree.
+/// Links and other special entries are skipped. Unpacking stops once the files are larger than
+/// `max_size` bytes in total, so small archives cannot fill the disk. Blocks, so it is run with `spawn_blocking`.
+pub fn unpack(
+    format: ArchiveFormat,
+    archive: &Path,
+    dir: &Path,
+    max_file_size: Option<u64>,
+    max_size: u64,
+) -> Result<Unpacked, (u8, String)> {
+    let invalid = |e: &dyn std::fmt::Display| (9, format!("Could not unpack archive ({e})"));
+    let archive = File::open(archive).map_err(|e| (8, format!("Could not read upload ({e})")))?;
+    let mut unpacked = Unpacked::default();
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = ZipArchive::new(archive).map_err(|e| invalid(&e))?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).map_err(|e| invalid(&e))?;
+                let name = entry.name().to_string();
+                let is_dir = entry.is_dir();
+                unpacked.add(&name, is_dir, &mut entry, dir, max_file_size, max_size)?;
+            }
+        }
+        ArchiveFormat::TarGz => {
+            let mut tar = tar::Archive::new(GzDecoder::new(archive));
+            for entry in tar.entries().map_err(|e| invalid(&e))? {
+                let mut entry = entry.map_err(|e| invalid(&e))?;
+                let kind = entry.header().entry_type();
+                if !kind.is_file() && !kind.is_dir() {
+                    continue;
+                }
+                let name = entry
+                    .path()
+                    .map_err(|e| invalid(&e))?
+                    .to_string_lossy()
+                    .into_owned();
+                unpacked.add(
+                    &name,
+                    kind.is_dir(),
+                    &mut entry,
+                    dir,
+                    max_file_size,
+                    max_size,
+                )?;
+            }
+        }
+    }
+    Ok(unpacked)
+}
+
+/// Entry of an archive being packed, named by its path relative to the root of the archive
+pub enum PackEntry {
+    Directory(String),
+    /// Name and path of the content on disk
+    File(String, PathBuf),
+}
+
+/// Writes the entries to a new archive at `to`
+///
+/// Blocks, so it is run with `spawn_blocking`.
+pub fn pack(format: ArchiveFormat, entries: &[PackEntry], to: &Path) -> io::Result<()> {
+    let archive = File::create(to)?;
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = ZipWriter::new(archive);
+            let options = SimpleFileOptions::default().large_file(true);
+            for entry in entries {
+                match entry {
+                    PackEntry::Directory(name) => {
+                        zip.add_directory(name, options).map_err(io::Error::other)?
+                    }
+                    PackEntry::File(name, path) => {
+                        zip.start_file(name, options).map_err(io::Error::other)?;
+                        io::copy(&mut File::open(path)?, &mut zip)?;
+                    }
+                }
+            }
+            zip.finish().map_err(io::Error::other)?.sync_all()
+        }
+        ArchiveFormat::TarGz => {
+            let mut tar = tar::Builder::new(GzEncoder::new(archive, Compression::default()));
+            for entry in entries {
+                match entry {
+                    PackEntry::Directory(name) => {
+                        let mut header = tar::Header::new_gnu();
+                        header.set_entry_type(tar::EntryType::Directory);
+                        header.set_mode(0o755);
+                        header.set_size(0);
+                        tar.append_data(&mut header, name, io::empty())?;
+                    }
+                    PackEntry::File(name, path) => tar.append_path_with_name(path, name)?,
+                }
+            }
+            tar.into_inner()?.finish()?.sync_all()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_format_of_name() {
+        assert_eq!(Some(ArchiveFormat::Zip), ArchiveFormat::of("bundle.ZIP"));
+        assert_eq!(
+            Some(ArchiveFormat::TarGz),
+            ArchiveFormat::of("bundle.tar.gz")
+        );
+        assert_eq!(Some(ArchiveFormat::TarGz), ArchiveFormat::of("bundle.tgz"));
+        assert_eq!(None, ArchiveFormat::of("bundle.tar"));
+        assert_eq!(None, ArchiveFormat::of("zip.png"));
+    }
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let dir = Path::new("./test_files").join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("content"), b"hello").unwrap();
+        let entries = [
+            PackEntry::Directory("empty".to_string()),
+            PackEntry::File("slides/a.txt".to_string(), dir.join("content")),
+        ];
+
+        for format in [ArchiveFormat::Zip, ArchiveFormat::TarGz] {
+            let archive = dir.join(format!("archive.{}", format.extension()));
+            pack(format, &entries, &archive).unwrap();
+            let unpacked = unpack(format, &archive, &dir, None, MAX_UNPACKED_SIZE).unwrap();
+            assert_eq!(vec!["empty".to_string()], unpacked.directories);
+            assert_eq!(1, unpacked.files.len());
+            assert_eq!("slides/a.txt", unpacked.files[0].name);
+            assert_eq!(5, unpacked.files[0].content.size());
+            assert_eq!(5, unpacked.size);
+
+            let error = unpack(format, &archive, &dir, Some(4), MAX_UNPACKED_SIZE).err();
+            assert_eq!(6, error.unwrap().0);
+        }
+        assert_eq!(
+            9,
+            unpack(
+                ArchiveFormat::Zip,
+                &dir.join("content"),
+                &dir,
+                None,
+                MAX_UNPACKED_SIZE
+            )
+            .err()
+            .unwrap()
+            .0
+        );
+    }
+
+    #[test]
+    fn test_unpacked_size_is_limited() {
+        let dir = Path::new("./test_files").join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("content"), b"hello").unwrap();
+        let entries = [
+            PackEntry::File("a.txt".to_string(), dir.join("content")),
+            PackEntry::File("b.txt".to_string(), dir.join("content")),
+        ];
+
+        for format in [ArchiveFormat::Zip, ArchiveFormat::TarGz] {
+            let archive = dir.join(format!("archive.{}", format.extension()));
+            pack(format, &entries, &archive).unwrap();
+            let (code, message) = unpack(format, &archive, &dir, Some(5), 8).err().unwrap();
+            assert_eq!(6, code);
+            assert!(message.contains("more than 8 bytes"));
+            assert_eq!(
+                10,
+                unpack(format, &archive, &dir, Some(5), 10).unwrap().size
+            );
+        }
+    }
+}
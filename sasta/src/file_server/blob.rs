@@ -0,0 +1,44 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+/// Problem found with the blob of a file by the scrub job
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/files/")]
+pub enum BlobDamage {
+    /// The blob is not on disk or cannot be read
+    #[serde(rename = "missing")]
+    Missing,
+    /// The content of the blob no longer matches its hash
+    #[serde(rename = "corrupted")]
+    Corrupted,
+}
+
+/// Hashes the blob at the path without reading all of it into memory
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Checks the blob against the hash it was stored with, returning the hash of its content
+///
+/// Blobs stored before they were hashed have no hash to check, so they only have to be readable.
+pub fn check(path: &Path, hash: Option<&str>) -> Result<String, BlobDamage> {
+    match hash_file(path) {
+        Ok(found) if hash.is_none_or(|hash| hash == found) => Ok(found),
+        Ok(_) => Err(BlobDamage::Corrupted),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(BlobDamage::Missing),
+        Err(e) => {
+            tracing::warn!("Could not read blob {} ({e})", path.display());
+            Err(BlobDamage::Missing)
+        }
+    }
+}
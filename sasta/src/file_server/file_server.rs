@@ -1,31 +1,37 @@
 use std::{
-    collections::{LinkedList, VecDeque},
+    collections::{HashMap, LinkedList, VecDeque},
     ffi::OsString,
-    io::Write,
     path::{Path, PathBuf},
     sync::{Arc, LazyLock, Mutex},
+    time::Duration,
 };
 
 use axum::{
     Json,
     body::Body,
-    extract::{DefaultBodyLimit, Multipart, State},
+    extract::{DefaultBodyLimit, Multipart, Path as UrlPath, Query, State},
     response::IntoResponse,
 };
 use axum_macros::debug_handler;
-use chrono::{DateTime, Local};
-use hyper::{Request, StatusCode, Uri};
+use chrono::{DateTime, Local, TimeDelta, Utc};
+use hyper::{
+    HeaderMap, Request, StatusCode,
+    header::{
+        CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, HeaderValue,
+        IF_NONE_MATCH, IF_RANGE, RANGE,
+    },
+};
 #[cfg(not(test))]
 use redis::{Client, JsonAsyncCommands, aio::MultiplexedConnection};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tokio::fs;
-#[cfg(not(test))]
 use tokio::sync::Mutex as AsyncMutex;
+use tokio_util::io::ReaderStream;
 use tower::ServiceExt;
 use tower_http::services::ServeFile;
-use tracing::warn;
-use tracing::{error_span, info_span, warn_span};
+use tracing::{error, info, warn};
+use tracing::{error_span, info_span};
 use ts_rs::TS;
 use utoipa::{
     ToSchema,
@@ -34,16 +40,35 @@ use utoipa::{
 use utoipa_axum::{router::OpenApiRouter, routes};
 use uuid::Uuid;
 
+use super::{
+    archive::{self, ArchiveFormat, ArchiveQuery, PackEntry},
+    blob::{self, BlobDamage},
+    pdf,
+    search::{self, SearchQuery, SearchResults},
+    upload::{
+        FileItem, FileUpload, Quota, ResumableUploads, StagedFile, StorageLeft, UploadProgress,
+    },
+    variants::{self, Variant, VariantQuery},
+};
 use crate::AppState;
 
 pub fn file_api_router() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes!(get_all_paths_tree))
         .routes(routes!(get_all_paths_list))
+        .routes(routes!(search_files))
+        .routes(routes!(update_metadata))
+        .routes(routes!(download_archive))
         .routes(routes!(delete_files))
         .routes(routes!(rename_files))
         .routes(routes!(add_files))
-        .layer(DefaultBodyLimit::max(100_000_000))
+        .routes(routes!(create_upload))
+        .routes(routes!(upload_progress, upload_chunk, cancel_upload))
+        .routes(routes!(list_trash, purge_trash))
+        .routes(routes!(restore_trashed))
+        .routes(routes!(purge_trashed))
+        // Uploads are streamed to disk and limited by the quota instead
+        .layer(DefaultBodyLimit::disable())
 }
 
 pub type Response<T> = Result<Json<T>, (StatusCode, Json<T>)>;
@@ -53,6 +78,9 @@ pub type Response<T> = Result<Json<T>, (StatusCode, Json<T>)>;
 #[ts(export, export_to = "api_bindings/files/")]
 pub enum Payload {
     FilePaths(ListView),
+    Search(SearchResults),
+    Upload(UploadProgress),
+    Trash(Vec<TrashItem>),
     Error { code: u8, message: String },
 }
 
@@ -63,6 +91,27 @@ pub struct TreeFile {
     name: String,
     size: usize,
     date: String,
+    /// Url of a small version of the image, for images only
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<String>,
+    /// Hex encoded SHA-256 of the content
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    /// Set when the scrub job found the content missing or changed on disk
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    damage: Option<BlobDamage>,
+    /// Amount of pages, for PDF documents only
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pages: Option<u32>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
 }
 
 /// Helper function to handle recursion for Vec<TreeDirectory>
@@ -93,6 +142,19 @@ impl From<&File> for TreeFile {
             name: value.name.clone(),
             size: value.size,
             date: value.date.to_rfc3339(),
+            thumbnail: value
+                .variants
+                .iter()
+                .any(|v| v.kind == variants::ImageVariant::Thumbnail)
+                .then(|| match &value.hash {
+                    Some(hash) => format!("/files{}?variant=thumbnail&v={hash}", value.path),
+                    None => format!("/files{}?variant=thumbnail", value.path),
+                }),
+            hash: value.hash.clone(),
+            damage: value.damage,
+            pages: value.pages,
+            tags: value.tags.clone(),
+            description: value.description.clone(),
         }
     }
 }
@@ -222,82 +284,93 @@ pub async fn get_all_paths_tree(State(state): State<AppState>) -> Response<TreeD
     Ok(Json(files))
 }
 
-pub async fn get_file(State(state): State<AppState>, uri: Uri) -> impl IntoResponse {
-    let file_server = state.file_server.lock().await;
-    let url_decoded_path = urlencoding::decode(&uri.to_string()).unwrap().into_owned();
-    let path = file_server.get_file(&url_decoded_path).await;
-
-    match path {
-        Some(p) => {
-            let req = Request::builder()
-                .uri(uri.clone())
-                .body(Body::empty())
-                .unwrap();
-            let f = ServeFile::new(file_server.path.join(&p));
-            f.oneshot(req)
-                .await
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")))
-        }
-        None => Err((StatusCode::NOT_FOUND, format!("{uri} not found"))),
-    }
+/// Whether the `If-None-Match` header of the request lists the ETag
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
+        })
 }
 
-/// Struct representing the multipart/form-data schema for file uploads
-#[derive(ToSchema)]
-pub struct FileUpload {
-    /// Target directory to upload files to
-    directory: String,
-    /// One or more files to upload
-    #[schema(value_type = Vec<String>, format = Binary)]
-    files: Vec<FileItem>,
-}
+/// Cache policy of urls carrying the hash of the content they were made for, which never changes
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
 
-struct FileItem {
-    pub name: String,
-    pub content: Vec<u8>,
-}
+/// Cache policy of other urls, whose content changes when a file is replaced
+const REVALIDATE: &str = "no-cache";
 
-impl FileUpload {
-    /// Parse Multipart stream into FileUpload struct
-    pub async fn from_multipart(mut multipart: Multipart) -> Result<Self, (u8, String)> {
-        let mut directory = None;
-        let mut files = Vec::new();
-
-        while let Some(field) = multipart.next_field().await.unwrap() {
-            if let Some(filename) = field.file_name() {
-                let filename = filename.to_string();
-                let bytes = match field.bytes().await {
-                    Ok(b) => b.into_iter().collect::<Vec<_>>(),
-                    Err(e) => return Err((5, e.body_text())),
-                };
-                info_span!("Add file ", filename);
-                files.push(FileItem {
-                    name: filename,
-                    content: bytes,
-                });
-            } else if let Some(name) = field.name()
-                && name == "directory"
-            {
-                let dir = field
-                    .text()
-                    .await
-                    .unwrap_or(String::new())
-                    .trim()
-                    .to_string();
-                info_span!("Got directory name", dir);
-                directory = Some(dir);
-            } else {
-                warn_span!("Unknown field", ?field);
-            }
-        }
+/// Serves the file at the path, or one of its variants picked by the query
+///
+/// Files are found without locking the FileServer and are sent with a strong ETag made from the hash of
+/// their content. Urls carrying that hash as `v` are cached for good, others are revalidated with
+/// `If-None-Match` or `If-Modified-Since`. Ranges are served, so videos can be seeked.
+pub async fn get_file(
+    State(state): State<AppState>,
+    Query(query): Query<VariantQuery>,
+    req: Request<Body>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let uri = req.uri().clone();
+    let Ok(url_decoded_path) = urlencoding::decode(uri.path()) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{uri} is not a valid path"),
+        ));
+    };
+    let Some(file) = state.files.get_file(&url_decoded_path) else {
+        return Err((StatusCode::NOT_FOUND, format!("{uri} not found")));
+    };
+    let variant = variants::pick(&file.variants, &query);
+    let etag = file.hash.as_ref().map(|hash| match variant {
+        Some(v) => format!("\"{hash}-{}\"", v.kind.suffix()),
+        None => format!("\"{hash}\""),
+    });
+    let cache_control = match (&file.hash, &query.version) {
+        (Some(hash), Some(version)) if hash == version => IMMUTABLE,
+        _ => REVALIDATE,
+    };
+    if let Some(etag) = etag
+        .as_ref()
+        .filter(|etag| etag_matches(req.headers(), etag))
+    {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(ETAG, etag.as_str()), (CACHE_CONTROL, cache_control)],
+        )
+            .into_response());
+    }
 
-        match directory {
-            Some(directory) => Ok(FileUpload { directory, files }),
-            None => Err((2, "Directory field cannot be empty".to_string())),
-        }
+    // ServeFile ignores If-Range, so a range is only sent when the client has the same content
+    let (mut parts, body) = req.into_parts();
+    let stale = parts
+        .headers
+        .remove(IF_RANGE)
+        .is_some_and(|if_range| etag.as_deref().is_none_or(|etag| if_range != etag));
+    if stale {
+        parts.headers.remove(RANGE);
+    }
+
+    let disk_name = variant.map_or(&file.file_server, |v| &v.file_server);
+    let mut response = ServeFile::new(state.files.path.join(disk_name))
+        .oneshot(Request::from_parts(parts, body))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")))?;
+    if response.status().is_client_error() || response.status().is_server_error() {
+        return Ok(response.into_response());
     }
+    let headers = response.headers_mut();
+    if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(&etag).ok()) {
+        headers.insert(ETAG, etag);
+    }
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    Ok(response.into_response())
 }
 
+/// Upload files
+///
+/// Uploaded `.zip`, `.tar.gz` and `.tgz` archives are unpacked into the directory, keeping the directories within them.
 #[utoipa::path(
     post,
     path = "/",
@@ -305,25 +378,78 @@ impl FileUpload {
     request_body(content = FileUpload, content_type = "multipart/form-data"),
     responses(
         (status = 200, description = "Files uploaded successfully", body = Payload),
-        (status = 400, description = "Bad Request (e.g. missing directory field)", body = Payload)
+        (status = 400, description = "Bad Request (e.g. missing directory field or broken archive)", body = Payload)
     )
 )]
 #[debug_handler]
 pub async fn add_files(State(state): State<AppState>, multipart: Multipart) -> Response<Payload> {
-    let mut file_server = state.file_server.lock().await;
-
-    let upload = match FileUpload::from_multipart(multipart).await {
-        Ok(u) => u,
-        Err((code, message)) => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(Payload::Error { code, message }),
-            ));
-        }
+    let upload_failed = |(code, message): (u8, String)| {
+        let status = match code {
+            6 => StatusCode::PAYLOAD_TOO_LARGE,
+            7 => StatusCode::INSUFFICIENT_STORAGE,
+            8 => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(Payload::Error { code, message }))
     };
+    // The upload is received and unpacked without holding the lock, so files are still served meanwhile
+    let (upload_dir, quota, storage) = {
+        let mut file_server = state.file_server.lock().await;
+        (
+            file_server.upload_dir(),
+            file_server.quota,
+            file_server.storage_left(),
+        )
+    };
+    // Archives are unpacked before the storage is checked, so they may not unpack to more than is free
+    let mut unpack_left = storage
+        .as_ref()
+        .map_or(archive::MAX_UNPACKED_SIZE, |storage| {
+            storage.max_size().min(archive::MAX_UNPACKED_SIZE)
+        });
+    let mut upload =
+        FileUpload::from_multipart(multipart, &upload_dir, quota.max_file_size, storage)
+            .await
+            .map_err(upload_failed)?;
+
+    let mut directories = vec![];
+    let mut files = vec![];
+    for file_item in upload.files {
+        let Some(format) = ArchiveFormat::of(&file_item.name) else {
+            files.push(file_item);
+            continue;
+        };
+        info_span!("Unpacking archive", file_item.name);
+        let dir = upload_dir.clone();
+        let unpacked = tokio::task::spawn_blocking(move || {
+            archive::unpack(
+                format,
+                file_item.content.path(),
+                &dir,
+                quota.max_file_size,
+                unpack_left,
+            )
+            .map_err(|(code, message)| (code, format!("{message} ({})", file_item.name)))
+        })
+        .await
+        .map_err(|e| upload_failed((8, e.to_string())))?
+        .map_err(upload_failed)?;
+        unpack_left -= unpacked.size;
+        directories.extend(unpacked.directories);
+        files.extend(unpacked.files);
+    }
+    upload.files = files;
+
+    let mut file_server = state.file_server.lock().await;
+    if let Err(message) = file_server.check_storage(upload.files.iter().map(|f| &f.content)) {
+        return Err((
+            StatusCode::INSUFFICIENT_STORAGE,
+            Json(Payload::Error { code: 7, message }),
+        ));
+    }
 
     // No files in request, create empty folder
-    if upload.files.is_empty() {
+    if upload.files.is_empty() && directories.is_empty() {
         info_span!("No files in request; creating dirs");
         match file_server.add_dir(&upload.directory).await {
             Ok(_) => (),
@@ -341,12 +467,18 @@ pub async fn add_files(State(state): State<AppState>, multipart: Multipart) -> R
 
     let mut errors = Vec::new();
 
-    for file_item in upload.files {
+    for dir in directories {
         if let Err(message) = file_server
-            .add_file(
-                format!("{}/{}", upload.directory, file_item.name),
-                file_item.content,
-            )
+            .add_dir(&format!("{}/{dir}/", upload.directory))
+            .await
+        {
+            errors.push(format!("{message} ({dir})"));
+        }
+    }
+    for file_item in upload.files {
+        let FileItem { name, content } = file_item;
+        if let Err((message, _)) = file_server
+            .add_staged(format!("{}/{name}", upload.directory), content)
             .await
         {
             errors.push(message);
@@ -368,6 +500,335 @@ pub async fn add_files(State(state): State<AppState>, multipart: Multipart) -> R
     }
 }
 
+/// Download a directory as an archive
+///
+/// The archive contains every file and directory below the directory. It is written to disk before it is sent,
+/// without holding the lock.
+#[utoipa::path(
+    get,
+    path = "/archive",
+    tag = "files",
+    params(ArchiveQuery),
+    responses(
+        (status = 200, description = "Archive of the directory", content(
+            (Vec<u8> = "application/zip"),
+            (Vec<u8> = "application/gzip")
+        )),
+        (status = 400, description = "Bad Request", body = Payload),
+        (status = 404, description = "Directory does not exist", body = Payload)
+    )
+)]
+pub async fn download_archive(
+    State(state): State<AppState>,
+    Query(query): Query<ArchiveQuery>,
+) -> Result<axum::response::Response, (StatusCode, Json<Payload>)> {
+    let error = |status, code, message| (status, Json(Payload::Error { code, message }));
+    let (name, entries) = state
+        .files
+        .archive_entries(&query.path)
+        .map_err(|message| error(StatusCode::BAD_REQUEST, 2, message))?
+        .ok_or_else(|| {
+            error(
+                StatusCode::NOT_FOUND,
+                1,
+                format!("Directory {} does not exist", query.path),
+            )
+        })?;
+
+    let archive_path = state
+        .files
+        .path
+        .join(UPLOAD_DIR)
+        .join(Uuid::new_v4().to_string());
+    let to = archive_path.clone();
+    let packed =
+        tokio::task::spawn_blocking(move || archive::pack(query.format, &entries, &to)).await;
+    let archive = match packed {
+        Ok(Ok(())) => fs::File::open(&archive_path).await,
+        Ok(Err(e)) => Err(e),
+        Err(e) => Err(std::io::Error::other(e)),
+    };
+    // An open file is still read after it was removed, so nothing is left behind once it is sent
+    let _ = fs::remove_file(&archive_path).await;
+    let archive = archive.map_err(|e| {
+        error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            8,
+            format!("Could not pack archive ({e})"),
+        )
+    })?;
+    let size = archive
+        .metadata()
+        .await
+        .map(|m| m.len())
+        .unwrap_or_default();
+
+    Ok((
+        [
+            (CONTENT_TYPE, query.format.content_type().to_string()),
+            (CONTENT_LENGTH, size.to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{name}.{}\"",
+                    query.format.extension()
+                ),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(archive)),
+    )
+        .into_response())
+}
+
+/// Search files
+///
+/// Every given filter has to match. Results are ordered by path and split into pages.
+#[utoipa::path(
+    get,
+    path = "/search",
+    tag = "files",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Page of the files found", body = Payload),
+        (status = 400, description = "Bad Request", body = Payload)
+    )
+)]
+pub async fn search_files(
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> Response<Payload> {
+    match state.files.search(&query) {
+        Ok(results) => Ok(Json(Payload::Search(results))),
+        Err(message) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(Payload::Error { code: 1, message }),
+        )),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/files/")]
+pub struct UpdateMetadataRequest {
+    /// Path of the file
+    id: String,
+    /// Replaces the tags of the file
+    tags: Vec<String>,
+    /// Replaces the description of the file, removing it if not given
+    #[ts(optional)]
+    description: Option<String>,
+}
+
+/// Set the tags and description of a file
+#[utoipa::path(
+    put,
+    path = "/metadata",
+    tag = "files",
+    request_body = UpdateMetadataRequest,
+    responses(
+        (status = 200, description = "Tags and description updated", body = Payload),
+        (status = 400, description = "Bad Request", body = Payload)
+    )
+)]
+#[debug_handler]
+pub async fn update_metadata(
+    State(state): State<AppState>,
+    Json(request): Json<UpdateMetadataRequest>,
+) -> Response<Payload> {
+    info_span!("Updating metadata", ?request);
+    let tags = search::normalize_tags(request.tags).map_err(|message| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(Payload::Error { code: 1, message }),
+        )
+    })?;
+    let description = request
+        .description
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
+
+    let mut file_server = state.file_server.lock().await;
+    if let Err(message) = file_server.set_metadata(&request.id, tags, description) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(Payload::Error { code: 2, message }),
+        ));
+    }
+    file_server.write().await;
+    Ok(Json(Payload::FilePaths(ListView(vec![]))))
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/files/")]
+pub struct CreateUploadRequest {
+    /// Path of the file once uploaded
+    path: String,
+    /// Size of the file in bytes
+    size: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UploadOffset {
+    offset: u64,
+}
+
+/// Response to a failed request of a resumable upload, with a status code fitting the error
+fn upload_error((code, message): (u8, String)) -> (StatusCode, Json<Payload>) {
+    let status = match code {
+        1 => StatusCode::NOT_FOUND,
+        3 => StatusCode::CONFLICT,
+        6 => StatusCode::PAYLOAD_TOO_LARGE,
+        7 => StatusCode::INSUFFICIENT_STORAGE,
+        8 => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, Json(Payload::Error { code, message }))
+}
+
+/// Start a resumable upload
+///
+/// The file is sent in chunks with `PATCH /uploads/{id}`, each continuing at the offset of the upload.
+/// After a lost connection, the offset to continue from is read with `GET /uploads/{id}`.
+#[utoipa::path(
+    post,
+    path = "/uploads",
+    tag = "files",
+    request_body = CreateUploadRequest,
+    responses(
+        (status = 200, description = "Upload started", body = Payload),
+        (status = 400, description = "Bad Request (e.g. file already exists)", body = Payload),
+        (status = 413, description = "File is larger than allowed", body = Payload),
+        (status = 507, description = "Not enough storage left for the file", body = Payload)
+    )
+)]
+#[debug_handler]
+pub async fn create_upload(
+    State(state): State<AppState>,
+    Json(request): Json<CreateUploadRequest>,
+) -> Response<Payload> {
+    let (path, uploads, quota, storage) = {
+        let mut file_server = state.file_server.lock().await;
+        let path = VirtualPath::parse(&request.path, true)
+            .map_err(|message| upload_error((2, message)))?
+            .to_string_path();
+        if file_server.get_file(&path).await.is_some() {
+            return Err(upload_error((2, format!("File {path} already exists"))));
+        }
+        (
+            path,
+            file_server.uploads.clone(),
+            file_server.quota,
+            file_server.storage_left(),
+        )
+    };
+    info_span!("Starting upload", path);
+    let progress = uploads
+        .create(path, request.size, quota.max_file_size, storage)
+        .await
+        .map_err(upload_error)?;
+    Ok(Json(Payload::Upload(progress)))
+}
+
+/// Get how much of a resumable upload was received
+#[utoipa::path(
+    get,
+    path = "/uploads/{id}",
+    tag = "files",
+    params(
+        ("id" = Uuid, Path, description = "Id of the upload")
+    ),
+    responses(
+        (status = 200, description = "Progress of the upload", body = Payload),
+        (status = 404, description = "No such upload", body = Payload)
+    )
+)]
+pub async fn upload_progress(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Response<Payload> {
+    let uploads = state.file_server.lock().await.uploads.clone();
+    let progress = uploads.progress(id).await.map_err(upload_error)?;
+    Ok(Json(Payload::Upload(progress)))
+}
+
+/// Send the next chunk of a resumable upload
+///
+/// The file is added once all of it was received. A chunk cut off by a lost connection is kept
+/// up to where it was cut off.
+#[utoipa::path(
+    patch,
+    path = "/uploads/{id}",
+    tag = "files",
+    request_body(content = Vec<u8>, description = "Bytes of the file from the offset", content_type = "application/octet-stream"),
+    params(
+        ("id" = Uuid, Path, description = "Id of the upload"),
+        ("offset" = u64, Query, description = "Offset of the chunk, which must be the offset of the upload")
+    ),
+    responses(
+        (status = 200, description = "Chunk received, the file is added once the offset reaches the size", body = Payload),
+        (status = 404, description = "No such upload", body = Payload),
+        (status = 409, description = "Chunk does not continue at the offset of the upload", body = Payload),
+        (status = 507, description = "Not enough storage left for the file", body = Payload)
+    )
+)]
+pub async fn upload_chunk(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+    Query(UploadOffset { offset }): Query<UploadOffset>,
+    body: Body,
+) -> Response<Payload> {
+    // Chunks are received without holding the lock, so files are still served meanwhile
+    let uploads = state.file_server.lock().await.uploads.clone();
+    let progress = uploads
+        .append(id, offset, body.into_data_stream())
+        .await
+        .map_err(upload_error)?;
+    if progress.offset < progress.size {
+        return Ok(Json(Payload::Upload(progress)));
+    }
+
+    let (path, content) = uploads.finish(id).await.map_err(upload_error)?;
+    let mut file_server = state.file_server.lock().await;
+    // Checked before adding the file, so the upload is kept to be finished once the problem is solved
+    let checked = match file_server.get_file(&path).await {
+        Some(_) => Err((2, format!("File {path} already exists"))),
+        None => file_server
+            .check_storage([&content])
+            .map_err(|message| (7, message)),
+    };
+    if let Err(e) = checked {
+        uploads.restore(id, path, content).await;
+        return Err(upload_error(e));
+    }
+    if let Err((message, content)) = file_server.add_staged(path.clone(), content).await {
+        uploads.restore(id, path, content).await;
+        return Err(upload_error((9, message)));
+    }
+    file_server.write().await;
+    Ok(Json(Payload::Upload(progress)))
+}
+
+/// Cancel a resumable upload, deleting what was received
+#[utoipa::path(
+    delete,
+    path = "/uploads/{id}",
+    tag = "files",
+    params(
+        ("id" = Uuid, Path, description = "Id of the upload")
+    ),
+    responses(
+        (status = 200, description = "Upload cancelled", body = Payload),
+        (status = 404, description = "No such upload", body = Payload)
+    )
+)]
+pub async fn cancel_upload(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Response<Payload> {
+    let uploads = state.file_server.lock().await.uploads.clone();
+    uploads.cancel(id).await.map_err(upload_error)?;
+    Ok(Json(Payload::FilePaths(ListView(vec![]))))
+}
+
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct RenameRequest {
     ids_from: Vec<String>,
@@ -504,17 +965,159 @@ pub async fn delete_files(
     }
 }
 
+/// Item in the trash, restored or purged by its id
+#[derive(Serialize, Deserialize, Debug, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/files/")]
+pub struct TrashItem {
+    #[ts(type = "string")]
+    id: Uuid,
+    /// Path the item was deleted from, directories end with a `'/'`
+    path: String,
+    r#type: ListViewItemType,
+    /// Sum of the sizes of the files in the item
+    size: usize,
+    deleted: String,
+    /// When the item is purged by the sweeper
+    expires: String,
+}
+
+/// Response to a failed request on the trash, with a status code fitting the error
+fn trash_error((code, message): (u8, String)) -> (StatusCode, Json<Payload>) {
+    let status = match code {
+        1 => StatusCode::NOT_FOUND,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, Json(Payload::Error { code, message }))
+}
+
+/// List deleted files and directories
+///
+/// Deleted items are kept in the trash until they are restored, purged, or their retention expired.
+#[utoipa::path(
+    get,
+    path = "/trash",
+    tag = "files",
+    responses(
+        (status = 200, description = "Items in the trash, most recently deleted first", body = Payload)
+    )
+)]
+pub async fn list_trash(State(state): State<AppState>) -> Response<Payload> {
+    let trash = state.file_server.lock().await.list_trash();
+    Ok(Json(Payload::Trash(trash)))
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/files/")]
+pub struct RestoreRequest {
+    /// Path to restore the item to instead of where it was deleted from, directories end with a `'/'`
+    #[ts(optional)]
+    path: Option<String>,
+}
+
+/// Restore an item from the trash
+#[utoipa::path(
+    post,
+    path = "/trash/{id}/restore",
+    tag = "files",
+    request_body = RestoreRequest,
+    params(
+        ("id" = Uuid, Path, description = "Id of the item in the trash")
+    ),
+    responses(
+        (status = 200, description = "Item restored", body = Payload),
+        (status = 400, description = "Bad Request (e.g. path already exists)", body = Payload),
+        (status = 404, description = "No such item in the trash", body = Payload)
+    )
+)]
+#[debug_handler]
+pub async fn restore_trashed(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+    Json(request): Json<RestoreRequest>,
+) -> Response<Payload> {
+    info_span!("Restoring from trash", ?id, ?request);
+    let mut file_server = state.file_server.lock().await;
+    file_server.restore(id, request.path).map_err(trash_error)?;
+    file_server.write().await;
+    Ok(Json(Payload::FilePaths(ListView(vec![]))))
+}
+
+/// Purge an item from the trash, deleting it irreversibly
+#[utoipa::path(
+    delete,
+    path = "/trash/{id}",
+    tag = "files",
+    params(
+        ("id" = Uuid, Path, description = "Id of the item in the trash")
+    ),
+    responses(
+        (status = 200, description = "Item purged", body = Payload),
+        (status = 404, description = "No such item in the trash", body = Payload)
+    )
+)]
+pub async fn purge_trashed(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Response<Payload> {
+    info_span!("Purging from trash", ?id);
+    let mut file_server = state.file_server.lock().await;
+    file_server.purge(id).await.map_err(trash_error)?;
+    file_server.write().await;
+    Ok(Json(Payload::FilePaths(ListView(vec![]))))
+}
+
+/// Empty the trash, deleting every item in it irreversibly
+#[utoipa::path(
+    delete,
+    path = "/trash",
+    tag = "files",
+    responses(
+        (status = 200, description = "Trash emptied", body = Payload)
+    )
+)]
+pub async fn purge_trash(State(state): State<AppState>) -> Response<Payload> {
+    info_span!("Emptying trash");
+    let mut file_server = state.file_server.lock().await;
+    file_server.purge_all().await;
+    file_server.write().await;
+    Ok(Json(Payload::FilePaths(ListView(vec![]))))
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct File {
     name: String,
-    /// Actual filename on disk
+    /// Actual filename on disk, shared by every file with the same content
     ///
-    /// `{UUID}.{ext}`
+    /// `{SHA-256}.{ext}`, or `{UUID}.{ext}` for files uploaded before blobs were hashed
     file_server: String,
     /// File path through built file tree
     path: String,
     size: usize,
     date: DateTime<Local>,
+    /// Hex encoded SHA-256 of the content, filled in by the scrub job for files uploaded before blobs were hashed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    /// Set by the scrub job when the blob is missing or no longer matches the hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    damage: Option<BlobDamage>,
+    /// Smaller copies of images, see `variants::encode`
+    #[serde(default)]
+    variants: Vec<Variant>,
+    /// Amount of pages of PDF documents, read when uploaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pages: Option<u32>,
+    /// Set by users to find the file again, see `search::normalize_tags`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+impl File {
+    /// Names of the file and its variants on disk
+    fn disk_names(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.file_server).chain(self.variants.iter().map(|v| &v.file_server))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -525,23 +1128,150 @@ pub struct Directory {
     children: Arc<Mutex<Vec<Directory>>>,
 }
 
-#[derive(Deserialize, Serialize)]
-struct DesDir {
-    name: String,
-    path: String,
-    files: Vec<File>,
-    children: Vec<Directory>,
-}
-
-impl<'de> Deserialize<'de> for Directory {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let input = DesDir::deserialize(deserializer)?;
-        Ok(Self {
-            name: input.name,
-            path: input.path,
+impl Directory {
+    /// Traverse through tree until path. Returns None if path does not exist
+    fn traverse(&self, path: &[&str]) -> Option<Directory> {
+        let mut dir = self.clone();
+        for p in path {
+            let dir_c = dir.clone();
+            let d = dir_c.children.lock().unwrap();
+            let pos = match d.binary_search_by_key(p, |d| &d.name) {
+                // Update dir to dir and traverse down the tree
+                Ok(pos) => pos,
+                Err(_) => return None,
+            };
+            dir = d.get(pos).unwrap().clone();
+        }
+        Some(dir)
+    }
+}
+
+/// Handle to the file tree, finding files without locking the FileServer
+///
+/// Downloads and searches use it, so they are served while the FileServer is busy, for example making variants of an upload.
+#[derive(Clone)]
+pub struct FileIndex {
+    root: Directory,
+    path: PathBuf,
+}
+
+impl FileIndex {
+    /// Name of the directory and the entries of an archive of it, `None` if it does not exist
+    fn archive_entries(&self, dir_path: &str) -> Result<Option<(String, Vec<PackEntry>)>, String> {
+        let path = VirtualPath::parse(dir_path, false)?;
+        let Some(dir) = self.root.traverse(path.path()) else {
+            return Ok(None);
+        };
+        let name = match path {
+            VirtualPath::Root => String::from("files"),
+            _ => dir.name.clone(),
+        };
+        let relative = |p: &str| p[dir.path.len()..].trim_start_matches('/').to_string();
+
+        let mut entries = vec![];
+        let mut stack = VecDeque::from([dir.clone()]);
+        while let Some(d) = stack.pop_front() {
+            for f in d.files.lock().unwrap().iter() {
+                entries.push(PackEntry::File(
+                    relative(&f.path),
+                    self.path.join(&f.file_server),
+                ));
+            }
+            for child in d.children.lock().unwrap().iter() {
+                entries.push(PackEntry::Directory(relative(&child.path)));
+                stack.push_back(child.clone());
+            }
+        }
+        Ok(Some((name, entries)))
+    }
+
+    /// Page of the files found by the query, ordered by path
+    pub fn search(&self, query: &SearchQuery) -> Result<SearchResults, String> {
+        let per_page = query.per_page()?;
+        let mut found = vec![];
+        FileServer::for_each_file(&self.root, |f| {
+            if query.matches(&f.name, f.size, &f.date, &f.tags) {
+                found.push(TreeFile::from(&*f));
+            }
+        });
+        found.sort_by(|a, b| a.id.cmp(&b.id));
+        let total = found.len();
+        Ok(SearchResults {
+            files: found
+                .into_iter()
+                .skip(query.page().saturating_mul(per_page))
+                .take(per_page)
+                .collect(),
+            total,
+            page: query.page(),
+            per_page,
+        })
+    }
+
+    /// Hash of the content of the file, sent as `v` in urls so they are cached for good
+    pub fn version(&self, file_path: &str) -> Option<String> {
+        self.get_file(file_path)?.hash
+    }
+
+    fn get_file(&self, file_path: &str) -> Option<File> {
+        let path = VirtualPath::parse(file_path, true).ok()?;
+        let dir = self.root.traverse(path.parents())?;
+        let files = dir.files.lock().unwrap();
+        match files.binary_search_by_key(&path.name(), |f| &f.name) {
+            Ok(pos) => Some(files[pos].clone()),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Deleted file or directory, keeping its blobs until it is purged
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TrashEntry {
+    id: Uuid,
+    /// Path the item was deleted from, directories end with a `'/'`
+    path: String,
+    deleted: DateTime<Local>,
+    item: Trashed,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", content = "content")]
+enum Trashed {
+    File(File),
+    Directory(Directory),
+}
+
+impl TrashEntry {
+    /// Calls `f` with every file in the entry
+    fn for_each_file(&mut self, mut f: impl FnMut(&mut File)) {
+        match &mut self.item {
+            Trashed::File(file) => f(file),
+            Trashed::Directory(dir) => FileServer::for_each_file(dir, f),
+        }
+    }
+
+    fn expires(&self, retention: TimeDelta) -> DateTime<Local> {
+        self.deleted + retention
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct DesDir {
+    name: String,
+    path: String,
+    files: Vec<File>,
+    children: Vec<Directory>,
+}
+
+impl<'de> Deserialize<'de> for Directory {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let input = DesDir::deserialize(deserializer)?;
+        Ok(Self {
+            name: input.name,
+            path: input.path,
             files: Arc::new(Mutex::new(input.files)),
             children: Arc::new(Mutex::new(input.children)),
         })
@@ -570,8 +1300,31 @@ pub struct FileServer {
     con: AsyncMutex<MultiplexedConnection>,
     root: Directory,
     path: PathBuf,
+    /// Amount of files in the tree using each blob, blobs are deleted from disk once unused
+    refs: HashMap<String, usize>,
+    quota: Quota,
+    uploads: Arc<ResumableUploads>,
+    /// Deleted files and directories, most recently deleted last
+    trash: Vec<TrashEntry>,
+    /// How long deleted items are kept before the sweeper purges them
+    trash_retention: TimeDelta,
 }
 
+/// Directory within the file path uploads are written to, so they can be moved into place atomically
+const UPLOAD_DIR: &str = ".uploads";
+
+/// Directory within the file path resumable uploads are written to, kept when Sasta restarts
+const PARTIAL_DIR: &str = ".partial";
+
+/// How often the blobs on disk are checked against their hashes
+const SCRUB_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often items past their retention are purged from the trash
+const TRASH_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often abandoned resumable uploads are cancelled
+const UPLOAD_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 pub static FILE_PATH_REGEX: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"^/[\w/_\- ]*(\w+\.\w+)$").unwrap());
 
@@ -647,9 +1400,15 @@ impl<'a> VirtualPath<'a> {
 
 impl FileServer {
     #[cfg(not(test))]
-    pub async fn new(redis_url: &str, path: impl Into<PathBuf>) -> Self {
+    pub async fn new(
+        redis_url: &str,
+        path: impl Into<PathBuf>,
+        quota: Quota,
+        trash_retention: TimeDelta,
+    ) -> Self {
         let path = path.into();
         fs::create_dir_all(&path).await.unwrap();
+        Self::clear_upload_dir(&path).await;
         let client = Client::open(redis_url).unwrap();
         let mut con = client.get_multiplexed_tokio_connection().await.unwrap();
 
@@ -669,17 +1428,36 @@ impl FileServer {
             }
         };
 
+        // Nothing was deleted yet when the trash was never written
+        let mut trash = match con.json_get::<_, _, String>("trash", ".").await {
+            Ok(str) => serde_json::from_str(&str).unwrap(),
+            Err(_) => vec![],
+        };
+
+        let refs = Self::count_refs(&root, &mut trash);
+        let uploads = ResumableUploads::new(con.clone(), path.join(PARTIAL_DIR)).await;
         Self {
             con: AsyncMutex::new(con),
             root,
             path,
+            refs,
+            quota,
+            uploads: Arc::new(uploads),
+            trash,
+            trash_retention,
         }
     }
 
     #[cfg(test)]
-    pub async fn new(_redis_url: &str, path: impl Into<PathBuf>) -> Self {
+    pub async fn new(
+        _redis_url: &str,
+        path: impl Into<PathBuf>,
+        quota: Quota,
+        trash_retention: TimeDelta,
+    ) -> Self {
         let path = path.into();
         fs::create_dir_all(&path).await.unwrap();
+        Self::clear_upload_dir(&path).await;
 
         // Return a fresh, empty in-memory tree for every test
         Self {
@@ -689,7 +1467,76 @@ impl FileServer {
                 files: Arc::new(Mutex::new(vec![])),
                 children: Arc::new(Mutex::new(vec![])),
             },
+            refs: HashMap::new(),
+            quota,
+            uploads: Arc::new(ResumableUploads::new(path.join(PARTIAL_DIR)).await),
             path,
+            trash: vec![],
+            trash_retention,
+        }
+    }
+
+    /// Removes uploads left behind when Sasta stopped while receiving them
+    async fn clear_upload_dir(path: &Path) {
+        let upload_dir = path.join(UPLOAD_DIR);
+        match fs::remove_dir_all(&upload_dir).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                warn!("Could not clear {} ({e})", upload_dir.display())
+            }
+            _ => (),
+        }
+        fs::create_dir_all(&upload_dir).await.unwrap();
+    }
+
+    /// Directory uploads are written to before they are moved into place
+    pub fn upload_dir(&self) -> PathBuf {
+        self.path.join(UPLOAD_DIR)
+    }
+
+    /// Size of the stored content by hash, counting identical content once
+    ///
+    /// Files in the trash still take up storage until they are purged.
+    fn stored_blobs(&mut self) -> HashMap<String, u64> {
+        let mut blobs = HashMap::new();
+        Self::for_each_stored_file(&self.root, &mut self.trash, |f| {
+            let key = f.hash.clone().unwrap_or_else(|| f.file_server.clone());
+            blobs.insert(key, f.size as u64);
+        });
+        blobs
+    }
+
+    /// Storage left within the quota for new uploads, None if storage is unlimited
+    pub fn storage_left(&mut self) -> Option<StorageLeft> {
+        let max_storage = self.quota.max_storage?;
+        let blobs = self.stored_blobs();
+        let used = blobs.values().sum::<u64>();
+        Some(StorageLeft::new(max_storage.saturating_sub(used), blobs))
+    }
+
+    /// Checks that storing the uploads stays within the storage quota, counting identical content once
+    ///
+    /// Files in the trash still take up storage until they are purged.
+    pub fn check_storage<'a>(
+        &mut self,
+        uploads: impl IntoIterator<Item = &'a StagedFile>,
+    ) -> Result<(), String> {
+        let Some(max_storage) = self.quota.max_storage else {
+            return Ok(());
+        };
+        let mut blobs = self.stored_blobs();
+        let used = blobs.values().sum::<u64>();
+        for upload in uploads {
+            blobs.insert(upload.hash().to_string(), upload.size());
+        }
+        let needed = blobs.values().sum::<u64>();
+        if needed > max_storage {
+            Err(format!(
+                "Storing the files needs {} bytes while {} of {max_storage} bytes are free",
+                needed - used,
+                max_storage.saturating_sub(used)
+            ))
+        } else {
+            Ok(())
         }
     }
 
@@ -701,59 +1548,201 @@ impl FileServer {
         (&self.root).into()
     }
 
-    /// Add file name to directory tree, and create folder if they don't already exists
+    /// Adds the content as a file, see `add_staged`
+    #[cfg(test)]
+    pub async fn add_file(&mut self, file_path: String, content: Vec<u8>) -> Result<File, String> {
+        let chunks =
+            futures_util::stream::iter([Ok::<_, String>(axum::body::Bytes::from(content))]);
+        let content = StagedFile::write(&self.upload_dir(), chunks, None)
+            .await
+            .map_err(|(_, message)| message)?;
+        self.add_staged(file_path, content)
+            .await
+            .map_err(|(message, _)| message)
+    }
+
+    /// Adds the uploaded content as a file at the path, moving it into place unless identical content is stored
     ///
+    /// Creates the directories up to the file if they don't already exist.
+    /// Variants are made of images, an image which cannot be read is still added without them.
     /// Does not call write to avoid writing when not all files are returning Ok()
-    pub async fn add_file(&mut self, file_path: String, content: Vec<u8>) -> Result<File, String> {
+    /// The content is given back if it could not be added.
+    pub async fn add_staged(
+        &mut self,
+        file_path: String,
+        content: StagedFile,
+    ) -> Result<File, (String, StagedFile)> {
         info_span!("Adding file with ", file_path);
-        let path = VirtualPath::parse(&file_path, true)?;
+        let path = match VirtualPath::parse(&file_path, true) {
+            Ok(path) => path,
+            Err(message) => return Err((message, content)),
+        };
 
         let dir = self.create_up_to_dir(path.parents());
 
+        if dir
+            .files
+            .lock()
+            .unwrap()
+            .binary_search_by_key(&path.name(), |f| &f.name)
+            .is_ok()
+        {
+            return Err((
+                format!("File {} already exists", path.to_string_path()),
+                content,
+            ));
+        }
+
+        let hash = content.hash().to_string();
+        let extension = Path::new(path.name())
+            .extension()
+            .unwrap_or(&OsString::from("txt"))
+            .to_str()
+            .unwrap()
+            .to_string();
+        // The extension is kept, so the blob is served with the right content type
+        let mut file = File {
+            name: path.name().to_string(),
+            file_server: format!("{hash}.{extension}"),
+            path: path.to_string_path(),
+            size: content.size() as usize,
+            date: Local::now(),
+            hash: Some(hash.clone()),
+            damage: None,
+            variants: vec![],
+            pages: None,
+            tags: vec![],
+            description: None,
+        };
+
+        // Identical content is stored once, so what was made from it before is reused
+        let mut stored = None;
+        if self.refs.contains_key(&file.file_server) {
+            Self::for_each_stored_file(&self.root, &mut self.trash, |f| {
+                if stored.is_none() && f.file_server == file.file_server {
+                    stored = Some(f.clone());
+                }
+            });
+        }
+        if let Some(stored) = stored.filter(|f| f.damage.is_none()) {
+            file.variants = stored.variants;
+            file.pages = stored.pages;
+        } else {
+            self.store_blob(&mut file, &extension, content).await?;
+        }
+        *self.refs.entry(file.file_server.clone()).or_default() += 1;
+
         let mut files = dir.files.lock().unwrap();
-        match files.binary_search_by_key(&path.name(), |f| &f.name) {
-            Ok(_) => Err(format!("File {} already exists", path.to_string_path())),
-            Err(pos) => {
-                let file = File {
-                    name: path.name().to_string(),
-                    file_server: format!(
-                        "{}.{}",
-                        Uuid::new_v4(),
-                        Path::new(path.name())
-                            .extension()
-                            .unwrap_or(&OsString::from("txt"))
-                            .to_str()
-                            .unwrap()
-                    ),
-                    path: path.to_string_path(),
-                    size: content.len(),
-                    date: Local::now(),
-                };
+        let pos = files
+            .binary_search_by_key(&path.name(), |f| &f.name)
+            .unwrap_err();
+        files.insert(pos, file.clone());
+        Ok(file)
+    }
 
-                match std::fs::File::create(self.path.join(&file.file_server)) {
-                    Ok(mut file) => {
-                        if let Err(e) = file.write_all(&content) {
-                            return Err(e.to_string());
+    /// Writes the content to the blob of the file, together with what is made from it
+    ///
+    /// A damaged blob is written again, repairing it for every file using it.
+    async fn store_blob(
+        &mut self,
+        file: &mut File,
+        extension: &str,
+        content: StagedFile,
+    ) -> Result<(), (String, StagedFile)> {
+        let hash = file.hash.clone().unwrap_or_default();
+        let blob_path = self.path.join(&file.file_server);
+        content
+            .persist(&blob_path)
+            .await
+            .map_err(|(e, content)| (e.to_string(), content))?;
+        Self::for_each_stored_file(&self.root, &mut self.trash, |f| {
+            if f.file_server == file.file_server {
+                f.damage = None;
+            }
+        });
+
+        if !variants::is_image(extension) && !pdf::is_pdf(extension) {
+            return Ok(());
+        }
+        let content = match fs::read(&blob_path).await {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Could not read {} back ({e})", file.path);
+                return Ok(());
+            }
+        };
+        if variants::is_image(extension) {
+            match tokio::task::spawn_blocking(move || variants::encode(&content)).await {
+                Ok(Ok(encoded)) => {
+                    for variant in encoded {
+                        let file_server = format!(
+                            "{hash}-{extension}-{}.{}",
+                            variant.kind.suffix(),
+                            variant.extension
+                        );
+                        if let Err(e) =
+                            fs::write(self.path.join(&file_server), variant.content).await
+                        {
+                            warn!("Could not write {file_server} ({e})");
+                            continue;
                         }
+                        file.variants.push(Variant {
+                            kind: variant.kind,
+                            file_server,
+                            width: variant.width,
+                            height: variant.height,
+                        });
                     }
-                    Err(e) => return Err(e.to_string()),
-                };
+                }
+                Ok(Err(e)) => warn!("Could not make variants of {} ({e})", file.path),
+                Err(e) => warn!("Could not make variants of {} ({e})", file.path),
+            }
+        } else if pdf::is_pdf(extension) {
+            match tokio::task::spawn_blocking(move || pdf::page_count(&content)).await {
+                Ok(Ok(pages)) => file.pages = Some(pages),
+                Ok(Err(e)) => warn!("Could not count the pages of {} ({e})", file.path),
+                Err(e) => warn!("Could not count the pages of {} ({e})", file.path),
+            }
+        }
+        Ok(())
+    }
 
-                files.insert(pos, file.clone());
-                Ok(file)
+    /// Replaces the tags and description of the file at the path
+    pub fn set_metadata(
+        &mut self,
+        file_path: &str,
+        tags: Vec<String>,
+        description: Option<String>,
+    ) -> Result<File, String> {
+        let path = VirtualPath::parse(file_path, true)?;
+        let dir = self
+            .traverse_to_dir(path.parents())
+            .ok_or_else(|| String::from("Directory does not exist"))?;
+        let mut files = dir.files.lock().unwrap();
+        match files.binary_search_by_key(&path.name(), |f| &f.name) {
+            Ok(pos) => {
+                files[pos].tags = tags;
+                files[pos].description = description;
+                Ok(files[pos].clone())
             }
+            Err(_) => Err(format!("File {} does not exist", path.to_string_path())),
         }
     }
 
-    async fn get_file(&self, file_path: &String) -> Option<String> {
-        let path = VirtualPath::parse(file_path, true).ok()?;
+    /// Amount of pages of the PDF document at the path, if it is known
+    pub async fn page_count(&self, file_path: &str) -> Option<u32> {
+        self.get_file(file_path).await?.pages
+    }
 
-        let dir = self.traverse_to_dir(path.parents())?;
+    async fn get_file(&self, file_path: &str) -> Option<File> {
+        self.index().get_file(file_path)
+    }
 
-        let files = dir.files.lock().unwrap();
-        match files.binary_search_by_key(&path.name(), |f| &f.name) {
-            Ok(pos) => Some(files[pos].file_server.clone()),
-            Err(_) => None,
+    /// Handle to find files without locking the FileServer
+    pub fn index(&self) -> FileIndex {
+        FileIndex {
+            root: self.root.clone(),
+            path: self.path.clone(),
         }
     }
 
@@ -835,10 +1824,34 @@ impl FileServer {
             }
         };
 
-        match fs::remove_file(self.path.join(&file.file_server)).await {
-            Ok(_) => Ok(file),
-            Err(e) => Err(e.to_string()),
+        self.trash.push(TrashEntry {
+            id: Uuid::new_v4(),
+            path: file.path.clone(),
+            deleted: Local::now(),
+            item: Trashed::File(file.clone()),
+        });
+        Ok(file)
+    }
+
+    /// Drops the reference of the file to its blob, deleting the blob and its variants once unused
+    async fn release(&mut self, file: &File) -> Result<(), String> {
+        match self.refs.get_mut(&file.file_server) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                return Ok(());
+            }
+            _ => self.refs.remove(&file.file_server),
+        };
+
+        let mut result = Ok(());
+        for name in file.disk_names() {
+            match fs::remove_file(self.path.join(name)).await {
+                Ok(_) => (),
+                Err(e) if *name == file.file_server => result = Err(e.to_string()),
+                Err(e) => warn!("Error deleting variant {:?}", e),
+            }
         }
+        result
     }
 
     pub async fn add_dir(&mut self, dir_path: &String) -> Result<(), String> {
@@ -983,19 +1996,245 @@ impl FileServer {
             }
         };
 
+        self.trash.push(TrashEntry {
+            id: Uuid::new_v4(),
+            path: format!("{}/", dir.path),
+            deleted: Local::now(),
+            item: Trashed::Directory(dir.clone()),
+        });
+        Ok(dir)
+    }
+
+    /// Items in the trash, most recently deleted first
+    pub fn list_trash(&mut self) -> Vec<TrashItem> {
+        let retention = self.trash_retention;
+        self.trash
+            .iter_mut()
+            .rev()
+            .map(|entry| {
+                let mut size = 0;
+                entry.for_each_file(|f| size += f.size);
+                TrashItem {
+                    id: entry.id,
+                    path: entry.path.clone(),
+                    r#type: match entry.item {
+                        Trashed::File(_) => ListViewItemType::File,
+                        Trashed::Directory(_) => ListViewItemType::Directory,
+                    },
+                    size,
+                    deleted: entry.deleted.to_rfc3339(),
+                    expires: entry.expires(retention).to_rfc3339(),
+                }
+            })
+            .collect()
+    }
+
+    /// Moves the item back into the tree, where it was deleted from unless another path is given
+    ///
+    /// Missing parent directories are created. Fails if the path is taken.
+    pub fn restore(&mut self, id: Uuid, to: Option<String>) -> Result<String, (u8, String)> {
+        let Some(pos) = self.trash.iter().position(|e| e.id == id) else {
+            return Err((1, format!("There is no item {id} in the trash")));
+        };
+        let entry = &self.trash[pos];
+        let to = to.unwrap_or_else(|| entry.path.clone());
+
+        let restored = match &entry.item {
+            Trashed::File(file) => {
+                let path = VirtualPath::parse(&to, true).map_err(|e| (2, e))?;
+                let dir = self.create_up_to_dir(path.parents());
+                let mut files = dir.files.lock().unwrap();
+                let pos = match files.binary_search_by_key(&path.name(), |f| &f.name) {
+                    Ok(_) => {
+                        return Err((2, format!("File {} already exists", path.to_string_path())));
+                    }
+                    Err(pos) => pos,
+                };
+                let mut file = file.clone();
+                file.name = path.name().to_string();
+                file.path = path.to_string_path();
+                files.insert(pos, file);
+                path.to_string_path()
+            }
+            Trashed::Directory(dir) => {
+                let path = VirtualPath::parse(&to, false).map_err(|e| (2, e))?;
+                if path.path().is_empty() {
+                    return Err((2, String::from("Cannot restore to the root folder")));
+                }
+                let parent_dir = self.create_up_to_dir(path.parents());
+                let mut dirs = parent_dir.children.lock().unwrap();
+                let pos = match dirs.binary_search_by_key(&path.name(), |d| &d.name) {
+                    Ok(_) => {
+                        return Err((
+                            2,
+                            format!("Directory {} already exists", path.to_string_path()),
+                        ));
+                    }
+                    Err(pos) => pos,
+                };
+                let mut dir = dir.clone();
+                dir.name = path.name().to_string();
+                dir.path = path.to_string_path();
+                Self::recursively_update_path(&dir);
+                dirs.insert(pos, dir);
+                format!("{}/", path.to_string_path())
+            }
+        };
+        self.trash.remove(pos);
+        Ok(restored)
+    }
+
+    /// Removes the item from the trash, deleting blobs no longer used by any file
+    pub async fn purge(&mut self, id: Uuid) -> Result<(), (u8, String)> {
+        let Some(pos) = self.trash.iter().position(|e| e.id == id) else {
+            return Err((1, format!("There is no item {id} in the trash")));
+        };
+        let mut entry = self.trash.remove(pos);
         let mut files = vec![];
+        entry.for_each_file(|f| files.push(f.clone()));
+        for file in &files {
+            if let Err(e) = self.release(file).await {
+                warn!("Error deleting file {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn purge_all(&mut self) {
+        let ids = self.trash.iter().map(|e| e.id).collect::<Vec<_>>();
+        for id in ids {
+            let _ = self.purge(id).await;
+        }
+    }
+
+    /// Purges the items deleted longer than the retention ago, returning how many were purged
+    pub async fn sweep(&mut self, now: DateTime<Local>) -> usize {
+        let expired = self
+            .trash
+            .iter()
+            .filter(|e| e.expires(self.trash_retention) <= now)
+            .map(|e| e.id)
+            .collect::<Vec<_>>();
+        for id in &expired {
+            let _ = self.purge(*id).await;
+        }
+        expired.len()
+    }
+
+    /// Purges expired items from the trash every hour, see `sweep`
+    pub async fn trash_loop(file_server: Arc<AsyncMutex<FileServer>>) {
+        let mut interval = tokio::time::interval(TRASH_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut file_server = file_server.lock().await;
+            let purged = file_server.sweep(Local::now()).await;
+            if purged > 0 {
+                info!("Purged {purged} expired items from the trash");
+                file_server.write().await;
+            }
+        }
+    }
+
+    /// Cancels abandoned resumable uploads every hour, see `ResumableUploads::sweep`
+    pub async fn upload_loop(file_server: Arc<AsyncMutex<FileServer>>) {
+        let uploads = file_server.lock().await.uploads.clone();
+        let mut interval = tokio::time::interval(UPLOAD_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let cancelled = uploads.sweep(Utc::now()).await;
+            if cancelled > 0 {
+                info!("Cancelled {cancelled} abandoned uploads");
+            }
+        }
+    }
+
+    /// Calls `f` with every file in the tree below the directory
+    fn for_each_file(dir: &Directory, mut f: impl FnMut(&mut File)) {
         let mut stack = VecDeque::from([dir.clone()]);
         while let Some(dir) = stack.pop_front() {
-            files.append(&mut dir.files.lock().unwrap());
-            stack.extend(std::mem::take(&mut *dir.children.lock().unwrap()).into_iter());
+            dir.files.lock().unwrap().iter_mut().for_each(&mut f);
+            stack.extend(dir.children.lock().unwrap().iter().cloned());
         }
+    }
 
-        for f in files {
-            if let Err(e) = fs::remove_file(self.path.join(&f.file_server)).await {
-                warn!("Error deleting file {:?}", e);
+    /// Calls `f` with every file in the tree and in the trash, which both keep their blobs on disk
+    fn for_each_stored_file(
+        root: &Directory,
+        trash: &mut [TrashEntry],
+        mut f: impl FnMut(&mut File),
+    ) {
+        Self::for_each_file(root, &mut f);
+        for entry in trash {
+            entry.for_each_file(&mut f);
+        }
+    }
+
+    #[cfg(not(test))]
+    fn count_refs(root: &Directory, trash: &mut [TrashEntry]) -> HashMap<String, usize> {
+        let mut refs = HashMap::new();
+        Self::for_each_stored_file(root, trash, |f| {
+            *refs.entry(f.file_server.clone()).or_default() += 1
+        });
+        refs
+    }
+
+    /// Checks the blobs on disk once a day, see `scrub`
+    pub async fn scrub_loop(file_server: Arc<AsyncMutex<FileServer>>) {
+        let mut interval = tokio::time::interval(SCRUB_INTERVAL);
+        loop {
+            interval.tick().await;
+            Self::scrub(&file_server).await;
+        }
+    }
+
+    /// Finds blobs missing on disk or no longer matching their hash, and marks the files using them
+    ///
+    /// Blobs are read without holding the lock, so files can still be served while large blobs are hashed.
+    /// Files uploaded before blobs were hashed get the hash of their blob. Returns the damaged blobs.
+    pub async fn scrub(file_server: &AsyncMutex<FileServer>) -> Vec<(String, BlobDamage)> {
+        let (path, blobs) = {
+            let file_server = file_server.lock().await;
+            let mut blobs = HashMap::new();
+            Self::for_each_file(&file_server.root, |f| {
+                blobs.insert(f.file_server.clone(), f.hash.clone());
+            });
+            (file_server.path.clone(), blobs)
+        };
+
+        let mut checked = HashMap::new();
+        for (name, hash) in blobs {
+            let blob_path = path.join(&name);
+            match tokio::task::spawn_blocking(move || blob::check(&blob_path, hash.as_deref()))
+                .await
+            {
+                Ok(result) => {
+                    checked.insert(name, result);
+                }
+                Err(e) => warn!("Could not check blob {name} ({e})"),
             }
         }
-        Ok(dir)
+
+        let mut file_server = file_server.lock().await;
+        Self::for_each_file(&file_server.root, |f| match checked.get(&f.file_server) {
+            Some(Ok(hash)) => {
+                f.hash = Some(hash.clone());
+                f.damage = None;
+            }
+            Some(Err(damage)) => f.damage = Some(*damage),
+            None => (),
+        });
+        file_server.write().await;
+
+        let mut damaged = checked
+            .into_iter()
+            .filter_map(|(name, result)| result.err().map(|damage| (name, damage)))
+            .collect::<Vec<_>>();
+        damaged.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, damage) in &damaged {
+            error!("Blob {name} is {damage:?}");
+        }
+        info!("Scrubbed blobs, {} damaged", damaged.len());
+        damaged
     }
 
     /// Traverse through tree until path and create dirs on the way
@@ -1033,19 +2272,7 @@ impl FileServer {
 
     /// Traverse through tree until path. Returns None if path does not exist
     fn traverse_to_dir(&self, path: &[&str]) -> Option<Directory> {
-        let mut dir = self.root.clone();
-        for p in path {
-            let dir_c = dir.clone();
-            let d = dir_c.children.lock().unwrap();
-            let pos = match d.binary_search_by_key(p, |d| &d.name) {
-                // Update dir to dir and traverse down the tree
-                Ok(pos) => pos,
-                // Add Dir at sorted position in Vec if not present
-                Err(_) => return None,
-            };
-            dir = d.get(pos).unwrap().clone();
-        }
-        Some(dir)
+        self.root.traverse(path)
     }
 
     #[cfg(test)]
@@ -1066,16 +2293,28 @@ impl FileServer {
             error_span!("Redis Error", ?error);
             // error_span!("Logging current state instead", ?self.content);
         }
+        if let Err(error) = self
+            .con
+            .lock()
+            .await
+            .json_set::<_, _, _, String>("trash", "$", &self.trash)
+            .await
+        {
+            error_span!("Redis Error", ?error);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::connection::commands::Connections;
+    use crate::store::clock::SystemClock;
     use crate::store::store::Store;
 
     use super::*;
     use axum::serve;
     use reqwest::multipart;
+    use sha2::{Digest, Sha256};
     use std::sync::Arc;
     use tokio::fs;
     use tokio::net::TcpListener;
@@ -1083,9 +2322,16 @@ mod tests {
 
     const FILE_PATH: &'static str = "./test_files";
 
-    /// Helper function
+    /// Helper function, giving every server its own directory since identical content shares a blob
     async fn setup_test_server() -> FileServer {
-        FileServer::new("not used since in test environment", FILE_PATH).await
+        let path = Path::new(FILE_PATH).join(Uuid::new_v4().to_string());
+        FileServer::new(
+            "not used since in test environment",
+            path,
+            Quota::default(),
+            TimeDelta::days(30),
+        )
+        .await
     }
 
     #[tokio::test]
@@ -1190,7 +2436,7 @@ mod tests {
 
         let file = server.add_file(path.clone(), vec![]).await.unwrap();
 
-        let disk_path = format!("{FILE_PATH}/{}", file.file_server);
+        let disk_path = server.path.join(&file.file_server);
         fs::write(&disk_path, b"dummy data").await.unwrap();
 
         let deleted = server
@@ -1199,6 +2445,9 @@ mod tests {
             .expect("Failed to delete file");
         assert_eq!(deleted.name, "delete_me.txt");
 
+        // Kept in the trash until purged
+        assert!(Path::new(&disk_path).exists());
+        server.purge_all().await;
         assert!(!Path::new(&disk_path).exists());
 
         let fail_result = server
@@ -1212,29 +2461,325 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_add_and_delete_directory() {
+    async fn test_image_variants_are_added_and_deleted() {
         let mut server = setup_test_server().await;
-
-        let dir_path = "/my_folder/child_folder/".to_string();
-        server.add_dir(&dir_path).await.expect("Failed to add dir");
+        let mut content = std::io::Cursor::new(vec![]);
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(2000, 1000))
+            .write_to(&mut content, image::ImageFormat::Png)
+            .unwrap();
 
         let file = server
-            .add_file("/my_folder/child_folder/test.txt".to_string(), vec![])
+            .add_file("/variants/photo.png".to_string(), content.into_inner())
             .await
             .unwrap();
-        let disk_path = format!("{FILE_PATH}/{}", file.file_server);
-        fs::write(&disk_path, b"data").await.unwrap();
-
-        let deleted_dir = server
+        assert_eq!(
+            vec![
+                (variants::ImageVariant::Thumbnail, 320, 160),
+                (variants::ImageVariant::Hd, 1920, 960),
+            ],
+            file.variants
+                .iter()
+                .map(|v| (v.kind, v.width, v.height))
+                .collect::<Vec<_>>()
+        );
+        let tree = server.get_paths_tree().await;
+        assert_eq!(
+            Some(format!(
+                "/files/variants/photo.png?variant=thumbnail&v={}",
+                file.hash.as_ref().unwrap()
+            )),
+            tree.directories[0].files[0].thumbnail
+        );
+
+        let disk_paths: Vec<_> = file
+            .disk_names()
+            .map(|name| server.path.join(name))
+            .collect();
+        assert!(disk_paths.iter().all(|p| p.exists()));
+        server
+            .delete_file("/variants/photo.png".to_string())
+            .await
+            .unwrap();
+        assert!(disk_paths.iter().all(|p| p.exists()));
+        server.purge_all().await;
+        assert!(disk_paths.iter().all(|p| !p.exists()));
+    }
+
+    #[tokio::test]
+    async fn test_variants_are_kept_for_other_extensions() {
+        let mut server = setup_test_server().await;
+        let mut content = std::io::Cursor::new(vec![]);
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(400, 200))
+            .write_to(&mut content, image::ImageFormat::Jpeg)
+            .unwrap();
+        let content = content.into_inner();
+
+        let jpg = server
+            .add_file("/photos/a.jpg".to_string(), content.clone())
+            .await
+            .unwrap();
+        let jpeg = server
+            .add_file("/photos/b.jpeg".to_string(), content)
+            .await
+            .unwrap();
+        assert_ne!(jpg.file_server, jpeg.file_server);
+
+        server
+            .delete_file("/photos/a.jpg".to_string())
+            .await
+            .unwrap();
+        server.purge_all().await;
+        assert!(
+            jpeg.disk_names()
+                .all(|name| server.path.join(name).exists())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_identical_content_shares_a_blob() {
+        let mut server = setup_test_server().await;
+        let first = server
+            .add_file("/a/same.txt".to_string(), b"same".to_vec())
+            .await
+            .unwrap();
+        let second = server
+            .add_file("/b/same.txt".to_string(), b"same".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(first.file_server, second.file_server);
+        assert_eq!(
+            Some("0967115f2813a3541eaef77de9d9d5773f1c0c04314b0bbfe4ff3b3b1c55b5d5"),
+            first.hash.as_deref()
+        );
+
+        let disk_path = server.path.join(&first.file_server);
+        server.delete_file("/a/same.txt".to_string()).await.unwrap();
+        assert!(disk_path.exists());
+        server.delete_dir("/b/".to_string()).await.unwrap();
+        assert!(disk_path.exists());
+        server.purge_all().await;
+        assert!(!disk_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_scrub_finds_damaged_blobs() {
+        let mut server = setup_test_server().await;
+        let intact = server
+            .add_file("/scrub/intact.txt".to_string(), b"intact".to_vec())
+            .await
+            .unwrap();
+        let corrupted = server
+            .add_file("/scrub/corrupted.txt".to_string(), b"corrupted".to_vec())
+            .await
+            .unwrap();
+        let missing = server
+            .add_file("/scrub/missing.txt".to_string(), b"missing".to_vec())
+            .await
+            .unwrap();
+        fs::write(server.path.join(&corrupted.file_server), b"changed")
+            .await
+            .unwrap();
+        fs::remove_file(server.path.join(&missing.file_server))
+            .await
+            .unwrap();
+
+        let server = AsyncMutex::new(server);
+        let mut expected = vec![
+            (corrupted.file_server, BlobDamage::Corrupted),
+            (missing.file_server, BlobDamage::Missing),
+        ];
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(expected, FileServer::scrub(&server).await);
+
+        let mut server = server.into_inner();
+        let tree = server.get_paths_tree().await;
+        let damage = |name: &str| {
+            tree.directories[0]
+                .files
+                .iter()
+                .find(|f| f.name == name)
+                .unwrap()
+                .damage
+        };
+        assert_eq!(None, damage("intact.txt"));
+        assert_eq!(Some(BlobDamage::Corrupted), damage("corrupted.txt"));
+        assert_eq!(Some(BlobDamage::Missing), damage("missing.txt"));
+
+        // Uploading the content again repairs the blob
+        server
+            .add_file("/scrub/again.txt".to_string(), b"corrupted".to_vec())
+            .await
+            .unwrap();
+        let server = AsyncMutex::new(server);
+        assert_eq!(1, FileServer::scrub(&server).await.len());
+        assert!(server.into_inner().path.join(&intact.file_server).exists());
+    }
+
+    #[tokio::test]
+    async fn test_search_with_tags_and_pages() {
+        let mut server = setup_test_server().await;
+        for path in ["/lobby/promo.mp4", "/lobby/menu.png", "/hall/promo.jpg"] {
+            server
+                .add_file(path.to_string(), path.as_bytes().to_vec())
+                .await
+                .unwrap();
+        }
+        server
+            .set_metadata(
+                "/hall/promo.jpg",
+                vec!["spring".to_string()],
+                Some("Spring campaign".to_string()),
+            )
+            .unwrap();
+        assert!(
+            server
+                .set_metadata("/hall/missing.jpg", vec![], None)
+                .is_err()
+        );
+
+        let parse = |query: &str| {
+            let uri = format!("/search?{query}").parse().unwrap();
+            Query::<SearchQuery>::try_from_uri(&uri).unwrap().0
+        };
+        let search = |query: &str| {
+            let results = server.index().search(&parse(query)).unwrap();
+            let ids = results.files.into_iter().map(|f| f.id).collect::<Vec<_>>();
+            (ids, results.total)
+        };
+        assert_eq!(
+            (
+                vec![
+                    "/hall/promo.jpg".to_string(),
+                    "/lobby/promo.mp4".to_string()
+                ],
+                2
+            ),
+            search("name=PROMO")
+        );
+        assert_eq!(
+            (vec!["/lobby/menu.png".to_string()], 3),
+            search("per_page=1&page=1")
+        );
+        assert_eq!(
+            (vec!["/hall/promo.jpg".to_string()], 1),
+            search("tags=spring")
+        );
+        assert_eq!(
+            (vec!["/lobby/menu.png".to_string()], 1),
+            search("mime=image/png")
+        );
+        assert!(server.index().search(&parse("per_page=1000")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pdf_page_count() {
+        let mut server = setup_test_server().await;
+        server
+            .add_file("/pages/slides.pdf".to_string(), pdf::test::pdf(3))
+            .await
+            .unwrap();
+        assert_eq!(Some(3), server.page_count("/pages/slides.pdf").await);
+        assert_eq!(None, server.page_count("/pages/other.pdf").await);
+
+        server
+            .delete_file("/pages/slides.pdf".to_string())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_add_and_delete_directory() {
+        let mut server = setup_test_server().await;
+
+        let dir_path = "/my_folder/child_folder/".to_string();
+        server.add_dir(&dir_path).await.expect("Failed to add dir");
+
+        let file = server
+            .add_file("/my_folder/child_folder/test.txt".to_string(), vec![])
+            .await
+            .unwrap();
+        let disk_path = server.path.join(&file.file_server);
+        fs::write(&disk_path, b"data").await.unwrap();
+
+        let deleted_dir = server
             .delete_dir("/my_folder/".to_string())
             .await
             .expect("Failed to delete dir");
         assert_eq!(deleted_dir.name, "my_folder");
 
-        assert!(!Path::new(&disk_path).exists());
-
         let tree = server.get_paths_tree().await;
         assert!(tree.directories.iter().all(|d| d.name != "my_folder"));
+
+        assert!(Path::new(&disk_path).exists());
+        server.purge_all().await;
+        assert!(!Path::new(&disk_path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_trash_restore_purge_and_sweep() {
+        let mut server = setup_test_server().await;
+        let kept = server
+            .add_file("/docs/kept.txt".to_string(), b"kept".to_vec())
+            .await
+            .unwrap();
+        let purged = server
+            .add_file("/docs/old/purged.txt".to_string(), b"purged".to_vec())
+            .await
+            .unwrap();
+
+        server
+            .delete_file("/docs/kept.txt".to_string())
+            .await
+            .unwrap();
+        server.delete_dir("/docs/old/".to_string()).await.unwrap();
+        let trash = server.list_trash();
+        assert_eq!(
+            vec!["/docs/old/", "/docs/kept.txt"],
+            trash.iter().map(|t| t.path.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(6, trash[0].size);
+
+        // Restoring fails while the path is taken
+        server
+            .add_file("/docs/kept.txt".to_string(), vec![])
+            .await
+            .unwrap();
+        assert_eq!(2, server.restore(trash[1].id, None).unwrap_err().0);
+        assert_eq!(
+            "/archive/kept.txt",
+            server
+                .restore(trash[1].id, Some("/archive/kept.txt".to_string()))
+                .unwrap()
+        );
+        assert_eq!(
+            Some("/archive/kept.txt".to_string()),
+            server.get_file("/archive/kept.txt").await.map(|f| f.path)
+        );
+        assert_eq!(1, server.restore(trash[1].id, None).unwrap_err().0);
+
+        server
+            .restore(trash[0].id, Some("/restored/".to_string()))
+            .unwrap();
+        assert_eq!(
+            Some("/restored/purged.txt".to_string()),
+            server
+                .get_file("/restored/purged.txt")
+                .await
+                .map(|f| f.path)
+        );
+        server.delete_dir("/restored/".to_string()).await.unwrap();
+
+        // Swept once the retention expired
+        let deleted = server.list_trash()[0].deleted.clone();
+        let deleted = DateTime::parse_from_rfc3339(&deleted).unwrap();
+        assert_eq!(0, server.sweep(deleted.into()).await);
+        assert!(server.path.join(&purged.file_server).exists());
+        let expired = deleted + TimeDelta::days(30);
+        assert_eq!(1, server.sweep(expired.into()).await);
+        assert!(server.list_trash().is_empty());
+        assert!(!server.path.join(&purged.file_server).exists());
+        assert!(server.path.join(&kept.file_server).exists());
+        assert_eq!(1, server.purge(Uuid::new_v4()).await.unwrap_err().0);
     }
 
     #[tokio::test]
@@ -1475,17 +3020,25 @@ mod tests {
         );
     }
 
-    #[tokio::test]
-    async fn test_full_api_upload_read_delete() {
-        let file_server = setup_test_server().await;
-
+    /// Serves the file API and the files, returning the base url
+    async fn start_api(file_server: FileServer) -> String {
         let state = AppState {
+            files: file_server.index(),
             file_server: Arc::new(AsyncMutex::new(file_server)),
             htmx_hash: String::new(),
-            store: Arc::new(Store::new("again, not used in test environment").await),
+            store: Arc::new(
+                Store::new("again, not used in test environment", Arc::new(SystemClock)).await,
+            ),
+            connections: Arc::new(Connections::new(Arc::new(SystemClock))),
         };
 
-        let (app, _api) = file_api_router().with_state(state).split_for_parts();
+        let (app, _api) = file_api_router()
+            .with_state(state.clone())
+            .split_for_parts();
+        let app = app.nest(
+            "/files",
+            axum::Router::new().fallback(get_file).with_state(state),
+        );
 
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
@@ -1494,7 +3047,421 @@ mod tests {
         tokio::spawn(async move {
             serve(listener, app).await.unwrap();
         });
+        base_url
+    }
 
+    fn upload_form(name: &str, content: Vec<u8>) -> multipart::Form {
+        let part = multipart::Part::bytes(content)
+            .file_name(name.to_string())
+            .mime_str("text/plain")
+            .unwrap();
+        multipart::Form::new()
+            .text("directory", "/api_folder")
+            .part("files", part)
+    }
+
+    #[tokio::test]
+    async fn test_archive_upload_and_download() {
+        let base_url = start_api(setup_test_server().await).await;
+        let client = reqwest::Client::new();
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("bundle/empty/", options).unwrap();
+        for (name, content) in [
+            ("bundle/slides/one.txt", "one"),
+            ("bundle/two.txt", "two"),
+            ("__MACOSX/bundle/._two.txt", "resource fork"),
+        ] {
+            zip.start_file(name, options).unwrap();
+            std::io::Write::write_all(&mut zip, content.as_bytes()).unwrap();
+        }
+        let zip = zip.finish().unwrap().into_inner();
+
+        let res = client
+            .post(format!("{base_url}/"))
+            .multipart(upload_form("bundle.zip", zip))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = client
+            .get(format!("{base_url}/files/api_folder/bundle/slides/one.txt"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!("one", res.text().await.unwrap());
+
+        let res = client
+            .get(format!(
+                "{base_url}/archive?path=/api_folder/bundle/&format=tar.gz"
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            "attachment; filename=\"bundle.tar.gz\"",
+            res.headers()[CONTENT_DISPOSITION]
+        );
+        let content = res.bytes().await.unwrap();
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(&content[..]));
+        let mut entries = tar
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(
+            vec!["empty", "slides", "slides/one.txt", "two.txt"],
+            entries
+        );
+
+        let res = client
+            .post(format!("{base_url}/"))
+            .multipart(upload_form("broken.zip", b"not a zip".to_vec()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        assert_eq!(
+            9,
+            res.json::<serde_json::Value>().await.unwrap()["content"]["code"]
+        );
+        let res = client
+            .get(format!("{base_url}/archive?path=/missing/"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn test_upload_quotas() {
+        let mut file_server = setup_test_server().await;
+        file_server.quota = Quota {
+            max_file_size: Some(10),
+            max_storage: Some(15),
+        };
+        let base_url = start_api(file_server).await;
+        let client = reqwest::Client::new();
+        let upload = |name: &str, content: &[u8]| {
+            client
+                .post(format!("{base_url}/"))
+                .multipart(upload_form(name, content.to_vec()))
+                .send()
+        };
+
+        let res = upload("large.txt", b"more than ten bytes").await.unwrap();
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+        assert_eq!(
+            serde_json::json!({
+                "type": "Error",
+                "content": {
+                    "code": 6,
+                    "message": "Files may not be larger than 10 bytes (large.txt)"
+                }
+            }),
+            res.json::<serde_json::Value>().await.unwrap()
+        );
+
+        let res = upload("first.txt", b"ten bytes!").await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        // Identical content takes no more space
+        let res = upload("copy.txt", b"ten bytes!").await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = upload("second.txt", b"ten again!").await.unwrap();
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, res.status());
+        assert_eq!(
+            7,
+            res.json::<serde_json::Value>().await.unwrap()["content"]["code"]
+        );
+
+        // Malformed requests are refused instead of panicking
+        let res = client
+            .post(format!("{base_url}/"))
+            .header("content-type", "multipart/form-data; boundary=x")
+            .body("--x\r\nnot a multipart body")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn test_upload_stops_at_free_storage() {
+        let mut file_server = setup_test_server().await;
+        file_server.quota = Quota {
+            max_file_size: None,
+            max_storage: Some(15),
+        };
+        let upload_dir = file_server.upload_dir();
+        let base_url = start_api(file_server).await;
+
+        let res = reqwest::Client::new()
+            .post(format!("{base_url}/"))
+            .multipart(upload_form("large.txt", vec![0; 64 * 1024]))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, res.status());
+        assert_eq!(
+            serde_json::json!({
+                "type": "Error",
+                "content": {
+                    "code": 7,
+                    "message": "Not enough storage is free to store files this large (large.txt)"
+                }
+            }),
+            res.json::<serde_json::Value>().await.unwrap()
+        );
+        assert_eq!(0, std::fs::read_dir(upload_dir).unwrap().count());
+    }
+
+    #[tokio::test]
+    async fn test_download_ranges_and_caching() {
+        let mut file_server = setup_test_server().await;
+        let file = file_server
+            .add_file("/media/clip.txt".to_string(), b"0123456789".to_vec())
+            .await
+            .unwrap();
+        let hash = file.hash.unwrap();
+        let base_url = start_api(file_server).await;
+        let client = reqwest::Client::new();
+        let url = format!("{base_url}/files/media/clip.txt");
+
+        let res = client.get(&url).send().await.unwrap();
+        assert_eq!(REVALIDATE, res.headers()[CACHE_CONTROL]);
+        let last_modified = res.headers()[hyper::header::LAST_MODIFIED].clone();
+        let res = client
+            .get(&url)
+            .header(hyper::header::IF_MODIFIED_SINCE, last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+
+        let res = client.get(format!("{url}?v={hash}")).send().await.unwrap();
+        assert_eq!(IMMUTABLE, res.headers()[CACHE_CONTROL]);
+
+        let res = client
+            .get(format!("{base_url}/files/media/%FF"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let res = client
+            .get(&url)
+            .header(RANGE, "bytes=2-5")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::PARTIAL_CONTENT, res.status());
+        assert_eq!(format!("\"{hash}\""), res.headers()[ETAG]);
+        assert_eq!("2345", res.text().await.unwrap());
+
+        // A range of other content than the client has is sent in full instead
+        let res = client
+            .get(&url)
+            .header(RANGE, "bytes=2-5")
+            .header(IF_RANGE, format!("\"{hash}\""))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::PARTIAL_CONTENT, res.status());
+        let res = client
+            .get(&url)
+            .header(RANGE, "bytes=2-5")
+            .header(IF_RANGE, "\"changed\"")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("0123456789", res.text().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let server = setup_test_server().await;
+        let server_path = server.path.clone();
+        let base_url = start_api(server).await;
+        let client = reqwest::Client::new();
+        let progress = |res: reqwest::Response| async move {
+            let status = res.status();
+            let payload = res.json::<serde_json::Value>().await.unwrap();
+            (status, payload["content"]["offset"].as_u64())
+        };
+
+        let res = client
+            .post(format!("{base_url}/uploads"))
+            .json(&serde_json::json!({ "path": "/api_folder/resumed.txt", "size": 11 }))
+            .send()
+            .await
+            .unwrap();
+        let payload = res.json::<serde_json::Value>().await.unwrap();
+        let id = payload["content"]["id"].as_str().unwrap().to_string();
+        let upload_url = format!("{base_url}/uploads/{id}");
+        let chunk = |offset: u64, content: &'static str| {
+            client
+                .patch(format!("{upload_url}?offset={offset}"))
+                .body(content)
+                .send()
+        };
+
+        let res = chunk(0, "hello ").await.unwrap();
+        assert_eq!((StatusCode::OK, Some(6)), progress(res).await);
+        // The chunk was already received, so the upload continues after it
+        let res = chunk(0, "hello ").await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let res = client.get(&upload_url).send().await.unwrap();
+        assert_eq!((StatusCode::OK, Some(6)), progress(res).await);
+        let res = chunk(6, "world!").await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let res = chunk(6, "world").await.unwrap();
+        assert_eq!((StatusCode::OK, Some(11)), progress(res).await);
+
+        let res = client
+            .get(format!("{base_url}/files/api_folder/resumed.txt"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!("hello world", res.text().await.unwrap());
+        let res = client.get(&upload_url).send().await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let res = client
+            .post(format!("{base_url}/uploads"))
+            .json(&serde_json::json!({ "path": "/api_folder/cancelled.txt", "size": 11 }))
+            .send()
+            .await
+            .unwrap();
+        let payload = res.json::<serde_json::Value>().await.unwrap();
+        let upload_url = format!(
+            "{base_url}/uploads/{}",
+            payload["content"]["id"].as_str().unwrap()
+        );
+        let res = client.delete(&upload_url).send().await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = client.get(&upload_url).send().await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // A file added at the path meanwhile keeps the upload, so it is not lost
+        let res = client
+            .post(format!("{base_url}/uploads"))
+            .json(&serde_json::json!({ "path": "/api_folder/taken.txt", "size": 5 }))
+            .send()
+            .await
+            .unwrap();
+        let payload = res.json::<serde_json::Value>().await.unwrap();
+        let upload_url = format!(
+            "{base_url}/uploads/{}",
+            payload["content"]["id"].as_str().unwrap()
+        );
+        let res = client
+            .post(format!("{base_url}/"))
+            .multipart(upload_form("taken.txt", b"first".to_vec()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = client
+            .patch(format!("{upload_url}?offset=0"))
+            .body("later")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let res = client.get(&upload_url).send().await.unwrap();
+        assert_eq!((StatusCode::OK, Some(5)), progress(res).await);
+
+        // Neither is it lost when the file cannot be stored, here since a directory is in the way of its blob
+        let res = client
+            .post(format!("{base_url}/uploads"))
+            .json(&serde_json::json!({ "path": "/api_folder/stuck.txt", "size": 5 }))
+            .send()
+            .await
+            .unwrap();
+        let payload = res.json::<serde_json::Value>().await.unwrap();
+        let upload_url = format!(
+            "{base_url}/uploads/{}",
+            payload["content"]["id"].as_str().unwrap()
+        );
+        let blob = server_path.join(format!("{:x}.txt", Sha256::digest(b"stuck")));
+        std::fs::create_dir(&blob).unwrap();
+        let res = client
+            .patch(format!("{upload_url}?offset=0"))
+            .body("stuck")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let res = client.get(&upload_url).send().await.unwrap();
+        assert_eq!((StatusCode::OK, Some(5)), progress(res).await);
+
+        std::fs::remove_dir(&blob).unwrap();
+        let res = client
+            .patch(format!("{upload_url}?offset=5"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = client
+            .get(format!("{base_url}/files/api_folder/stuck.txt"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!("stuck", res.text().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_resumable_uploads_share_free_storage() {
+        let mut server = setup_test_server().await;
+        server.quota.max_storage = Some(10);
+        server
+            .add_file("/stored.txt".to_string(), b"stored".to_vec())
+            .await
+            .unwrap();
+        let uploads = server.uploads.clone();
+        let mut create = |size| {
+            let storage = server.storage_left();
+            let uploads = uploads.clone();
+            async move {
+                uploads
+                    .create("/up.txt".to_string(), size, None, storage)
+                    .await
+            }
+        };
+
+        // 4 bytes are free, or 6 for content identical to the stored file
+        let open = create(6).await.unwrap();
+        assert_eq!(7, create(1).await.unwrap_err().0);
+        uploads.cancel(open.id).await.unwrap();
+        assert!(create(4).await.is_ok());
+        assert_eq!(7, create(3).await.unwrap_err().0);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_uploads_are_cancelled() {
+        let server = setup_test_server().await;
+        let upload = server
+            .uploads
+            .create("/abandoned.txt".to_string(), 10, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(0, server.uploads.sweep(Utc::now()).await);
+        assert!(server.uploads.progress(upload.id).await.is_ok());
+        assert_eq!(
+            1,
+            server.uploads.sweep(Utc::now() + TimeDelta::days(2)).await
+        );
+        assert_eq!(1, server.uploads.progress(upload.id).await.unwrap_err().0);
+    }
+
+    #[tokio::test]
+    async fn test_full_api_upload_read_delete() {
+        let base_url = start_api(setup_test_server().await).await;
         let client = reqwest::Client::new();
 
         // ==========================================
@@ -1543,7 +3510,30 @@ mod tests {
         assert_eq!(api_folder.files[0].size, 20); // Length of "Hello, Axum Web API!"
 
         // ==========================================
-        // TEST C: DELETE FILE (DELETE /)
+        // TEST C: DOWNLOAD FILE (GET /files/...)
+        // ==========================================
+        let download_res = client
+            .get(format!("{}/files/api_folder/api_test.txt", base_url))
+            .send()
+            .await
+            .unwrap();
+        let etag = download_res.headers()[ETAG].clone();
+        assert_eq!(
+            format!("\"{}\"", api_folder.files[0].hash.as_ref().unwrap()),
+            etag.to_str().unwrap()
+        );
+        assert_eq!(file_content, download_res.bytes().await.unwrap());
+
+        let cached_res = client
+            .get(format!("{}/files/api_folder/api_test.txt", base_url))
+            .header(IF_NONE_MATCH, etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(cached_res.status(), StatusCode::NOT_MODIFIED);
+
+        // ==========================================
+        // TEST D: DELETE FILE (DELETE /)
         // ==========================================
         let delete_payload = DeleteFilesRequest {
             ids: vec!["/api_folder/api_test.txt".to_string()],
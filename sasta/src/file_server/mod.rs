@@ -1 +1,8 @@
-pub mod file_server;
\ No newline at end of file
+pub mod archive;
+pub mod blob;
+#[allow(clippy::module_inception)]
+pub mod file_server;
+pub mod pdf;
+pub mod search;
+pub mod upload;
+pub mod variants;
\ No newline at end of file
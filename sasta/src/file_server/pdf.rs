@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest document downloaded to count its pages
+const MAX_PROBE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Whether the page count is read from files with the extension
+pub fn is_pdf(extension: &str) -> bool {
+    extension.eq_ignore_ascii_case("pdf")
+}
+
+/// Amount of pages in the PDF document
+pub fn page_count(content: &[u8]) -> Result<u32, String> {
+    let document = lopdf::Document::load_mem(content).map_err(|e| e.to_string())?;
+    Ok(document.get_pages().len() as u32)
+}
+
+/// Downloads the PDF document at the url to count its pages, for documents not stored by Sasta
+pub async fn probe(client: &reqwest::Client, url: &str) -> Result<u32, String> {
+    let content = download(client, url, MAX_PROBE_SIZE).await?;
+    tokio::task::spawn_blocking(move || page_count(&content))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Downloads the content at the url, failing once it is larger than `max_size`
+///
+/// Also used for other content fetched from urls given by users, such as feeds.
+pub async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    max_size: usize,
+) -> Result<Vec<u8>, String> {
+    let too_large = || format!("Content is larger than {max_size} bytes");
+    let mut response = client
+        .get(url)
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?;
+    if response
+        .content_length()
+        .is_some_and(|l| l > max_size as u64)
+    {
+        return Err(too_large());
+    }
+    let mut content = vec![];
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        if content.len() + chunk.len() > max_size {
+            return Err(too_large());
+        }
+        content.extend_from_slice(&chunk);
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+pub mod test {
+    use axum::{Router, body::Body, routing::get};
+    use futures_util::stream;
+    use lopdf::{Document, Object, dictionary};
+    use tokio::net::TcpListener;
+
+    use super::{download, page_count};
+
+    /// Empty PDF document with the given amount of pages
+    pub fn pdf(pages: u32) -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let kids = (0..pages)
+            .map(|_| {
+                document
+                    .add_object(dictionary! {
+                        "Type" => "Page",
+                        "Parent" => pages_id,
+                        "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+                    })
+                    .into()
+            })
+            .collect::<Vec<Object>>();
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => pages,
+            }),
+        );
+        let catalog = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog);
+
+        let mut content = vec![];
+        document.save_to(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn test_page_count() {
+        assert_eq!(Ok(1), page_count(&pdf(1)));
+        assert_eq!(Ok(12), page_count(&pdf(12)));
+        assert!(page_count(b"not a document").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_is_limited() {
+        let app = Router::new()
+            .route("/small", get(|| async { vec![0u8; 100] }))
+            // Streamed without a content length, so the limit is checked while downloading
+            .route(
+                "/streamed",
+                get(|| async {
+                    let chunks = (0..10).map(|_| Ok::<_, std::io::Error>(vec![0u8; 100]));
+                    Body::from_stream(stream::iter(chunks))
+                }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        assert_eq!(
+            Ok(100),
+            download(&client, &format!("{base_url}/small"), 500)
+                .await
+                .map(|c| c.len())
+        );
+        assert!(
+            download(&client, &format!("{base_url}/small"), 50)
+                .await
+                .is_err()
+        );
+        assert!(
+            download(&client, &format!("{base_url}/streamed"), 500)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            Ok(1000),
+            download(&client, &format!("{base_url}/streamed"), 1000)
+                .await
+                .map(|c| c.len())
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+use super::file_server::TreeFile;
+
+/// Results per page when not given
+const DEFAULT_PER_PAGE: usize = 50;
+
+/// Most results of one page
+const MAX_PER_PAGE: usize = 500;
+
+/// Filters of a file search, every given filter has to match
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Part of the file name, ignoring case
+    name: Option<String>,
+    /// Pattern the whole file name matches ignoring case, `*` matching any characters and `?` one
+    glob: Option<String>,
+    /// MIME type such as `video/mp4`, or all types of a kind such as `image/*`
+    mime: Option<String>,
+    /// Smallest size in bytes
+    min_size: Option<usize>,
+    /// Largest size in bytes
+    max_size: Option<usize>,
+    /// Uploaded at or after this RFC 3339 time
+    #[param(value_type = Option<String>)]
+    after: Option<DateTime<FixedOffset>>,
+    /// Uploaded before this RFC 3339 time
+    #[param(value_type = Option<String>)]
+    before: Option<DateTime<FixedOffset>>,
+    /// Comma separated tags the file has all of
+    tags: Option<String>,
+    /// Page of the results, counting from 0
+    #[serde(default)]
+    page: usize,
+    /// Results per page, 50 if not given
+    per_page: Option<usize>,
+}
+
+/// Page of the files found, ordered by path
+#[derive(Serialize, Deserialize, Debug, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/files/")]
+pub struct SearchResults {
+    pub files: Vec<TreeFile>,
+    /// Amount of files found on all pages
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+/// MIME type of the file, guessed from its name
+pub fn mime_type(name: &str) -> String {
+    mime_guess::from_path(name)
+        .first_or_octet_stream()
+        .essence_str()
+        .to_string()
+}
+
+/// Whether the name matches the pattern, with `*` matching any characters and `?` one
+///
+/// Only the last `*` is backtracked to, so patterns with many `*` cannot take exponential time.
+fn glob_matches(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` in the pattern and of the name where it started matching
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` match one more character
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl SearchQuery {
+    /// Results per page, returning what is wrong if it cannot be used
+    pub fn per_page(&self) -> Result<usize, String> {
+        match self.per_page.unwrap_or(DEFAULT_PER_PAGE) {
+            0 => Err("At least 1 result has to be shown per page".to_string()),
+            n if n > MAX_PER_PAGE => {
+                Err(format!("At most {MAX_PER_PAGE} results are shown per page"))
+            }
+            n => Ok(n),
+        }
+    }
+
+    /// Tags in the query, trimmed and without empty ones
+    fn tags(&self) -> impl Iterator<Item = &str> {
+        self.tags
+            .iter()
+            .flat_map(|tags| tags.split(','))
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+    }
+
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    /// Whether a file with the given properties is found by the query
+    pub fn matches(
+        &self,
+        name: &str,
+        size: usize,
+        date: &DateTime<Local>,
+        tags: &[String],
+    ) -> bool {
+        let lowercase = name.to_lowercase();
+        self.name
+            .as_ref()
+            .is_none_or(|part| lowercase.contains(&part.to_lowercase()))
+            && self.glob.as_ref().is_none_or(|glob| {
+                let pattern = glob.to_lowercase().chars().collect::<Vec<_>>();
+                glob_matches(&pattern, &lowercase.chars().collect::<Vec<_>>())
+            })
+            && self.mime.as_ref().is_none_or(|mime| {
+                let found = mime_type(name);
+                match mime.strip_suffix("/*") {
+                    Some(kind) => found.split('/').next() == Some(kind),
+                    None => found.eq_ignore_ascii_case(mime),
+                }
+            })
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+            && self.after.is_none_or(|after| *date >= after)
+            && self.before.is_none_or(|before| *date < before)
+            && self
+                .tags()
+                .all(|tag| tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+    }
+}
+
+/// Tags as stored on a file: trimmed, without duplicates and sorted
+///
+/// Tags may not contain commas, since they separate the tags searched for.
+pub fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut tags = tags
+        .into_iter()
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<_>>();
+    if let Some(tag) = tags.iter().find(|tag| tag.contains(',')) {
+        return Err(format!("Tag '{tag}' may not contain a comma"));
+    }
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Local, TimeZone};
+
+    use super::{SearchQuery, glob_matches};
+
+    #[test]
+    fn test_search_query_matches() {
+        let date = Local.with_ymd_and_hms(2025, 3, 10, 9, 0, 0).unwrap();
+        let tags = vec!["Lobby".to_string(), "spring".to_string()];
+        let matches = |query: SearchQuery| query.matches("Promo-01.MP4", 2000, &date, &tags);
+
+        assert!(matches(SearchQuery::default()));
+        assert!(matches(SearchQuery {
+            name: Some("promo".to_string()),
+            ..Default::default()
+        }));
+        assert!(matches(SearchQuery {
+            glob: Some("promo-??.mp4".to_string()),
+            ..Default::default()
+        }));
+        assert!(!matches(SearchQuery {
+            glob: Some("*.jpg".to_string()),
+            ..Default::default()
+        }));
+        assert!(matches(SearchQuery {
+            mime: Some("video/*".to_string()),
+            min_size: Some(1000),
+            max_size: Some(2000),
+            ..Default::default()
+        }));
+        assert!(!matches(SearchQuery {
+            mime: Some("image/*".to_string()),
+            ..Default::default()
+        }));
+        assert!(matches(SearchQuery {
+            after: Some(date.fixed_offset()),
+            tags: Some("lobby, spring".to_string()),
+            ..Default::default()
+        }));
+        assert!(!matches(SearchQuery {
+            before: Some(date.fixed_offset()),
+            ..Default::default()
+        }));
+        assert!(!matches(SearchQuery {
+            tags: Some("lobby,summer".to_string()),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        let matches = |pattern: &str, name: &str| {
+            glob_matches(
+                &pattern.chars().collect::<Vec<_>>(),
+                &name.chars().collect::<Vec<_>>(),
+            )
+        };
+        assert!(matches("*", ""));
+        assert!(matches("a*b?c", "axxbyc"));
+        assert!(matches("*.tar.gz", "backup.tar.gz"));
+        assert!(!matches("a?", "a"));
+        assert!(!matches("*.png", "image.png.bak"));
+
+        // Backtracking to every `*` would take too long here
+        let name = "a".repeat(1000);
+        assert!(!matches(&format!("{}b", "*a".repeat(50)), &name));
+        assert!(matches(&"*a".repeat(50), &name));
+    }
+}
@@ -0,0 +1,632 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{body::Bytes, extract::Multipart};
+use chrono::{DateTime, TimeDelta, Utc};
+use futures_util::{Stream, StreamExt};
+#[cfg(not(test))]
+use redis::{AsyncCommands, aio::MultiplexedConnection};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs,
+    io::{AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex as AsyncMutex,
+};
+#[cfg(not(test))]
+use tracing::error_span;
+use tracing::{info_span, warn, warn_span};
+use ts_rs::TS;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::blob;
+
+/// Time after which a resumable upload which received nothing is cancelled
+const ABANDONED_AFTER: TimeDelta = TimeDelta::days(1);
+
+/// Limits on stored files in bytes, unlimited when not set
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Quota {
+    /// Largest file which may be uploaded
+    pub max_file_size: Option<u64>,
+    /// Total size of all stored files, counting identical content once
+    pub max_storage: Option<u64>,
+}
+
+/// Storage left within the quota for an upload, checked while it is received so it cannot fill the disk
+pub struct StorageLeft {
+    free: u64,
+    /// Size of the stored content by hash, which takes no more storage when uploaded again
+    stored: HashMap<String, u64>,
+}
+
+impl StorageLeft {
+    pub fn new(free: u64, stored: HashMap<String, u64>) -> Self {
+        StorageLeft { free, stored }
+    }
+
+    /// Largest file which could still be stored, the free storage or content identical to a stored file
+    pub fn max_size(&self) -> u64 {
+        self.stored.values().copied().fold(self.free, u64::max)
+    }
+
+    /// Takes the storage the staged file needs, failing if not enough is left
+    pub fn take(&mut self, staged: &StagedFile) -> Result<(), (u8, String)> {
+        if self.stored.contains_key(staged.hash()) {
+            return Ok(());
+        }
+        if staged.size() > self.free {
+            return Err((
+                7,
+                format!(
+                    "Storing the files needs more than the {} bytes of storage that are free",
+                    self.free
+                ),
+            ));
+        }
+        self.free -= staged.size();
+        self.stored.insert(staged.hash().to_string(), staged.size());
+        Ok(())
+    }
+}
+
+/// Upload written to a temporary file, hashed while it was written
+///
+/// The file is deleted when dropped, unless it was moved into place with `persist`.
+pub struct StagedFile {
+    path: PathBuf,
+    size: u64,
+    hash: String,
+}
+
+impl StagedFile {
+    /// Writes the chunks to a new file in the directory, failing once they exceed `max_size`
+    pub async fn write<E: Display>(
+        dir: &Path,
+        mut chunks: impl Stream<Item = Result<Bytes, E>> + Unpin,
+        max_size: Option<u64>,
+    ) -> Result<StagedFile, (u8, String)> {
+        let mut staged = StagedFile {
+            path: dir.join(Uuid::new_v4().to_string()),
+            size: 0,
+            hash: String::new(),
+        };
+        let io_error = |e: io::Error| (8, format!("Could not store upload ({e})"));
+        let mut file = fs::File::create(&staged.path).await.map_err(io_error)?;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|e| (5, e.to_string()))?;
+            staged.size += chunk.len() as u64;
+            if let Some(max_size) = max_size.filter(|max| staged.size > *max) {
+                return Err((6, format!("Files may not be larger than {max_size} bytes")));
+            }
+            hasher.update(&chunk);
+            file.write_all(&chunk).await.map_err(io_error)?;
+        }
+        file.sync_all().await.map_err(io_error)?;
+        staged.hash = format!("{:x}", hasher.finalize());
+        Ok(staged)
+    }
+
+    /// Copies the content to a new file in the directory, failing once it exceeds `max_size`
+    ///
+    /// Blocks, for content read synchronously such as the files of an archive.
+    pub fn read_from(
+        dir: &Path,
+        mut content: impl Read,
+        max_size: Option<u64>,
+    ) -> Result<StagedFile, (u8, String)> {
+        let mut staged = StagedFile {
+            path: dir.join(Uuid::new_v4().to_string()),
+            size: 0,
+            hash: String::new(),
+        };
+        let io_error = |e: io::Error| (8, format!("Could not store upload ({e})"));
+        let mut file = std::fs::File::create(&staged.path).map_err(io_error)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = content.read(&mut buf).map_err(|e| (5, e.to_string()))?;
+            if read == 0 {
+                break;
+            }
+            staged.size += read as u64;
+            if let Some(max_size) = max_size.filter(|max| staged.size > *max) {
+                return Err((6, format!("Files may not be larger than {max_size} bytes")));
+            }
+            hasher.update(&buf[..read]);
+            file.write_all(&buf[..read]).map_err(io_error)?;
+        }
+        file.sync_all().map_err(io_error)?;
+        staged.hash = format!("{:x}", hasher.finalize());
+        Ok(staged)
+    }
+
+    /// Takes over a file already written in full, such as a finished resumable upload
+    fn existing(path: PathBuf, size: u64, hash: String) -> StagedFile {
+        StagedFile { path, size, hash }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Hex encoded SHA-256 of the content
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Moves the file to the path, atomically as long as it is on the same file system
+    ///
+    /// Gives the staged file back if it could not be moved.
+    pub async fn persist(self, to: &Path) -> Result<(), (io::Error, StagedFile)> {
+        fs::rename(&self.path, to).await.map_err(|e| (e, self))
+    }
+
+    /// Keeps the file on disk, returning its path
+    fn keep(mut self) -> PathBuf {
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        // Already gone if it was persisted
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Struct representing the multipart/form-data schema for file uploads
+#[derive(ToSchema)]
+pub struct FileUpload {
+    /// Target directory to upload files to
+    pub directory: String,
+    /// One or more files to upload
+    #[schema(value_type = Vec<String>, format = Binary)]
+    pub files: Vec<FileItem>,
+}
+
+pub struct FileItem {
+    pub name: String,
+    pub content: StagedFile,
+}
+
+impl FileUpload {
+    /// Parse Multipart stream into FileUpload struct
+    ///
+    /// Files are streamed to temporary files in `upload_dir`, so they are never held in memory.
+    /// Receiving stops once a file is larger than the storage left.
+    pub async fn from_multipart(
+        mut multipart: Multipart,
+        upload_dir: &Path,
+        max_file_size: Option<u64>,
+        mut storage: Option<StorageLeft>,
+    ) -> Result<Self, (u8, String)> {
+        let mut directory = None;
+        let mut files = Vec::new();
+
+        loop {
+            let field = match multipart.next_field().await {
+                Ok(Some(field)) => field,
+                Ok(None) => break,
+                Err(e) => return Err((1, e.body_text())),
+            };
+            if let Some(filename) = field.file_name() {
+                let filename = filename.to_string();
+                info_span!("Add file ", filename);
+                let max_storage = storage.as_ref().map(StorageLeft::max_size);
+                let storage_limited =
+                    max_storage.is_some_and(|s| max_file_size.is_none_or(|max| s < max));
+                let max_size = if storage_limited {
+                    max_storage
+                } else {
+                    max_file_size
+                };
+                let content = StagedFile::write(upload_dir, field, max_size)
+                    .await
+                    .map_err(|(code, message)| match code {
+                        6 if storage_limited => (
+                            7,
+                            "Not enough storage is free to store files this large".to_string(),
+                        ),
+                        _ => (code, message),
+                    })
+                    .and_then(|content| match &mut storage {
+                        Some(storage) => storage.take(&content).map(|_| content),
+                        None => Ok(content),
+                    })
+                    .map_err(|(code, message)| (code, format!("{message} ({filename})")))?;
+                files.push(FileItem {
+                    name: filename,
+                    content,
+                });
+            } else if let Some(name) = field.name()
+                && name == "directory"
+            {
+                let dir = match field.text().await {
+                    Ok(dir) => dir.trim().to_string(),
+                    Err(e) => return Err((5, e.body_text())),
+                };
+                info_span!("Got directory name", dir);
+                directory = Some(dir);
+            } else {
+                warn_span!("Unknown field", ?field);
+            }
+        }
+
+        match directory {
+            Some(directory) => Ok(FileUpload { directory, files }),
+            None => Err((2, "Directory field cannot be empty".to_string())),
+        }
+    }
+}
+
+/// Upload sent in chunks, which can be resumed at its offset when the connection was lost
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct PartialUpload {
+    /// Path of the file in the tree once the upload is complete
+    path: String,
+    size: u64,
+    /// Amount of bytes received
+    offset: u64,
+    /// When the last chunk was received, uploads recorded before this was kept count from when Sasta started
+    #[serde(default = "Utc::now")]
+    updated: DateTime<Utc>,
+}
+
+/// Progress of a resumable upload, the next chunk is sent from `offset`
+#[derive(Serialize, Deserialize, Debug, PartialEq, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/files/")]
+pub struct UploadProgress {
+    #[ts(type = "string")]
+    pub id: Uuid,
+    pub path: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Upload being received, whose size is known without waiting for the chunks it is receiving
+struct OpenUpload {
+    size: u64,
+    upload: Arc<AsyncMutex<PartialUpload>>,
+}
+
+impl From<PartialUpload> for OpenUpload {
+    fn from(upload: PartialUpload) -> Self {
+        OpenUpload {
+            size: upload.size,
+            upload: Arc::new(AsyncMutex::new(upload)),
+        }
+    }
+}
+
+/// Partial uploads, written to their own file in `dir` and recorded in Redis so they survive restarts
+pub struct ResumableUploads {
+    #[cfg(not(test))]
+    con: AsyncMutex<MultiplexedConnection>,
+    dir: PathBuf,
+    /// Each upload has its own lock, so chunks of different uploads are received at the same time
+    uploads: AsyncMutex<HashMap<Uuid, OpenUpload>>,
+}
+
+impl ResumableUploads {
+    /// Resumes the uploads recorded in Redis, continuing from the bytes found on disk
+    #[cfg(not(test))]
+    pub async fn new(mut con: MultiplexedConnection, dir: PathBuf) -> Self {
+        fs::create_dir_all(&dir).await.unwrap();
+        let recorded = match con.hgetall::<_, HashMap<String, String>>("uploads").await {
+            Ok(recorded) => recorded
+                .into_iter()
+                .filter_map(|(id, upload)| {
+                    Some((
+                        Uuid::parse_str(&id).ok()?,
+                        serde_json::from_str(&upload).ok()?,
+                    ))
+                })
+                .collect(),
+            Err(e) => {
+                warn!("Could not read partial uploads, they have to be started again ({e})");
+                HashMap::new()
+            }
+        };
+        let uploads = Self {
+            con: AsyncMutex::new(con),
+            dir,
+            uploads: AsyncMutex::new(HashMap::new()),
+        };
+        uploads.resume(recorded).await;
+        uploads
+    }
+
+    #[cfg(test)]
+    pub async fn new(dir: PathBuf) -> Self {
+        fs::create_dir_all(&dir).await.unwrap();
+        Self {
+            dir,
+            uploads: AsyncMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keeps the recorded uploads with a file on disk, and removes files without a recorded upload
+    ///
+    /// The offset is taken from the file, which may be ahead of Redis if Sasta stopped while receiving a chunk.
+    #[cfg(not(test))]
+    async fn resume(&self, recorded: HashMap<Uuid, PartialUpload>) {
+        let mut uploads = self.uploads.lock().await;
+        for (id, mut upload) in recorded {
+            match fs::metadata(self.dir.join(id.to_string())).await {
+                Ok(metadata) if metadata.len() <= upload.size => {
+                    upload.offset = metadata.len();
+                    self.record(id, &upload).await;
+                    uploads.insert(id, upload.into());
+                }
+                _ => self.forget(id).await,
+            }
+        }
+
+        let Ok(mut entries) = fs::read_dir(&self.dir).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let known = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| Uuid::parse_str(name).ok())
+                .is_some_and(|id| uploads.contains_key(&id));
+            if !known {
+                let _ = fs::remove_file(entry.path()).await;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    async fn record(&self, _id: Uuid, _upload: &PartialUpload) {
+        // Do not write to any db when in test environment
+    }
+
+    #[cfg(not(test))]
+    async fn record(&self, id: Uuid, upload: &PartialUpload) {
+        if let Err(error) = self
+            .con
+            .lock()
+            .await
+            .hset::<_, _, _, ()>(
+                "uploads",
+                id.to_string(),
+                serde_json::to_string(upload).unwrap(),
+            )
+            .await
+        {
+            error_span!("Redis Error", ?error);
+        }
+    }
+
+    #[cfg(test)]
+    async fn forget(&self, _id: Uuid) {
+        // Do not write to any db when in test environment
+    }
+
+    #[cfg(not(test))]
+    async fn forget(&self, id: Uuid) {
+        if let Err(error) = self
+            .con
+            .lock()
+            .await
+            .hdel::<_, _, ()>("uploads", id.to_string())
+            .await
+        {
+            error_span!("Redis Error", ?error);
+        }
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Arc<AsyncMutex<PartialUpload>>, (u8, String)> {
+        self.uploads
+            .lock()
+            .await
+            .get(&id)
+            .map(|open| open.upload.clone())
+            .ok_or_else(|| (1, format!("No upload with the id {id} was found")))
+    }
+
+    /// Starts an upload of `size` bytes, to be added at the path once complete
+    ///
+    /// The other open uploads count as stored, so together they cannot be larger than the storage left.
+    pub async fn create(
+        &self,
+        path: String,
+        size: u64,
+        max_file_size: Option<u64>,
+        storage: Option<StorageLeft>,
+    ) -> Result<UploadProgress, (u8, String)> {
+        if let Some(max_file_size) = max_file_size.filter(|max| size > *max) {
+            return Err((
+                6,
+                format!("Files may not be larger than {max_file_size} bytes"),
+            ));
+        }
+        // Held until the upload is added, so uploads started at the same time are counted
+        let mut uploads = self.uploads.lock().await;
+        if let Some(storage) = storage {
+            let open = uploads.values().map(|u| u.size).sum::<u64>();
+            if size > storage.max_size().saturating_sub(open) {
+                return Err((
+                    7,
+                    "Not enough storage is free to store files this large".to_string(),
+                ));
+            }
+        }
+        let id = Uuid::new_v4();
+        fs::File::create(self.dir.join(id.to_string()))
+            .await
+            .map_err(|e| (8, format!("Could not store upload ({e})")))?;
+        let upload = PartialUpload {
+            path,
+            size,
+            offset: 0,
+            updated: Utc::now(),
+        };
+        self.record(id, &upload).await;
+        let progress = upload.progress(id);
+        uploads.insert(id, upload.into());
+        Ok(progress)
+    }
+
+    pub async fn progress(&self, id: Uuid) -> Result<UploadProgress, (u8, String)> {
+        Ok(self.get(id).await?.lock().await.progress(id))
+    }
+
+    /// Appends the chunks to the upload, which must be sent from the current offset
+    ///
+    /// Bytes received before the connection was lost are kept, so the upload continues from there.
+    pub async fn append<E: Display>(
+        &self,
+        id: Uuid,
+        offset: u64,
+        mut chunks: impl Stream<Item = Result<Bytes, E>> + Unpin,
+    ) -> Result<UploadProgress, (u8, String)> {
+        let upload = self.get(id).await?;
+        let mut upload = upload.lock().await;
+        if offset != upload.offset {
+            return Err((
+                3,
+                format!(
+                    "Upload {id} continues at offset {}, not {offset}",
+                    upload.offset
+                ),
+            ));
+        }
+
+        let io_error = |e: io::Error| (8, format!("Could not store upload ({e})"));
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(self.dir.join(id.to_string()))
+            .await
+            .map_err(io_error)?;
+        // Drops bytes of a chunk which was cut off before it was recorded
+        file.set_len(upload.offset).await.map_err(io_error)?;
+        file.seek(io::SeekFrom::End(0)).await.map_err(io_error)?;
+
+        let mut result = Ok(());
+        while let Some(chunk) = chunks.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    result = Err((5, e.to_string()));
+                    break;
+                }
+            };
+            if upload.offset + chunk.len() as u64 > upload.size {
+                result = Err((
+                    4,
+                    format!("Upload {id} is only {} bytes large", upload.size),
+                ));
+                break;
+            }
+            if let Err(e) = file.write_all(&chunk).await {
+                result = Err(io_error(e));
+                break;
+            }
+            upload.offset += chunk.len() as u64;
+        }
+        upload.updated = Utc::now();
+        if let Err(e) = file.sync_all().await {
+            warn!("Could not sync upload {id} ({e})");
+        }
+        self.record(id, &upload).await;
+        result.map(|_| upload.progress(id))
+    }
+
+    /// Ends the complete upload, returning the path it is added at and its content
+    pub async fn finish(&self, id: Uuid) -> Result<(String, StagedFile), (u8, String)> {
+        let upload = self.get(id).await?;
+        let upload = upload.lock().await.clone();
+        if upload.offset != upload.size {
+            return Err((
+                4,
+                format!(
+                    "Upload {id} has received {} of {} bytes",
+                    upload.offset, upload.size
+                ),
+            ));
+        }
+        self.uploads.lock().await.remove(&id);
+        self.forget(id).await;
+
+        let path = self.dir.join(id.to_string());
+        let hash_path = path.clone();
+        let hash = tokio::task::spawn_blocking(move || blob::hash_file(&hash_path))
+            .await
+            .map_err(|e| (8, e.to_string()))?
+            .map_err(|e| (8, format!("Could not read upload ({e})")))?;
+        Ok((upload.path, StagedFile::existing(path, upload.size, hash)))
+    }
+
+    /// Takes back a complete upload returned by `finish` which could not be added, so it can be finished later
+    pub async fn restore(&self, id: Uuid, path: String, content: StagedFile) {
+        let upload = PartialUpload {
+            path,
+            size: content.size(),
+            offset: content.size(),
+            updated: Utc::now(),
+        };
+        content.keep();
+        self.record(id, &upload).await;
+        self.uploads.lock().await.insert(id, upload.into());
+    }
+
+    /// Cancels the uploads which received nothing for a day, returning how many were cancelled
+    ///
+    /// Uploads receiving a chunk right now are skipped.
+    pub async fn sweep(&self, now: DateTime<Utc>) -> usize {
+        let abandoned = self
+            .uploads
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, open)| {
+                open.upload
+                    .try_lock()
+                    .is_ok_and(|u| u.updated + ABANDONED_AFTER <= now)
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in &abandoned {
+            let _ = self.cancel(*id).await;
+        }
+        abandoned.len()
+    }
+
+    /// Stops the upload and deletes what was received
+    pub async fn cancel(&self, id: Uuid) -> Result<(), (u8, String)> {
+        let upload = self.get(id).await?;
+        // Waits for chunks being received
+        let _upload = upload.lock().await;
+        self.uploads.lock().await.remove(&id);
+        self.forget(id).await;
+        if let Err(e) = fs::remove_file(self.dir.join(id.to_string())).await {
+            warn!("Could not delete upload {id} ({e})");
+        }
+        Ok(())
+    }
+}
+
+impl PartialUpload {
+    fn progress(&self, id: Uuid) -> UploadProgress {
+        UploadProgress {
+            id,
+            path: self.path.clone(),
+            offset: self.offset,
+            size: self.size,
+        }
+    }
+}
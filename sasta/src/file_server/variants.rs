@@ -0,0 +1,302 @@
+use std::io::Cursor;
+
+use image::{
+    AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader,
+    codecs::{gif::GifDecoder, jpeg::JpegEncoder},
+    imageops::FilterType,
+};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// Smaller copy of an uploaded image, so displays and the file browser need not load the original
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export, export_to = "api_bindings/files/")]
+pub enum ImageVariant {
+    #[serde(rename = "thumbnail")]
+    Thumbnail,
+    #[serde(rename = "1080p")]
+    Hd,
+    #[serde(rename = "4k")]
+    Uhd,
+}
+
+impl ImageVariant {
+    /// Every variant, from smallest to largest
+    pub const ALL: [ImageVariant; 3] =
+        [ImageVariant::Thumbnail, ImageVariant::Hd, ImageVariant::Uhd];
+
+    /// Longest and shortest side the variant fits in, independent of the orientation of the image
+    pub fn bounds(self) -> (u32, u32) {
+        match self {
+            ImageVariant::Thumbnail => (320, 320),
+            ImageVariant::Hd => (1920, 1080),
+            ImageVariant::Uhd => (3840, 2160),
+        }
+    }
+
+    /// Name used in the file name of the variant on disk
+    pub fn suffix(self) -> &'static str {
+        match self {
+            ImageVariant::Thumbnail => "thumbnail",
+            ImageVariant::Hd => "1080p",
+            ImageVariant::Uhd => "4k",
+        }
+    }
+
+    fn fits(self, width: u32, height: u32) -> bool {
+        let (long, short) = self.bounds();
+        width.max(height) <= long && width.min(height) <= short
+    }
+}
+
+/// Variant of an image stored next to the original
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Variant {
+    pub kind: ImageVariant,
+    /// Actual filename on disk
+    ///
+    /// `{hash}-{extension of the original}-{variant}.{ext}`, named after the blob it was made from
+    /// since blobs with the same hash but another extension are counted apart
+    pub file_server: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Variant made from an image, not yet written to disk
+pub struct EncodedVariant {
+    pub kind: ImageVariant,
+    pub width: u32,
+    pub height: u32,
+    pub extension: &'static str,
+    pub content: Vec<u8>,
+}
+
+/// Query of a file request, asking for a variant by name or for one covering a screen
+#[derive(Deserialize, Debug, Default)]
+pub struct VariantQuery {
+    pub variant: Option<ImageVariant>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Hash of the content the url was made for, see `file_server::get_file`
+    #[serde(rename = "v")]
+    pub version: Option<String>,
+}
+
+/// Whether variants are made of files with the extension
+pub fn is_image(extension: &str) -> bool {
+    ["jpg", "jpeg", "png", "webp", "gif"].contains(&extension.to_lowercase().as_str())
+}
+
+/// Decodes the image and scales it down to the variants it is larger than
+///
+/// Variants are turned upright according to the EXIF orientation of the image and carry no metadata,
+/// so clients ignoring the orientation show them the right way up. The thumbnail is always made,
+/// as is the first variant the image fits in if the image had to be turned. Animated GIFs only get
+/// a thumbnail, since the other variants would only keep the first frame.
+pub fn encode(content: &[u8]) -> Result<Vec<EncodedVariant>, String> {
+    let reader = ImageReader::new(Cursor::new(content))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    let animated = reader.format() == Some(ImageFormat::Gif) && is_animated_gif(content);
+    let mut decoder = reader.into_decoder().map_err(|e| e.to_string())?;
+    let orientation = decoder.orientation().map_err(|e| e.to_string())?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    let turned = orientation != image::metadata::Orientation::NoTransforms;
+    image.apply_orientation(orientation);
+
+    let mut variants = vec![];
+    // Whether the previous variant was smaller than the image
+    let mut scaled = true;
+    for kind in ImageVariant::ALL {
+        let fits = kind.fits(image.width(), image.height());
+        if kind != ImageVariant::Thumbnail && (animated || fits && !(turned && scaled)) {
+            break;
+        }
+
+        let variant = if fits {
+            image.clone()
+        } else {
+            let (long, short) = kind.bounds();
+            let (width, height) = if image.width() >= image.height() {
+                (long, short)
+            } else {
+                (short, long)
+            };
+            let filter = match kind {
+                ImageVariant::Thumbnail => FilterType::Triangle,
+                _ => FilterType::Lanczos3,
+            };
+            image.resize(width, height, filter)
+        };
+
+        // Transparent images are kept as PNG, since JPEG has no alpha channel
+        let mut content = Cursor::new(vec![]);
+        let extension = if variant.color().has_alpha() {
+            variant
+                .write_to(&mut content, image::ImageFormat::Png)
+                .map_err(|e| e.to_string())?;
+            "png"
+        } else {
+            variant
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut content, 85))
+                .map_err(|e| e.to_string())?;
+            "jpg"
+        };
+        variants.push(EncodedVariant {
+            kind,
+            width: variant.width(),
+            height: variant.height(),
+            extension,
+            content: content.into_inner(),
+        });
+        scaled = !fits;
+    }
+    Ok(variants)
+}
+
+fn is_animated_gif(content: &[u8]) -> bool {
+    GifDecoder::new(Cursor::new(content))
+        .is_ok_and(|decoder| decoder.into_frames().take(2).count() > 1)
+}
+
+/// Picks the variant asked for, None if the original should be sent
+///
+/// A screen size picks the smallest variant reaching either side of it, in either orientation,
+/// since that side limits how large an image scaled to fit the screen is shown.
+pub fn pick<'a>(variants: &'a [Variant], query: &VariantQuery) -> Option<&'a Variant> {
+    if let Some(kind) = query.variant {
+        return variants.iter().find(|v| v.kind == kind);
+    }
+    let (width, height) = (query.width?, query.height?);
+    let (long, short) = (width.max(height), width.min(height));
+    variants
+        .iter()
+        .filter(|v| v.kind != ImageVariant::Thumbnail)
+        .filter(|v| v.width.max(v.height) >= long || v.width.min(v.height) >= short)
+        .min_by_key(|v| v.width * v.height)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use image::{DynamicImage, Frame, ImageFormat, RgbImage, RgbaImage, codecs::gif::GifEncoder};
+
+    use super::{ImageVariant, Variant, VariantQuery, encode, pick};
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut content = Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut content, ImageFormat::Jpeg)
+            .unwrap();
+        content.into_inner()
+    }
+
+    /// Adds an EXIF segment with the given orientation right after the start of the JPEG
+    fn with_orientation(jpeg: Vec<u8>, orientation: u8) -> Vec<u8> {
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0".to_vec();
+        exif.extend([orientation, 0, 0, 0, 0, 0, 0]);
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend(((exif.len() + 2) as u16).to_be_bytes());
+        segment.extend(exif);
+        [&jpeg[..2], &segment, &jpeg[2..]].concat()
+    }
+
+    fn sizes(content: &[u8]) -> Vec<(ImageVariant, u32, u32)> {
+        encode(content)
+            .unwrap()
+            .iter()
+            .map(|v| (v.kind, v.width, v.height))
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_variants() {
+        assert_eq!(
+            vec![
+                (ImageVariant::Thumbnail, 320, 16),
+                (ImageVariant::Hd, 1920, 96),
+                (ImageVariant::Uhd, 3840, 192),
+            ],
+            sizes(&jpeg(4000, 200))
+        );
+        assert_eq!(
+            vec![(ImageVariant::Thumbnail, 240, 320)],
+            sizes(&jpeg(300, 400))
+        );
+
+        // Turned a quarter clockwise, so the image is upright in the variants
+        assert_eq!(
+            vec![
+                (ImageVariant::Thumbnail, 240, 320),
+                (ImageVariant::Hd, 600, 800),
+            ],
+            sizes(&with_orientation(jpeg(800, 600), 6))
+        );
+
+        assert!(encode(b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_animated_gif_only_gets_thumbnail() {
+        let gif = |frames: usize| {
+            let mut content = vec![];
+            let mut encoder = GifEncoder::new(&mut content);
+            for _ in 0..frames {
+                encoder
+                    .encode_frame(Frame::new(RgbaImage::new(2000, 1500)))
+                    .unwrap();
+            }
+            drop(encoder);
+            content
+        };
+
+        assert_eq!(vec![(ImageVariant::Thumbnail, 320, 240)], sizes(&gif(2)));
+        assert_eq!(
+            vec![
+                (ImageVariant::Thumbnail, 320, 240),
+                (ImageVariant::Hd, 1440, 1080),
+            ],
+            sizes(&gif(1))
+        );
+    }
+
+    #[test]
+    fn test_pick_variant() {
+        let variant = |kind, width, height| Variant {
+            kind,
+            file_server: String::new(),
+            width,
+            height,
+        };
+        let variants = [
+            variant(ImageVariant::Thumbnail, 320, 240),
+            variant(ImageVariant::Hd, 1440, 1080),
+            variant(ImageVariant::Uhd, 2880, 2160),
+        ];
+        let query = |variant, width, height| VariantQuery {
+            variant,
+            width,
+            height,
+            version: None,
+        };
+
+        let picked = |q| pick(&variants, &q).map(|v| v.kind);
+        assert_eq!(
+            Some(ImageVariant::Thumbnail),
+            picked(query(Some(ImageVariant::Thumbnail), None, None))
+        );
+        assert_eq!(
+            Some(ImageVariant::Hd),
+            picked(query(None, Some(1920), Some(1080)))
+        );
+        // Portrait screens are covered by the same variants
+        assert_eq!(
+            Some(ImageVariant::Uhd),
+            picked(query(None, Some(2160), Some(3840)))
+        );
+        assert_eq!(None, picked(query(None, Some(7680), Some(4320))));
+        assert_eq!(None, picked(query(None, None, None)));
+    }
+}
@@ -1,4 +1,6 @@
-use std::{collections::HashSet, env, net::SocketAddr, str::FromStr, sync::Arc, vec};
+use std::{
+    collections::HashSet, env, net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc, vec,
+};
 
 use axum::{
     Json, Router,
@@ -7,7 +9,7 @@ use axum::{
     routing::get,
 };
 use axum_macros::debug_handler;
-use chrono::Local;
+use chrono::TimeDelta;
 use hyper::StatusCode;
 use read::Payload;
 use store::{schedule::Moment, store::Store};
@@ -26,9 +28,22 @@ use uuid::Uuid;
 
 use crate::{
     casta::casta::{casta_index, compute_hash, minify},
-    connection::connection::client_connection,
-    file_server::file_server::{FileServer, file_api_router, get_file},
-    store::{schedule, store::DisplayMaterial},
+    connection::{
+        commands::{Connections, command_api_router},
+        connection::{ASTA_FLE_PREFIX, client_connection},
+    },
+    file_server::{
+        file_server::{FileIndex, FileServer, file_api_router, get_file},
+        pdf,
+        upload::Quota,
+    },
+    store::{
+        clock::SystemClock,
+        explain::{self, CronCheck, CronValidation},
+        playback::PlaybackMode,
+        schedule,
+        store::{DisplayMaterial, DisplaySettings, PlaylistItem, Transition},
+    },
 };
 
 mod casta;
@@ -53,6 +68,9 @@ impl From<(u8, String)> for read::Payload {
 pub struct AppState {
     store: Arc<Store>,
     file_server: Arc<Mutex<FileServer>>,
+    /// Finds files to download without waiting for the lock of the FileServer
+    files: FileIndex,
+    connections: Arc<Connections>,
     htmx_hash: String,
 }
 
@@ -81,19 +99,45 @@ async fn main() {
     let redis_url = env::var("REDIS_URL").expect("REDIS_URL variable must be set");
     let sasta_address = env::var("ADDRESS").unwrap_or("127.0.0.1:8080".into());
     let sasta_file_path = env::var("FILE_PATH").unwrap_or("./files".into());
+    let quota = Quota {
+        max_file_size: env::var("MAX_FILE_SIZE")
+            .ok()
+            .map(|v| v.parse().expect("MAX_FILE_SIZE must be a number of bytes")),
+        max_storage: env::var("MAX_STORAGE")
+            .ok()
+            .map(|v| v.parse().expect("MAX_STORAGE must be a number of bytes")),
+    };
+    let trash_retention_days = env::var("TRASH_RETENTION_DAYS")
+        .ok()
+        .map(|v| {
+            v.parse()
+                .expect("TRASH_RETENTION_DAYS must be a number of days")
+        })
+        .unwrap_or(30);
+    let calendar_path = env::var("CALENDAR_PATH").ok().map(PathBuf::from);
     tracing_subscriber::fmt::init();
     info!("REDIS_URL={redis_url}");
     info!("ADDRESS={sasta_address}");
     info!("FILE_PATH={sasta_file_path}");
+    info!("{quota:?}");
+    info!("TRASH_RETENTION_DAYS={trash_retention_days}");
+    info!("CALENDAR_PATH={calendar_path:?}");
     minify();
     info!("JS and CSS minified");
     let htmx_hash = compute_hash();
     info!("Computed Hash for Casta Htmx");
 
-    let store = Arc::new(Store::new(&redis_url).await);
-    let file_server = Arc::new(Mutex::new(
-        FileServer::new(&redis_url, sasta_file_path).await,
-    ));
+    let clock = Arc::new(SystemClock);
+    let store = Arc::new(Store::new(&redis_url, clock.clone()).await);
+    let file_server = FileServer::new(
+        &redis_url,
+        sasta_file_path,
+        quota,
+        TimeDelta::days(trash_retention_days),
+    )
+    .await;
+    let files = file_server.index();
+    let file_server = Arc::new(Mutex::new(file_server));
 
     let store_copy = store.clone();
     let (tx, rx) = oneshot::channel::<()>();
@@ -104,11 +148,38 @@ async fn main() {
 
     rx.await.unwrap();
 
+    let store_copy = store.clone();
+    tokio::spawn(async move {
+        store_copy.feed_loop().await;
+    });
+
+    let store_copy = store.clone();
+    tokio::spawn(async move {
+        store_copy.calendar_loop(calendar_path).await;
+    });
+
+    let file_server_copy = file_server.clone();
+    tokio::spawn(async move {
+        FileServer::scrub_loop(file_server_copy).await;
+    });
+
+    let file_server_copy = file_server.clone();
+    tokio::spawn(async move {
+        FileServer::trash_loop(file_server_copy).await;
+    });
+
+    let file_server_copy = file_server.clone();
+    tokio::spawn(async move {
+        FileServer::upload_loop(file_server_copy).await;
+    });
+
     info!("{}", store.to_string().await);
 
     let app_state = AppState {
         store,
         file_server,
+        files,
+        connections: Arc::new(Connections::new(clock)),
         htmx_hash,
     };
 
@@ -121,6 +192,7 @@ async fn main() {
                 .routes(routes!(create_display))
                 .routes(routes!(update_display))
                 .routes(routes!(delete_display))
+                .merge(command_api_router())
                 // Playlist
                 .routes(routes!(read_playlist))
                 .routes(routes!(create_playlist))
@@ -130,6 +202,7 @@ async fn main() {
                 .routes(routes!(read_schedule))
                 .routes(routes!(create_schedule))
                 .routes(routes!(schedule_info))
+                .routes(routes!(check_cron))
                 .routes(routes!(update_schedule))
                 .routes(routes!(delete_schedule))
                 // Files
@@ -199,8 +272,10 @@ mod read {
     use uuid::Uuid;
 
     use crate::store::{
+        calendar::CalendarInput,
+        playback::PlaybackMode,
         schedule,
-        store::{self, DisplayMaterial},
+        store::{self, DisplayMaterial, DisplaySettings, Transition},
     };
 
     pub type Response = Result<Json<Payload>, (StatusCode, Json<Payload>)>;
@@ -223,6 +298,9 @@ mod read {
         pub uuid: Uuid,
         pub name: String,
         pub display_material: DisplayMaterial,
+        pub sync_group: Option<String>,
+        pub transition: Transition,
+        pub settings: DisplaySettings,
     }
 
     impl From<(Uuid, store::Display)> for Display {
@@ -231,6 +309,9 @@ mod read {
                 uuid,
                 name: d.name,
                 display_material: d.display_material,
+                sync_group: d.sync_group,
+                transition: d.transition,
+                settings: d.settings,
             }
         }
     }
@@ -242,6 +323,7 @@ mod read {
         pub uuid: Uuid,
         pub name: String,
         pub items: Vec<store::PlaylistItem>,
+        pub playback: PlaybackMode,
     }
 
     impl From<(Uuid, store::Playlist)> for Playlist {
@@ -250,6 +332,7 @@ mod read {
                 uuid,
                 name: p.name,
                 items: p.items,
+                playback: p.playback,
             }
         }
     }
@@ -263,6 +346,9 @@ mod read {
         #[ts(type = "string")]
         pub playlist: Uuid,
         pub scheduled: Option<Vec<schedule::ScheduledPlaylistInput>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[ts(optional)]
+        pub calendar: Option<CalendarInput>,
     }
 
     impl From<(Uuid, schedule::Schedule)> for Schedule {
@@ -273,6 +359,7 @@ mod read {
                 name: s.name,
                 playlist: s.playlist,
                 scheduled: s.scheduled,
+                calendar: s.calendar,
             }
         }
     }
@@ -283,6 +370,10 @@ mod read {
         #[ts(type = "string")]
         pub current: Uuid,
         pub next: Option<NextMoment>,
+        /// Set if the Schedule can not decide when its active playlist changes next
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[ts(optional)]
+        pub error: Option<schedule::ScheduleError>,
     }
 
     #[derive(Serialize, TS, ToSchema)]
@@ -302,7 +393,7 @@ mod create {
     use uuid::Uuid;
 
     pub use crate::read::Response;
-    use crate::store::store::DisplayMaterial;
+    use crate::store::store::{DisplayMaterial, DisplaySettings, Transition};
 
     #[derive(Debug, Deserialize, TS, ToSchema)]
     #[ts(export, export_to = "api_bindings/create/", rename = "CreateDisplay")]
@@ -311,6 +402,12 @@ mod create {
         pub uuid: Option<Uuid>,
         pub name: String,
         pub display_material: DisplayMaterial,
+        #[ts(optional)]
+        pub sync_group: Option<String>,
+        #[ts(optional)]
+        pub transition: Option<Transition>,
+        #[ts(optional)]
+        pub settings: Option<DisplaySettings>,
     }
 
     #[derive(Deserialize, TS, ToSchema)]
@@ -329,15 +426,19 @@ mod create {
 }
 
 mod update {
-    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Deserializer};
     use ts_rs::TS;
     use utoipa::ToSchema;
     use uuid::Uuid;
 
     pub use crate::read::{Payload, Response};
     use crate::store::{
+        calendar::CalendarInput,
+        playback::PlaybackMode,
         schedule,
-        store::{self, DisplayMaterial},
+        store::{self, DisplayMaterial, DisplaySettings, Transition},
     };
 
     #[derive(Deserialize, ToSchema, TS)]
@@ -346,6 +447,41 @@ mod update {
     pub struct Display {
         pub name: String,
         pub display_material: DisplayMaterial,
+        /// Keeps the current sync group if not given, leaves the sync group if `null`
+        #[serde(default, deserialize_with = "present")]
+        #[ts(optional, type = "string | null")]
+        #[schema(value_type = Option<String>, nullable)]
+        pub sync_group: Option<Option<String>>,
+        /// Keeps the current transition if not given
+        #[ts(optional)]
+        pub transition: Option<Transition>,
+        /// Keeps the current settings if not given
+        #[ts(optional)]
+        pub settings: Option<DisplaySettings>,
+    }
+
+    /// Deserializes a field which is present, telling a missing field apart from one set to `null`
+    fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        T::deserialize(deserializer).map(Some)
+    }
+
+    impl Display {
+        /// The Display after the update, keeping the current value of the fields not given
+        pub fn apply(self, current: &store::Display) -> store::Display {
+            store::Display {
+                name: self.name,
+                display_material: self.display_material,
+                sync_group: self
+                    .sync_group
+                    .unwrap_or_else(|| current.sync_group.clone()),
+                transition: self.transition.unwrap_or(current.transition),
+                settings: self.settings.unwrap_or_else(|| current.settings.clone()),
+            }
+        }
     }
 
     #[derive(Deserialize, ToSchema, TS)]
@@ -354,6 +490,9 @@ mod update {
     pub struct Playlist {
         pub name: String,
         pub items: Vec<store::PlaylistItem>,
+        /// Keeps the current playback mode if not given
+        #[ts(optional)]
+        pub playback: Option<PlaybackMode>,
     }
 
     #[derive(Deserialize, ToSchema, TS)]
@@ -365,6 +504,106 @@ mod update {
         pub playlist: Uuid,
         #[ts(optional)]
         pub scheduled: Option<Vec<schedule::ScheduledPlaylistInput>>,
+        /// Calendar whose events decide the active playlist, taking priority over `scheduled`
+        #[ts(optional)]
+        pub calendar: Option<CalendarInput>,
+    }
+
+    impl Schedule {
+        /// First Playlist used by the Schedule, scheduled or by a calendar rule, which does not exist
+        pub fn unknown_playlist(&self, playlists: &HashMap<Uuid, store::Playlist>) -> Option<Uuid> {
+            let scheduled = self.scheduled.iter().flatten().map(|s| s.playlist);
+            let calendar = self
+                .calendar
+                .iter()
+                .flat_map(|c| c.rules.iter().map(|r| r.playlist));
+            std::iter::once(self.playlist)
+                .chain(scheduled)
+                .chain(calendar)
+                .find(|uuid| !playlists.contains_key(uuid))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::json;
+
+        use super::*;
+
+        #[test]
+        fn test_missing_fields_keep_current_value() {
+            let current: store::Display = serde_json::from_value(json!({
+                "name": "Lobby",
+                "display_material": { "type": "playlist", "uuid": "00000000-0000-0000-0000-000000000001" },
+                "sync_group": "hall",
+                "transition": { "type": "crossfade", "duration": 500 },
+                "settings": { "rotation": 90 }
+            }))
+            .unwrap();
+            let update = |value| {
+                serde_json::from_value::<Display>(value)
+                    .unwrap()
+                    .apply(&current)
+            };
+            let material = current.display_material.clone();
+
+            let kept = update(json!({ "name": "Hall", "display_material": material }));
+            assert_eq!("Hall", kept.name);
+            assert_eq!(Some("hall".to_string()), kept.sync_group);
+            assert_eq!(current.transition, kept.transition);
+            assert_eq!(current.settings, kept.settings);
+
+            let changed = update(json!({
+                "name": "Hall",
+                "display_material": material,
+                "sync_group": "entrance",
+                "transition": { "type": "cut" },
+                "settings": {}
+            }));
+            assert_eq!(Some("entrance".to_string()), changed.sync_group);
+            assert_eq!(store::Transition::Cut, changed.transition);
+            assert_eq!(DisplaySettings::default(), changed.settings);
+
+            let left = update(json!({
+                "name": "Hall",
+                "display_material": material,
+                "sync_group": null
+            }));
+            assert_eq!(None, left.sync_group);
+        }
+
+        #[test]
+        fn test_unknown_playlists_are_found() {
+            let known = Uuid::from_u128(1);
+            let unknown = Uuid::from_u128(2);
+            let playlists = HashMap::from([(
+                known,
+                store::Playlist {
+                    name: "default".to_string(),
+                    items: vec![],
+                    playback: PlaybackMode::default(),
+                },
+            )]);
+            let schedule = |scheduled: Uuid, rule: Uuid| {
+                serde_json::from_value::<Schedule>(json!({
+                    "name": "week",
+                    "playlist": known,
+                    "scheduled": [{ "playlist": scheduled, "start": "0 0 10 * * *", "end": "0 0 12 * * *" }],
+                    "calendar": { "source": "https://example.com/cal.ics", "rules": [{ "playlist": rule }] }
+                }))
+                .unwrap()
+            };
+
+            assert_eq!(None, schedule(known, known).unknown_playlist(&playlists));
+            assert_eq!(
+                Some(unknown),
+                schedule(unknown, known).unknown_playlist(&playlists)
+            );
+            assert_eq!(
+                Some(unknown),
+                schedule(known, unknown).unknown_playlist(&playlists)
+            );
+        }
     }
 }
 
@@ -424,6 +663,12 @@ async fn create_display(
     }
     drop(read);
 
+    let settings = disp.settings.unwrap_or_default();
+    if let Err(e) = settings.validate() {
+        error!("[Api] Invalid settings: {e}");
+        return Err((StatusCode::BAD_REQUEST, Json((4, e).into())));
+    }
+
     let uuid = match disp.uuid {
         Some(u) => u,
         None => Uuid::new_v4(),
@@ -431,7 +676,14 @@ async fn create_display(
     info!("[Api] Using Uuid {uuid} for new Display");
 
     if let Err(e) = store
-        .create_display(uuid, disp.name, disp.display_material)
+        .create_display(
+            uuid,
+            disp.name,
+            disp.display_material,
+            disp.sync_group,
+            disp.transition.unwrap_or_default(),
+            settings,
+        )
         .await
     {
         return Err((
@@ -586,9 +838,9 @@ async fn create_schedule(
         (status = 200, description = "Get all Displays", body = inline(Vec<read::Display>),
             example = json!(
                 read::Payload::Display(vec![
-                        read::Display { uuid: Uuid::new_v4(), name: "name1".into(), display_material: DisplayMaterial::Schedule(Uuid::new_v4()) },
-                        read::Display { uuid: Uuid::new_v4(), name: "name2".into(), display_material: DisplayMaterial::Playlist(Uuid::new_v4()) },
-                        read::Display { uuid: Uuid::new_v4(), name: "name3".into(), display_material: DisplayMaterial::Schedule(Uuid::new_v4()) }
+                        read::Display { uuid: Uuid::new_v4(), name: "name1".into(), display_material: DisplayMaterial::Schedule(Uuid::new_v4()), sync_group: None, transition: Transition::Cut, settings: DisplaySettings::default() },
+                        read::Display { uuid: Uuid::new_v4(), name: "name2".into(), display_material: DisplayMaterial::Playlist(Uuid::new_v4()), sync_group: None, transition: Transition::Cut, settings: DisplaySettings::default() },
+                        read::Display { uuid: Uuid::new_v4(), name: "name3".into(), display_material: DisplayMaterial::Schedule(Uuid::new_v4()), sync_group: None, transition: Transition::Cut, settings: DisplaySettings::default() }
                 ])
             )
         ),
@@ -623,8 +875,8 @@ async fn read_displays(State(state): State<AppState>) -> Json<Vec<read::Display>
                                     duration: 60u64
                                 }
                             }
-                        ] },
-                        read::Playlist { uuid: Uuid::new_v4(), name: "name2".into(), items: vec![] }
+                        ], playback: PlaybackMode::Sequential },
+                        read::Playlist { uuid: Uuid::new_v4(), name: "name2".into(), items: vec![], playback: PlaybackMode::Shuffle }
                 ])
             )
         ),
@@ -657,8 +909,8 @@ async fn read_playlist(State(state): State<AppState>) -> Json<Vec<read::Playlist
                             start: "0 0 10 * * Mon-Fri *".into(),
                             end: "0 0 14 * * Mon-Fri *".into()
                         }
-                    ]) },
-                    read::Schedule { uuid: Uuid::new_v4(), name: "name2".into(), playlist: Uuid::new_v4(), scheduled: Some(vec![]) }
+                    ]), calendar: None },
+                    read::Schedule { uuid: Uuid::new_v4(), name: "name2".into(), playlist: Uuid::new_v4(), scheduled: Some(vec![]), calendar: None }
                 ])
             )
         ),
@@ -686,7 +938,7 @@ async fn read_schedule(State(state): State<AppState>) -> Json<Vec<read::Schedule
         (status = 200, description = "Display updated", body = inline(read::Payload),
             example = json!(
                 read::Payload::Display(vec![
-                        read::Display { uuid: Uuid::new_v4(), name: "name".into(), display_material: DisplayMaterial::Schedule(Uuid::new_v4()) }
+                        read::Display { uuid: Uuid::new_v4(), name: "name".into(), display_material: DisplayMaterial::Schedule(Uuid::new_v4()), sync_group: None, transition: Transition::Cut, settings: DisplaySettings::default() }
                 ])
             )
         ),
@@ -702,6 +954,12 @@ async fn read_schedule(State(state): State<AppState>) -> Json<Vec<read::Schedule
                 value = json!(
                     read::Payload::from((2, format!("Avoid using the name <name> as it is already used by another display")))
                 )
+            )),
+            ("error_4" = (
+                summary = "A setting is out of its range",
+                value = json!(
+                    read::Payload::from((4, "Rotation must be one of 0, 90, 180 and 270, not 45".to_string()))
+                )
             ))
         )),
     ),
@@ -717,12 +975,17 @@ async fn update_display(
     info!("[Api] Updating Display {uuid}");
     let store = state.store;
     let read = store.read().await;
-    if !read.displays.contains_key(&uuid) {
+    let Some(current) = read.displays.get(&uuid) else {
         error!("[Api] No display with {uuid} was found");
         return Err((
             StatusCode::BAD_REQUEST,
             Json((1, format!("No Display with the Uuid {uuid} was found")).into()),
         ));
+    };
+    let display = display.apply(current);
+    if let Err(e) = display.settings.validate() {
+        error!("[Api] Invalid settings: {e}");
+        return Err((StatusCode::BAD_REQUEST, Json((4, e).into())));
     }
     if let Some((uuid, _)) = read
         .displays
@@ -747,7 +1010,14 @@ async fn update_display(
     drop(read);
 
     if let Err(e) = store
-        .update_display(uuid, display.name, display.display_material)
+        .update_display(
+            uuid,
+            display.name,
+            display.display_material,
+            display.sync_group,
+            display.transition,
+            display.settings,
+        )
         .await
     {
         return Err((
@@ -776,6 +1046,38 @@ async fn update_display(
     };
 }
 
+/// Fills in the page count of PDF items paging through their document, and checks their pages
+///
+/// Documents stored by Sasta were counted when uploaded, others are downloaded to count their pages.
+async fn count_pages(
+    items: &mut [PlaylistItem],
+    file_server: &Mutex<FileServer>,
+) -> Result<(), String> {
+    let client = reqwest::Client::new();
+    for item in items.iter_mut() {
+        let PlaylistItem::PortableDocumentFormat { settings, .. } = item else {
+            continue;
+        };
+        if settings.pages.is_some() {
+            settings.page_count = match settings.src.strip_prefix(ASTA_FLE_PREFIX) {
+                Some(path) => {
+                    let path = format!("/{}", path.trim_start_matches('/'));
+                    file_server.lock().await.page_count(&path).await
+                }
+                None => match pdf::probe(&client, &settings.src).await {
+                    Ok(count) => Some(count),
+                    Err(e) => {
+                        error!("[Api] Could not count the pages of {} ({e})", settings.src);
+                        None
+                    }
+                },
+            };
+        }
+        settings.validate()?;
+    }
+    Ok(())
+}
+
 #[utoipa::path(
     put,
     path = "/playlist/{uuid}",
@@ -793,18 +1095,18 @@ async fn update_display(
 async fn update_playlist(
     State(state): State<AppState>,
     Path(uuid): Path<Uuid>,
-    Json(playlist): Json<update::Playlist>,
+    Json(mut playlist): Json<update::Playlist>,
 ) -> update::Response {
     info!("[Api] Updating Playlist {uuid}");
     let store = state.store;
     let read = store.read().await;
-    if !read.playlists.contains_key(&uuid) {
+    let Some(current) = read.playlists.get(&uuid) else {
         error!("[Api] No Playlist with {uuid} was found");
         return Err((
             StatusCode::BAD_REQUEST,
             Json((1, format!("No Playlist with the Uuid {uuid} was found")).into()),
         ));
-    }
+    };
     if let Some((uuid, _)) = read
         .playlists
         .iter()
@@ -826,10 +1128,24 @@ async fn update_playlist(
         ));
     }
 
+    let playback = playlist
+        .playback
+        .unwrap_or_else(|| current.playback.clone());
     drop(read);
 
+    // Documents are downloaded to count their pages, so only once the request is known to be for a Playlist
+    if let Err(e) = count_pages(&mut playlist.items, &state.file_server).await {
+        error!("[Api] Invalid pages: {e}");
+        return Err((StatusCode::BAD_REQUEST, Json((6, e).into())));
+    }
+    // The duration of documents depends on their amount of pages
+    if let Err(e) = playback.validate(&playlist.items) {
+        error!("[Api] Invalid playback mode: {e}");
+        return Err((StatusCode::BAD_REQUEST, Json((3, e).into())));
+    }
+
     if let Err(e) = store
-        .update_playlist(uuid, playlist.name, playlist.items)
+        .update_playlist(uuid, playlist.name, playlist.items, playback)
         .await
     {
         return Err((
@@ -917,21 +1233,38 @@ async fn update_schedule(
             return Err((StatusCode::BAD_REQUEST, Json((3, format!("Must not use the same Playlist more than once in a Schedule to avoid server meltdown")).into())));
         }
     }
+    if let Some(playlist) = schedule.unknown_playlist(&read.playlists) {
+        error!("[Api] Schedule uses unknown Playlist {playlist}");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json((9, format!("No Playlist with the Uuid {playlist} was found")).into()),
+        ));
+    }
     drop(read);
 
-    if let Err(e) = store
-        .update_schedule(
-            uuid,
-            schedule.name,
-            schedule.playlist,
-            schedule.scheduled.unwrap_or(vec![]),
-        )
-        .await
+    let new_schedule = match schedule::Schedule::new(
+        schedule.name,
+        schedule.scheduled.unwrap_or(vec![]),
+        schedule.playlist,
+        &store.now(),
+    )
+    .and_then(|s| s.with_calendar(schedule.calendar))
     {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[Api] Schedule {uuid} is invalid ({e})");
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json((e.code(), e.to_string()).into()),
+            ));
+        }
+    };
+
+    if let Err(e) = store.update_schedule(uuid, new_schedule).await {
         error!("[Api] Schedule update failed with error: {e}");
         return Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json((4, format!("{e}")).into()),
+            Json((4, format!("Could not write changes to db ({e})")).into()),
         ));
     }
 
@@ -965,7 +1298,7 @@ async fn update_schedule(
         (status = 200, description = "Display deleted", body = Payload,
             example = json!(
                 read::Payload::Display(vec![
-                        read::Display { uuid: Uuid::new_v4(), name: "name".into(), display_material: DisplayMaterial::Schedule(Uuid::new_v4()) }
+                        read::Display { uuid: Uuid::new_v4(), name: "name".into(), display_material: DisplayMaterial::Schedule(Uuid::new_v4()), sync_group: None, transition: Transition::Cut, settings: DisplaySettings::default() }
                 ])
             )
         ),
@@ -1013,7 +1346,7 @@ async fn delete_display(State(state): State<AppState>, Path(uuid): Path<Uuid>) -
         (status = 200, description = "Playlist deleted", body = Payload,
             example = json!(
                 read::Payload::Playlist(vec![
-                        read::Playlist { uuid: Uuid::new_v4(), name: "name".into(), items: vec![] }
+                        read::Playlist { uuid: Uuid::new_v4(), name: "name".into(), items: vec![], playback: PlaybackMode::Sequential }
                 ])
             )
         ),
@@ -1131,7 +1464,7 @@ pub(crate) async fn delete_playlist(
         (status = 200, description = "Schedule deleted", body = Payload,
             example = json!(
                 read::Payload::Schedule(vec![
-                        read::Schedule { uuid: Uuid::new_v4(), name: "name".into(), playlist: Uuid::new_v4(), scheduled: None }
+                        read::Schedule { uuid: Uuid::new_v4(), name: "name".into(), playlist: Uuid::new_v4(), scheduled: None, calendar: None }
                 ])
             )
         ),
@@ -1224,33 +1557,59 @@ async fn delete_schedule(State(state): State<AppState>, Path(uuid): Path<Uuid>)
     )
 )]
 async fn schedule_info(State(state): State<AppState>, Path(uuid): Path<Uuid>) -> impl IntoResponse {
-    let current_moment = Local::now();
+    let current_moment = state.store.now();
     let store = state.store.read().await;
 
     if let Some(schedule) = store.schedules.get(&uuid) {
-        let next_moment =
-            schedule
-                .next_schedule(&current_moment)
-                .and_then(|Moment { time, playlist }| {
-                    Some(read::NextMoment {
-                        in_ms: (time - current_moment).num_milliseconds() as u64,
-                        playlist,
-                    })
-                });
+        let (next, error) = match schedule.next_schedule(&current_moment) {
+            Ok(next) => (next, None),
+            Err(e) => (None, Some(e)),
+        };
+        let next_moment = next.map(|Moment { time, playlist }| read::NextMoment {
+            in_ms: (time - current_moment).num_milliseconds() as u64,
+            playlist,
+        });
 
         Ok(Json(read::ScheduleInfo {
             current: schedule.current_playlist(&current_moment),
             next: next_moment,
+            error,
         }))
     } else {
         Err(format!("Schedule '{uuid}' not found"))
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/schedule/cron",
+    tag = "schedule",
+    request_body = CronCheck,
+    responses(
+        (status = 200, description = "Description, upcoming fire times and likely mistakes of the cron expressions", body = CronValidation),
+    )
+)]
+async fn check_cron(
+    State(state): State<AppState>,
+    Json(check): Json<CronCheck>,
+) -> Json<CronValidation> {
+    info!("[Api] Checking cron expressions {check:?}");
+    Json(explain::validate(&check, &state.store.now()))
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| client_connection(socket, addr, state.store, state.htmx_hash))
+    ws.on_upgrade(move |socket| {
+        client_connection(
+            socket,
+            addr,
+            state.store,
+            state.connections,
+            state.files,
+            state.htmx_hash,
+        )
+    })
 }
@@ -0,0 +1,896 @@
+use std::{
+    collections::HashSet,
+    io::BufReader,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+    time::Duration,
+};
+
+use chrono::{
+    DateTime, Datelike, Days, Local, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta,
+    TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use ical::{IcalParser, parser::ical::component::IcalEvent, property::Property};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+use tracing::warn;
+use ts_rs::TS;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::file_server::pdf;
+
+/// How often the calendars of Schedules are fetched
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+/// How far ahead events are expanded into occurrences, moved forward on every fetch
+pub const HORIZON: TimeDelta = TimeDelta::days(31);
+/// Largest calendar read, larger ones are not loaded
+const MAX_CALENDAR_SIZE: usize = 16 * 1024 * 1024;
+/// Recurring events are not followed past this many periods, so a rule which never matches ends
+const MAX_PERIODS: u32 = 50_000;
+/// Largest INTERVAL of a recurrence rule, events recurring less often are skipped
+const MAX_INTERVAL: u32 = 1_000;
+
+static DURATION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^([+-])?P(?:(\d+)W)?(?:(\d+)D)?(?:T(?:(\d+)H)?(?:(\d+)M)?(?:(\d+)S)?)?$").unwrap()
+});
+
+/// iCalendar source of a Schedule, whose events decide the active playlist while they take place
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, TS, ToSchema)]
+#[ts(export, export_to = "api_bindings/update/")]
+pub struct CalendarInput {
+    /// Url (http, https or webcal) or path of an `.ics` file within `CALENDAR_PATH`, polled for changes
+    pub source: String,
+    /// The first rule matching an event decides its playlist, events matching no rule are ignored
+    pub rules: Vec<CalendarRule>,
+}
+
+/// Maps events to a playlist by category or summary, a rule with neither matches every event
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, TS, ToSchema)]
+#[ts(export, export_to = "api_bindings/update/")]
+pub struct CalendarRule {
+    #[ts(type = "string")]
+    pub playlist: Uuid,
+    /// Category of the event, ignoring case
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub category: Option<String>,
+    /// Regex matched against the summary of the event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub summary: Option<String>,
+}
+
+impl CalendarInput {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.source.trim().is_empty() {
+            return Err("calendar source is empty".to_string());
+        }
+        self.rules.iter().try_for_each(|r| r.regex().map(|_| ()))
+    }
+}
+
+impl CalendarRule {
+    fn regex(&self) -> Result<Option<Regex>, String> {
+        self.summary
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| format!("invalid summary regex ({e})"))
+    }
+}
+
+/// Time an event of the calendar takes place, with the playlist active meanwhile
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occurrence {
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub playlist: Uuid,
+}
+
+/// Reads the calendar from a url, or from a file within `dir`
+///
+/// Files elsewhere are not read, so Schedules cannot make Sasta read any file of the host.
+pub async fn fetch(
+    client: &reqwest::Client,
+    source: &str,
+    dir: Option<&Path>,
+) -> Result<String, String> {
+    let url = match source.strip_prefix("webcal://") {
+        Some(rest) => format!("https://{rest}"),
+        None => source.to_string(),
+    };
+    if url.starts_with("http://") || url.starts_with("https://") {
+        let content = pdf::download(client, &url, MAX_CALENDAR_SIZE).await?;
+        Ok(String::from_utf8_lossy(&content).into_owned())
+    } else {
+        let path = local_path(source, dir).await?;
+        let mut content = String::new();
+        tokio::fs::File::open(path)
+            .await
+            .map_err(|e| e.to_string())?
+            .take(MAX_CALENDAR_SIZE as u64 + 1)
+            .read_to_string(&mut content)
+            .await
+            .map_err(|e| e.to_string())?;
+        if content.len() > MAX_CALENDAR_SIZE {
+            return Err(format!("Calendar is larger than {MAX_CALENDAR_SIZE} bytes"));
+        }
+        Ok(content)
+    }
+}
+
+/// Path of the calendar file, which has to be within `dir` after following links
+async fn local_path(source: &str, dir: Option<&Path>) -> Result<PathBuf, String> {
+    let Some(dir) = dir else {
+        return Err("Calendar files are only read once CALENDAR_PATH is set".to_string());
+    };
+    let dir = tokio::fs::canonicalize(dir)
+        .await
+        .map_err(|e| format!("Could not read CALENDAR_PATH ({e})"))?;
+    let path = tokio::fs::canonicalize(dir.join(source))
+        .await
+        .map_err(|e| e.to_string())?;
+    if !path.starts_with(&dir) {
+        return Err(format!(
+            "Calendar file {source} is not within CALENDAR_PATH"
+        ));
+    }
+    Ok(path)
+}
+
+/// Occurrences of the events matching a rule which overlap `from` to `to`, ordered by start
+///
+/// Recurring events are expanded, leaving out their excluded dates and the instances moved or cancelled by
+/// an exception. Events which can not be read are skipped with a warning.
+pub fn occurrences(
+    ics: &str,
+    rules: &[CalendarRule],
+    from: DateTime<Local>,
+    to: DateTime<Local>,
+) -> Result<Vec<Occurrence>, String> {
+    let rules = rules
+        .iter()
+        .map(|r| Ok((r, r.regex()?)))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut events = vec![];
+    for calendar in IcalParser::new(BufReader::new(ics.as_bytes())) {
+        let calendar = calendar.map_err(|e| format!("invalid calendar ({e})"))?;
+        for event in &calendar.events {
+            match Event::parse(event) {
+                Ok(e) => events.push(e),
+                Err(e) => warn!("[Calendar] Skipping event ({e})"),
+            }
+        }
+    }
+
+    // Instances of recurring events replaced by an exception, which may also cancel them
+    let overridden: HashSet<(&str, DateTime<Local>)> = events
+        .iter()
+        .filter_map(|e| Some((e.uid.as_str(), e.recurrence_id?)))
+        .collect();
+
+    let mut occurrences = vec![];
+    for event in events.iter().filter(|e| !e.cancelled) {
+        let Some(playlist) = rules
+            .iter()
+            .find(|(rule, regex)| event.matches(rule, regex.as_ref()))
+            .map(|(rule, _)| rule.playlist)
+        else {
+            continue;
+        };
+
+        let starts = match (&event.rrule, event.recurrence_id) {
+            (Some(rrule), None) => rrule.starts(event.wall_start, event.zone, to)?,
+            _ => vec![event.start],
+        };
+        let starts = starts
+            .into_iter()
+            .chain(event.rdates.iter().copied())
+            .filter(|s| !event.exdates.contains(s))
+            .filter(|s| {
+                event.recurrence_id.is_some() || !overridden.contains(&(event.uid.as_str(), *s))
+            })
+            .collect::<HashSet<_>>();
+
+        occurrences.extend(
+            starts
+                .into_iter()
+                .map(|start| Occurrence {
+                    start,
+                    end: start + event.duration,
+                    playlist,
+                })
+                .filter(|o| o.end > from && o.start < to),
+        );
+    }
+    occurrences.sort_by_key(|o| (o.start, o.end));
+    Ok(occurrences)
+}
+
+/// Time zone of a date-time in the calendar
+#[derive(Debug, Clone, Copy)]
+enum Zone {
+    Utc,
+    Tz(Tz),
+    /// Without a time zone, taking place at the same wall time wherever it is shown
+    Floating,
+}
+
+impl Zone {
+    fn of(property: &Property, value: &str) -> Zone {
+        if value.ends_with('Z') {
+            return Zone::Utc;
+        }
+        match param(property, "TZID") {
+            Some(tzid) => match Tz::from_str(tzid.trim_start_matches('/')) {
+                Ok(tz) => Zone::Tz(tz),
+                Err(_) => {
+                    warn!("[Calendar] Unknown time zone {tzid}, using local time");
+                    Zone::Floating
+                }
+            },
+            None => Zone::Floating,
+        }
+    }
+
+    /// Local time of the wall time, None if it is skipped by a daylight saving change
+    fn resolve(self, time: NaiveDateTime) -> Option<DateTime<Local>> {
+        match self {
+            Zone::Utc => Some(Utc.from_utc_datetime(&time).with_timezone(&Local)),
+            Zone::Tz(tz) => tz
+                .from_local_datetime(&time)
+                .earliest()
+                .map(|t| t.with_timezone(&Local)),
+            Zone::Floating => Local.from_local_datetime(&time).earliest(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Event {
+    uid: String,
+    summary: String,
+    categories: Vec<String>,
+    /// Start in the time zone of the event, which recurrences keep
+    wall_start: NaiveDateTime,
+    zone: Zone,
+    start: DateTime<Local>,
+    duration: TimeDelta,
+    rrule: Option<RRule>,
+    rdates: Vec<DateTime<Local>>,
+    exdates: Vec<DateTime<Local>>,
+    /// Set on exceptions, the instance of the recurring event with the same uid it replaces
+    recurrence_id: Option<DateTime<Local>>,
+    cancelled: bool,
+}
+
+impl Event {
+    fn parse(event: &IcalEvent) -> Result<Self, String> {
+        let property = |name: &str| {
+            event
+                .properties
+                .iter()
+                .find(|p| p.name.eq_ignore_ascii_case(name))
+                .and_then(|p| Some((p, p.value.as_deref()?)))
+        };
+        let all = |name: &'static str| {
+            event
+                .properties
+                .iter()
+                .filter(move |p| p.name.eq_ignore_ascii_case(name))
+                .filter_map(|p| Some((p, p.value.as_deref()?)))
+        };
+
+        let uid = property("UID")
+            .map(|(_, v)| v.to_string())
+            .unwrap_or_default();
+        let (dtstart, value) =
+            property("DTSTART").ok_or(format!("event {uid:?} has no DTSTART"))?;
+        let zone = Zone::of(dtstart, value);
+        let wall_start = parse_time(value)?;
+        let all_day = is_date(dtstart, value);
+        let start = zone
+            .resolve(wall_start)
+            .ok_or(format!("event {uid:?} starts at a nonexistent time"))?;
+
+        let duration = if let Some((p, v)) = property("DTEND") {
+            let end = Zone::of(p, v)
+                .resolve(parse_time(v)?)
+                .ok_or(format!("event {uid:?} ends at a nonexistent time"))?;
+            end - start
+        } else if let Some((_, v)) = property("DURATION") {
+            parse_duration(v)?
+        } else if all_day {
+            TimeDelta::days(1)
+        } else {
+            TimeDelta::zero()
+        };
+        if duration <= TimeDelta::zero() {
+            return Err(format!("event {uid:?} has no duration"));
+        }
+
+        let recurrence_id = property("RECURRENCE-ID")
+            .map(|(p, v)| {
+                Zone::of(p, v)
+                    .resolve(parse_time(v)?)
+                    .ok_or(format!("event {uid:?} replaces a nonexistent time"))
+            })
+            .transpose()?;
+
+        Ok(Event {
+            summary: property("SUMMARY")
+                .map(|(_, v)| unescape(v))
+                .unwrap_or_default(),
+            categories: all("CATEGORIES")
+                .flat_map(|(_, v)| split_list(v))
+                .map(|c| unescape(&c))
+                .collect(),
+            rrule: property("RRULE")
+                .map(|(_, v)| RRule::parse(v, zone))
+                .transpose()?,
+            rdates: times(all("RDATE"))?,
+            exdates: times(all("EXDATE"))?,
+            cancelled: property("STATUS").is_some_and(|(_, v)| v.eq_ignore_ascii_case("CANCELLED")),
+            uid,
+            wall_start,
+            zone,
+            start,
+            duration,
+            recurrence_id,
+        })
+    }
+
+    fn matches(&self, rule: &CalendarRule, regex: Option<&Regex>) -> bool {
+        rule.category.as_ref().is_none_or(|category| {
+            self.categories
+                .iter()
+                .any(|c| c.trim().eq_ignore_ascii_case(category.trim()))
+        }) && regex.is_none_or(|r| r.is_match(&self.summary))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// Recurrence rule of an event, supporting the parts calendar applications write for everyday events
+#[derive(Debug)]
+struct RRule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<DateTime<Local>>,
+    /// Weekdays, with an ordinal such as the second (2) or last (-1) in the month
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+    by_month: Vec<u32>,
+}
+
+impl RRule {
+    fn parse(value: &str, zone: Zone) -> Result<Self, String> {
+        let invalid = || format!("invalid RRULE {value:?}");
+        let mut rule = RRule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: vec![],
+            by_month_day: vec![],
+            by_month: vec![],
+        };
+        let mut frequency = None;
+        for part in value.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(invalid)?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        f => return Err(format!("unsupported recurrence frequency {f}")),
+                    })
+                }
+                "INTERVAL" => rule.interval = value.parse().map_err(|_| invalid())?,
+                "COUNT" => rule.count = Some(value.parse().map_err(|_| invalid())?),
+                "UNTIL" => {
+                    // A date-only UNTIL includes the whole day
+                    let until = match value.len() {
+                        8 => parse_time(value)? + TimeDelta::days(1) - TimeDelta::seconds(1),
+                        _ => parse_time(value)?,
+                    };
+                    let zone = if value.ends_with('Z') {
+                        Zone::Utc
+                    } else {
+                        zone
+                    };
+                    rule.until = zone.resolve(until);
+                }
+                "BYDAY" => {
+                    rule.by_day = value
+                        .split(',')
+                        .map(|d| {
+                            let (ordinal, day) = d.split_at(d.len().saturating_sub(2));
+                            let ordinal = match ordinal {
+                                "" | "+" => None,
+                                o => {
+                                    Some(o.trim_start_matches('+').parse().map_err(|_| invalid())?)
+                                }
+                            };
+                            Ok((ordinal, weekday(day).ok_or_else(invalid)?))
+                        })
+                        .collect::<Result<_, String>>()?
+                }
+                "BYMONTHDAY" => {
+                    rule.by_month_day = value
+                        .split(',')
+                        .map(|d| d.parse().map_err(|_| invalid()))
+                        .collect::<Result<_, _>>()?
+                }
+                "BYMONTH" => {
+                    rule.by_month = value
+                        .split(',')
+                        .map(|m| m.parse().map_err(|_| invalid()))
+                        .collect::<Result<_, _>>()?
+                }
+                "WKST" => {}
+                k => warn!("[Calendar] Ignoring unsupported recurrence part {k}"),
+            }
+        }
+        rule.frequency = frequency.ok_or_else(invalid)?;
+        if rule.interval == 0 || rule.interval > MAX_INTERVAL {
+            return Err(invalid());
+        }
+        Ok(rule)
+    }
+
+    /// Starts of the instances up to `to`, counted from the first instance at `start`
+    fn starts(
+        &self,
+        start: NaiveDateTime,
+        zone: Zone,
+        to: DateTime<Local>,
+    ) -> Result<Vec<DateTime<Local>>, String> {
+        let mut starts = vec![];
+        let mut counted = 0;
+        for period in 0..MAX_PERIODS {
+            // Dates past the last one chrono supports end the recurrence
+            let Some(step) = period.checked_mul(self.interval) else {
+                break;
+            };
+            let mut candidates = match self.frequency {
+                Frequency::Daily => {
+                    let Some(day) = start.date().checked_add_days(Days::new(step.into())) else {
+                        break;
+                    };
+                    let matches = (self.by_day.is_empty()
+                        || self.by_day.iter().any(|(_, d)| *d == day.weekday()))
+                        && (self.by_month.is_empty() || self.by_month.contains(&day.month()))
+                        && (self.by_month_day.is_empty()
+                            || self.by_month_day.contains(&(day.day() as i32)));
+                    if matches { vec![day] } else { vec![] }
+                }
+                Frequency::Weekly => {
+                    let Some(monday) = start
+                        .date()
+                        .checked_sub_days(Days::new(start.weekday().num_days_from_monday().into()))
+                        .and_then(|d| d.checked_add_days(Days::new(u64::from(step) * 7)))
+                    else {
+                        break;
+                    };
+                    let days = match self.by_day.is_empty() {
+                        true => vec![start.weekday()],
+                        false => self.by_day.iter().map(|(_, d)| *d).collect(),
+                    };
+                    days.into_iter()
+                        .filter_map(|d| {
+                            monday.checked_add_days(Days::new(d.num_days_from_monday().into()))
+                        })
+                        .filter(|d| self.by_month.is_empty() || self.by_month.contains(&d.month()))
+                        .collect()
+                }
+                Frequency::Monthly => {
+                    let Some(month) =
+                        first_of_month(start.date()).checked_add_months(Months::new(step))
+                    else {
+                        break;
+                    };
+                    match self.by_month.is_empty() || self.by_month.contains(&month.month()) {
+                        true => self.days_in_month(month, start.day()),
+                        false => vec![],
+                    }
+                }
+                Frequency::Yearly => {
+                    let Some(year) = first_of_month(start.date())
+                        .with_month(1)
+                        .and_then(|d| d.checked_add_months(Months::new(step.checked_mul(12)?)))
+                    else {
+                        break;
+                    };
+                    let months = match self.by_month.is_empty() {
+                        true => vec![start.month()],
+                        false => self.by_month.clone(),
+                    };
+                    months
+                        .into_iter()
+                        .filter_map(|m| year.with_month(m))
+                        .flat_map(|month| self.days_in_month(month, start.day()))
+                        .collect()
+                }
+            };
+            candidates.sort();
+            candidates.dedup();
+
+            for day in candidates {
+                let wall = day.and_time(start.time());
+                if wall < start {
+                    continue;
+                }
+                // Instances at a time skipped by a daylight saving change do not take place
+                let Some(time) = zone.resolve(wall) else {
+                    continue;
+                };
+                if self.until.is_some_and(|u| time > u)
+                    || self.count.is_some_and(|c| counted >= c)
+                    || time >= to
+                {
+                    return Ok(starts);
+                }
+                counted += 1;
+                starts.push(time);
+            }
+        }
+        Ok(starts)
+    }
+
+    /// Days of the month the rule takes place on, on the day of the first instance if no day is given
+    fn days_in_month(&self, month: NaiveDate, start_day: u32) -> Vec<NaiveDate> {
+        let length = month
+            .checked_add_months(Months::new(1))
+            .map_or(31, |next| (next - month).num_days() as i32);
+        // Days counted from the end of the month are negative
+        let day = |d: i32| match d {
+            d if d > 0 && d <= length => month.with_day(d as u32),
+            d if d < 0 && d >= -length => month.with_day((length + 1 + d) as u32),
+            _ => None,
+        };
+
+        if !self.by_day.is_empty() {
+            let days: Vec<NaiveDate> = month.iter_days().take(length as usize).collect();
+            self.by_day
+                .iter()
+                .flat_map(|(ordinal, weekday)| {
+                    let matching: Vec<NaiveDate> = days
+                        .iter()
+                        .copied()
+                        .filter(|d| d.weekday() == *weekday)
+                        .collect();
+                    match ordinal {
+                        None => matching,
+                        Some(o) if *o > 0 => {
+                            matching.get(*o as usize - 1).copied().into_iter().collect()
+                        }
+                        Some(o) => matching
+                            .len()
+                            .checked_sub(o.unsigned_abs() as usize)
+                            .and_then(|i| matching.get(i).copied())
+                            .into_iter()
+                            .collect(),
+                    }
+                })
+                .filter(|d| {
+                    self.by_month_day.is_empty()
+                        || self.by_month_day.iter().any(|m| day(*m) == Some(*d))
+                })
+                .collect()
+        } else if !self.by_month_day.is_empty() {
+            self.by_month_day.iter().filter_map(|d| day(*d)).collect()
+        } else {
+            // Months without the day are skipped, as in the iCalendar specification
+            day(start_day as i32).into_iter().collect()
+        }
+    }
+}
+
+fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap()
+}
+
+fn weekday(day: &str) -> Option<Weekday> {
+    Some(match day.to_ascii_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn param<'a>(property: &'a Property, name: &str) -> Option<&'a str> {
+    property
+        .params
+        .as_ref()?
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))?
+        .1
+        .first()
+        .map(String::as_str)
+}
+
+fn is_date(property: &Property, value: &str) -> bool {
+    param(property, "VALUE").is_some_and(|v| v.eq_ignore_ascii_case("DATE")) || value.len() == 8
+}
+
+/// Parses a date or date-time value to its wall time, dates start at midnight
+fn parse_time(value: &str) -> Result<NaiveDateTime, String> {
+    let trimmed = value.trim().trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(trimmed, "%Y%m%dT%H%M%S")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(trimmed, "%Y%m%d").map(|d| d.and_time(NaiveTime::MIN))
+        })
+        .map_err(|_| format!("invalid date {value:?}"))
+}
+
+/// Times of RDATE and EXDATE properties, each of which may hold a list
+fn times<'a>(
+    properties: impl Iterator<Item = (&'a Property, &'a str)>,
+) -> Result<Vec<DateTime<Local>>, String> {
+    let mut times = vec![];
+    for (property, value) in properties {
+        for value in value.split(',') {
+            // Periods are given by their start
+            let value = value.split('/').next().unwrap_or_default();
+            if let Some(time) = Zone::of(property, value).resolve(parse_time(value)?) {
+                times.push(time);
+            }
+        }
+    }
+    Ok(times)
+}
+
+fn parse_duration(value: &str) -> Result<TimeDelta, String> {
+    let captures = DURATION_REGEX
+        .captures(value.trim())
+        .ok_or(format!("invalid duration {value:?}"))?;
+    let part = |i: usize| {
+        captures
+            .get(i)
+            .map_or(0, |m| m.as_str().parse().unwrap_or(0))
+    };
+    let duration = TimeDelta::weeks(part(2))
+        + TimeDelta::days(part(3))
+        + TimeDelta::hours(part(4))
+        + TimeDelta::minutes(part(5))
+        + TimeDelta::seconds(part(6));
+    Ok(match captures.get(1).map(|s| s.as_str()) {
+        Some("-") => -duration,
+        _ => duration,
+    })
+}
+
+/// Splits a list of text values on the commas which are not escaped
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => items.push(String::new()),
+            c => {
+                escaped = c == '\\' && !escaped;
+                items.last_mut().unwrap().push(c);
+            }
+        }
+    }
+    items
+}
+
+fn unescape(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n' | 'N') => text.push('\n'),
+                Some(c) => text.push(c),
+                None => text.push('\\'),
+            },
+            (c, false) => text.push(c),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Local, TimeZone};
+    use uuid::Uuid;
+
+    use super::{CalendarRule, Occurrence, local_path, occurrences};
+
+    const MEETING: Uuid = Uuid::from_u128(1);
+    const LECTURE: Uuid = Uuid::from_u128(2);
+    const ANY: Uuid = Uuid::from_u128(3);
+
+    const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Test//EN\r
+BEGIN:VEVENT\r
+UID:standup\r
+SUMMARY:Standup\r
+CATEGORIES:Meeting,Internal\r
+DTSTART:20250106T090000\r
+DTEND:20250106T093000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR;COUNT=6\r
+EXDATE:20250108T090000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup\r
+RECURRENCE-ID:20250110T090000\r
+SUMMARY:Standup\r
+CATEGORIES:Meeting\r
+DTSTART:20250110T100000\r
+DURATION:PT1H\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup\r
+RECURRENCE-ID:20250113T090000\r
+STATUS:CANCELLED\r
+DTSTART:20250113T090000\r
+DTEND:20250113T093000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:lecture\r
+SUMMARY:Lecture: Rust\\, part 1\r
+DTSTART:20250107T130000\r
+DTEND:20250107T150000\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:board\r
+SUMMARY:Board meeting\r
+DTSTART;VALUE=DATE:20250101\r
+RRULE:FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20250301\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn local(month: u32, day: u32, hour: u32, min: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2025, month, day, hour, min, 0)
+            .unwrap()
+    }
+
+    fn rule(playlist: Uuid, category: Option<&str>, summary: Option<&str>) -> CalendarRule {
+        CalendarRule {
+            playlist,
+            category: category.map(str::to_string),
+            summary: summary.map(str::to_string),
+        }
+    }
+
+    fn occurrence(start: DateTime<Local>, end: DateTime<Local>, playlist: Uuid) -> Occurrence {
+        Occurrence {
+            start,
+            end,
+            playlist,
+        }
+    }
+
+    #[test]
+    fn test_recurrences_exceptions_and_rules() {
+        let rules = [
+            rule(MEETING, Some("meeting"), None),
+            rule(LECTURE, None, Some("^Lecture:")),
+        ];
+        let found = occurrences(ICS, &rules, local(1, 1, 0, 0), local(2, 1, 0, 0)).unwrap();
+        assert_eq!(
+            vec![
+                occurrence(local(1, 6, 9, 0), local(1, 6, 9, 30), MEETING),
+                occurrence(local(1, 7, 13, 0), local(1, 7, 15, 0), LECTURE),
+                // Wednesday the 8th is excluded and Friday the 10th is moved
+                occurrence(local(1, 10, 10, 0), local(1, 10, 11, 0), MEETING),
+                // Monday the 13th is cancelled
+                occurrence(local(1, 15, 9, 0), local(1, 15, 9, 30), MEETING),
+                occurrence(local(1, 17, 9, 0), local(1, 17, 9, 30), MEETING),
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn test_monthly_all_day_events_and_range() {
+        let rules = [rule(ANY, None, Some("Board"))];
+        let found = occurrences(ICS, &rules, local(1, 1, 0, 0), local(12, 31, 0, 0)).unwrap();
+        // Last friday of each month until the first of March
+        assert_eq!(
+            vec![
+                occurrence(local(1, 31, 0, 0), local(2, 1, 0, 0), ANY),
+                occurrence(local(2, 28, 0, 0), local(3, 1, 0, 0), ANY),
+            ],
+            found
+        );
+
+        // Occurrences overlapping the start of the range are kept
+        let found = occurrences(ICS, &rules, local(2, 28, 12, 0), local(3, 1, 0, 0)).unwrap();
+        assert_eq!(
+            vec![occurrence(local(2, 28, 0, 0), local(3, 1, 0, 0), ANY)],
+            found
+        );
+    }
+
+    #[test]
+    fn test_recurrences_past_the_last_date() {
+        let ics = |rrule: &str| {
+            format!(
+                "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:never\r
+SUMMARY:Never\r
+DTSTART:20250106T090000\r
+DTEND:20250106T093000\r
+RRULE:{rrule}\r
+END:VEVENT\r
+END:VCALENDAR\r
+"
+            )
+        };
+        let rules = [rule(ANY, None, None)];
+        // Rules which never match run out of dates before they run out of periods
+        for rrule in [
+            "FREQ=DAILY;INTERVAL=1000;BYMONTH=2;BYMONTHDAY=30",
+            "FREQ=WEEKLY;INTERVAL=1000;BYMONTH=2;BYMONTHDAY=30",
+            "FREQ=YEARLY;INTERVAL=1000;BYMONTH=2;BYMONTHDAY=30",
+            "FREQ=MONTHLY;BYMONTHDAY=-2147483648",
+            // Skipped for its interval
+            "FREQ=DAILY;INTERVAL=4000000000",
+        ] {
+            let found = occurrences(&ics(rrule), &rules, local(1, 1, 0, 0), local(2, 1, 0, 0));
+            assert_eq!(Ok(vec![]), found, "{rrule}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(
+            occurrences(
+                ICS,
+                &[rule(ANY, None, Some("("))],
+                local(1, 1, 0, 0),
+                local(2, 1, 0, 0)
+            )
+            .is_err()
+        );
+        assert!(occurrences("not a calendar", &[], local(1, 1, 0, 0), local(2, 1, 0, 0)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_calendar_files_are_read_from_calendar_path() {
+        let dir = std::path::Path::new("./test_files").join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(dir.join("calendars")).unwrap();
+        std::fs::write(dir.join("calendars/rooms.ics"), ICS).unwrap();
+        std::fs::write(dir.join("secret.ics"), ICS).unwrap();
+        let calendars = dir.join("calendars");
+
+        assert!(local_path("rooms.ics", Some(&calendars)).await.is_ok());
+        assert!(local_path("rooms.ics", None).await.is_err());
+        assert!(local_path("../secret.ics", Some(&calendars)).await.is_err());
+        let secret = std::fs::canonicalize(dir.join("secret.ics")).unwrap();
+        assert!(
+            local_path(secret.to_str().unwrap(), Some(&calendars))
+                .await
+                .is_err()
+        );
+    }
+}
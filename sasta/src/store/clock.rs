@@ -0,0 +1,39 @@
+use chrono::{DateTime, Local};
+
+/// Source of the wall-clock time schedules are evaluated at
+///
+/// Sleeping is left to tokio, which only keeps monotonic time, so the wall-clock time may jump between two readings.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Local>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// Clock moving along with tokio's time, which tests pause and advance, that can also be set to jump
+#[cfg(test)]
+pub struct ManualClock(std::sync::Mutex<(DateTime<Local>, tokio::time::Instant)>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(now: DateTime<Local>) -> Self {
+        ManualClock(std::sync::Mutex::new((now, tokio::time::Instant::now())))
+    }
+
+    pub fn set(&self, now: DateTime<Local>) {
+        *self.0.lock().unwrap() = (now, tokio::time::Instant::now());
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Local> {
+        let (set, at) = *self.0.lock().unwrap();
+        set + chrono::TimeDelta::from_std(at.elapsed()).unwrap_or_default()
+    }
+}
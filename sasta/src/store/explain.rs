@@ -0,0 +1,437 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+use cron::Schedule as CronSchedule;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use super::schedule::first_shared_time;
+
+/// Fire times listed when no amount is asked for
+pub const DEFAULT_COUNT: usize = 5;
+pub const MAX_COUNT: usize = 100;
+
+const FIELDS: [&str; 7] = [
+    "second",
+    "minute",
+    "hour",
+    "day of the month",
+    "month",
+    "day of the week",
+    "year",
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+/// Days of the week as numbered by the cron crate, where Sunday is 1
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+#[derive(Deserialize, Debug, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/cron/", rename = "CheckCron")]
+pub struct CronCheck {
+    /// Start expression of a scheduled playlist, or any expression to check on its own
+    pub start: String,
+    /// End expression of the scheduled playlist
+    #[ts(optional)]
+    pub end: Option<String>,
+    /// Amount of upcoming fire times to list, 5 if not set and at most 100
+    #[ts(optional)]
+    pub count: Option<usize>,
+}
+
+#[derive(Serialize, Debug, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/cron/")]
+pub struct CronValidation {
+    pub start: CronExplanation,
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<CronExplanation>,
+    /// Mistakes in how the start and end expressions work together
+    pub warnings: Vec<CronWarning>,
+}
+
+#[derive(Serialize, Debug, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/cron/")]
+pub struct CronExplanation {
+    pub expression: String,
+    pub valid: bool,
+    /// Why the expression could not be parsed
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the expression fires, in plain language
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Upcoming fire times in RFC 3339
+    pub next: Vec<String>,
+    pub warnings: Vec<CronWarning>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/cron/")]
+pub struct CronWarning {
+    pub kind: CronWarningKind,
+    pub message: String,
+    /// Expression likely meant instead
+    #[ts(optional)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/cron/")]
+#[serde(rename_all = "snake_case")]
+pub enum CronWarningKind {
+    /// Five fields as in Unix crontab, where the cron crate expects seconds first
+    UnixSyntax,
+    /// Days of the week given as numbers, which start from Sunday as 1 instead of 0
+    NumericWeekday,
+    /// The expression has no upcoming fire times
+    NeverFires,
+    /// Start and end fire at the same moment, which makes the scheduled playlist stop being scheduled
+    StartEqualsEnd,
+}
+
+impl CronWarning {
+    fn new(kind: CronWarningKind, message: String) -> Self {
+        CronWarning {
+            kind,
+            message,
+            suggestion: None,
+        }
+    }
+}
+
+/// Checks the start and end expressions of a scheduled playlist, listing fire times after `now`
+pub fn validate(check: &CronCheck, now: &DateTime<Local>) -> CronValidation {
+    let count = check.count.unwrap_or(DEFAULT_COUNT).min(MAX_COUNT);
+    let start = explain(&check.start, count, now);
+    let end = check.end.as_deref().map(|e| explain(e, count, now));
+
+    let mut warnings = vec![];
+    let shared = match (
+        CronSchedule::from_str(&check.start),
+        check.end.as_deref().map(CronSchedule::from_str),
+    ) {
+        (Ok(start), Some(Ok(end))) => first_shared_time(&start, &end, now),
+        _ => None,
+    };
+    if let (Some(time), Some(end_expression)) = (shared, &check.end) {
+        warnings.push(CronWarning::new(
+            CronWarningKind::StartEqualsEnd,
+            format!(
+                "'{}' and '{end_expression}' both fire at {}, so the playlist would stop being scheduled",
+                check.start,
+                time.to_rfc3339()
+            ),
+        ));
+    }
+
+    CronValidation {
+        start,
+        end,
+        warnings,
+    }
+}
+
+/// Describes the expression with its upcoming fire times and likely mistakes
+pub fn explain(expression: &str, count: usize, now: &DateTime<Local>) -> CronExplanation {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    let mut warnings = vec![];
+
+    if fields.len() == 5 {
+        let suggestion = format!("0 {}", fields.join(" "));
+        warnings.push(CronWarning {
+            kind: CronWarningKind::UnixSyntax,
+            message: format!(
+                "'{expression}' uses the 5 field Unix syntax, add the seconds as the first field"
+            ),
+            suggestion: CronSchedule::from_str(&suggestion)
+                .is_ok()
+                .then_some(suggestion),
+        });
+    }
+    // Steps such as */2 count days rather than name them
+    let numeric_weekdays = fields
+        .get(5)
+        .filter(|w| w.chars().any(|c| c.is_ascii_digit()) && !w.starts_with("*/"));
+    if let Some(weekdays) = numeric_weekdays {
+        warnings.push(CronWarning::new(
+            CronWarningKind::NumericWeekday,
+            format!(
+                "Days of the week are numbered from Sunday as 1 to Saturday as 7, so '{weekdays}' means {}; names such as Mon-Fri are clearer",
+                describe_field(weekdays, 5).unwrap_or_else(|| "every day".to_string())
+            ),
+        ));
+    }
+
+    match CronSchedule::from_str(expression) {
+        Ok(cron) => {
+            let next: Vec<String> = cron
+                .after(now)
+                .take(count)
+                .map(|t| t.to_rfc3339())
+                .collect();
+            if cron.after(now).next().is_none() {
+                warnings.push(CronWarning::new(
+                    CronWarningKind::NeverFires,
+                    format!("'{expression}' never fires"),
+                ));
+            }
+            CronExplanation {
+                expression: expression.to_string(),
+                valid: true,
+                error: None,
+                description: match fields.len() {
+                    6 | 7 => Some(describe(&fields)),
+                    _ => describe_shorthand(expression.trim()),
+                },
+                next,
+                warnings,
+            }
+        }
+        Err(e) => CronExplanation {
+            expression: expression.to_string(),
+            valid: false,
+            error: Some(e.to_string()),
+            description: None,
+            next: vec![],
+            warnings,
+        },
+    }
+}
+
+/// Plain language description of a parsed expression with 6 or 7 fields
+fn describe(fields: &[&str]) -> String {
+    let number = |f: &str| f.parse::<u32>().ok();
+    let time = match (number(fields[0]), number(fields[1]), number(fields[2])) {
+        (Some(0), Some(m), Some(h)) => format!("At {h:02}:{m:02}"),
+        (Some(s), Some(m), Some(h)) => format!("At {h:02}:{m:02}:{s:02}"),
+        _ => {
+            let parts: Vec<String> = (0..3)
+                .rev()
+                .filter_map(|i| describe_field(fields[i], i))
+                .map(|d| match d.starts_with("every") {
+                    true => d,
+                    false => format!("at {d}"),
+                })
+                .collect();
+            match parts.is_empty() {
+                true => "Every second".to_string(),
+                false => capitalize(&parts.join(", ")),
+            }
+        }
+    };
+
+    let mut parts = vec![time];
+    parts.extend(describe_field(fields[3], 3).map(|d| format!("on {d}")));
+    parts.extend(describe_field(fields[5], 5).map(|d| format!("on {d}")));
+    parts.extend(describe_field(fields[4], 4).map(|d| format!("in {d}")));
+    parts.extend(
+        fields
+            .get(6)
+            .and_then(|y| describe_field(y, 6))
+            .map(|d| format!("in {d}")),
+    );
+    if parts.len() == 1 && fields[3..6].iter().all(|f| is_any(f)) {
+        parts.push("every day".to_string());
+    }
+    parts.join(", ")
+}
+
+/// Plain language description of a shorthand such as `@daily`
+fn describe_shorthand(shorthand: &str) -> Option<String> {
+    let description = match shorthand {
+        "@yearly" => "At 00:00, on January 1",
+        "@monthly" => "At 00:00, on day of the month 1",
+        "@weekly" => "At 00:00, on Sunday",
+        "@daily" => "At 00:00, every day",
+        "@hourly" => "Every hour, at minute 0",
+        _ => return None,
+    };
+    Some(description.to_string())
+}
+
+fn is_any(field: &str) -> bool {
+    field == "*" || field == "?"
+}
+
+/// Describes the values of a field, None if it matches every value
+fn describe_field(field: &str, index: usize) -> Option<String> {
+    if is_any(field) {
+        return None;
+    }
+    let unit = FIELDS[index];
+    let plural = match unit.split_once(' ') {
+        Some((first, rest)) => format!("{first}s {rest}"),
+        None => format!("{unit}s"),
+    };
+    // Months, days of the week and years read on their own, as in "on Monday" or "in March"
+    let named = matches!(index, 4..=6);
+    let values: Vec<&str> = field.split(',').collect();
+
+    if values.iter().all(|v| !v.contains(['-', '/'])) {
+        let names: Vec<String> = values.iter().map(|v| name(v, index)).collect();
+        return Some(match (named, names.len()) {
+            (true, _) => list(&names),
+            (false, 1) => format!("{unit} {}", names[0]),
+            (false, _) => format!("{plural} {}", list(&names)),
+        });
+    }
+    let values: Vec<String> = values
+        .iter()
+        .map(|value| {
+            let (range, step) = match value.split_once('/') {
+                Some((range, step)) => (range, Some(step)),
+                None => (*value, None),
+            };
+            let range = match range.split_once('-') {
+                Some((from, to)) => format!("{} through {}", name(from, index), name(to, index)),
+                None if is_any(range) => String::new(),
+                None => name(range, index),
+            };
+            match (step, range.is_empty(), named) {
+                (Some(step), true, _) => format!("every {step} {plural}"),
+                (Some(step), false, true) => format!("every {step} {plural} from {range}"),
+                (Some(step), false, false) => format!("every {step} {plural} from {unit} {range}"),
+                (None, _, true) => range,
+                (None, _, false) => format!("{plural} {range}"),
+            }
+        })
+        .collect();
+    Some(list(&values))
+}
+
+/// Name of a month or day of the week given by number or name
+fn name(value: &str, index: usize) -> String {
+    let names: &[&str] = match index {
+        4 => &MONTHS,
+        5 => &WEEKDAYS,
+        _ => return value.to_string(),
+    };
+    match value.parse::<usize>() {
+        Ok(n) if (1..=names.len()).contains(&n) => names[n - 1].to_string(),
+        Ok(_) => value.to_string(),
+        Err(_) => names
+            .iter()
+            .find(|n| n.to_lowercase().starts_with(&value.to_lowercase()))
+            .map_or(value.to_string(), |n| n.to_string()),
+    }
+}
+
+fn list(values: &[String]) -> String {
+    match values {
+        [] => String::new(),
+        [value] => value.clone(),
+        [rest @ .., last] => format!("{} and {last}", rest.join(", ")),
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Local, TimeZone};
+
+    use super::{CronCheck, CronWarningKind, explain, validate};
+
+    /// A Monday morning
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2025, 3, 10, 9, 0, 0).unwrap()
+    }
+
+    fn warnings(expression: &str) -> Vec<CronWarningKind> {
+        explain(expression, 1, &now())
+            .warnings
+            .iter()
+            .map(|w| w.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_descriptions() {
+        let description = |e: &str| explain(e, 1, &now()).description.unwrap();
+        assert_eq!(
+            "At 10:00, on Monday through Friday",
+            description("0 0 10 * * Mon-Fri *")
+        );
+        assert_eq!("At 08:30:15, every day", description("15 30 8 * * *"));
+        assert_eq!(
+            "Every 15 minutes, at second 0, every day",
+            description("0 */15 * * * *")
+        );
+        assert_eq!(
+            "At 00:00, on days of the month 1 and 15, in January through March, in 2030",
+            description("0 0 0 1,15 1-3 * 2030")
+        );
+        assert_eq!("Every second, every day", description("* * * * * *"));
+        assert_eq!("At 00:00, every day", description("@daily"));
+        assert_eq!("Every hour, at minute 0", description("@hourly"));
+        assert_eq!("At 00:00, on Sunday", description("@weekly"));
+    }
+
+    #[test]
+    fn test_common_mistakes() {
+        let unix = explain("0 9 * * 1-5", 1, &now());
+        assert!(!unix.valid);
+        assert_eq!(
+            Some("0 0 9 * * 1-5"),
+            unix.warnings[0].suggestion.as_deref()
+        );
+        assert_eq!(
+            vec![CronWarningKind::NumericWeekday],
+            warnings("0 0 9 * * 1-5")
+        );
+        assert_eq!(
+            vec![CronWarningKind::NeverFires],
+            warnings("0 0 0 31 Feb *")
+        );
+        assert!(warnings("0 0 10 * * Mon-Fri").is_empty());
+
+        let validation = validate(
+            &CronCheck {
+                start: "0 0 10 * * *".to_string(),
+                end: Some("0 0 */2 * * Mon".to_string()),
+                count: Some(3),
+            },
+            &now(),
+        );
+        assert_eq!(3, validation.start.next.len());
+        assert!(validation.start.next[0].starts_with("2025-03-10T10:00:00"));
+        assert_eq!(
+            vec![CronWarningKind::StartEqualsEnd],
+            validation
+                .warnings
+                .iter()
+                .map(|w| w.kind)
+                .collect::<Vec<_>>()
+        );
+    }
+}
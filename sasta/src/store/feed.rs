@@ -0,0 +1,380 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, Utc};
+use regex::{Captures, Regex};
+use tokio::{sync::RwLock, time::Instant};
+use tracing::{info, warn};
+
+use super::store::{FeedData, PlaylistItem, TextData};
+use crate::file_server::pdf;
+
+/// Seconds between fetches of a feed, if not set on the item
+pub const DEFAULT_REFRESH: u64 = 300;
+/// Largest feed fetched, larger ones are not shown
+const MAX_FEED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Entry variables in the template of a Feed item, such as `{{entry.title}}`
+static ENTRY_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*entry\.(\w+)\s*\}\}").unwrap());
+static TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedEntry {
+    pub title: String,
+    pub summary: String,
+    pub link: String,
+    pub date: Option<DateTime<Utc>>,
+}
+
+impl From<feed_rs::model::Entry> for FeedEntry {
+    fn from(entry: feed_rs::model::Entry) -> Self {
+        let text = |t: feed_rs::model::Text| {
+            if t.content_type.to_string().contains("html") {
+                strip_html(&t.content)
+            } else {
+                t.content
+            }
+        };
+        FeedEntry {
+            title: entry.title.map(text).unwrap_or_default(),
+            summary: entry.summary.map(text).unwrap_or_default(),
+            link: entry
+                .links
+                .into_iter()
+                .next()
+                .map(|l| l.href)
+                .unwrap_or_default(),
+            date: entry.published.or(entry.updated),
+        }
+    }
+}
+
+/// Text of an HTML snippet, the templates are rendered as plain text or Markdown
+fn strip_html(html: &str) -> String {
+    TAG_REGEX
+        .replace_all(html, "")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+struct CachedFeed {
+    /// Entries of the last successful fetch, kept when a later fetch fails
+    entries: Option<Vec<FeedEntry>>,
+    /// Time of the last fetch, successful or not
+    fetched: Instant,
+}
+
+/// Feeds referenced by Feed items, fetched on an interval and expanded into Text items
+pub struct Feeds {
+    client: reqwest::Client,
+    cache: RwLock<HashMap<String, CachedFeed>>,
+}
+
+impl Feeds {
+    pub fn new() -> Self {
+        Feeds {
+            client: reqwest::Client::new(),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Fetches the feeds which were not fetched within their refresh interval
+    ///
+    /// Returns the urls of the feeds whose entries changed. Feeds not given are dropped from the cache.
+    pub async fn refresh(&self, feeds: HashMap<String, Duration>) -> HashSet<String> {
+        let due: Vec<(String, bool)> = {
+            let mut cache = self.cache.write().await;
+            cache.retain(|url, _| feeds.contains_key(url));
+            feeds
+                .iter()
+                .filter_map(|(url, interval)| match cache.get(url) {
+                    None => Some((url.clone(), true)),
+                    Some(c) if c.fetched.elapsed() >= *interval => {
+                        Some((url.clone(), c.entries.is_none()))
+                    }
+                    Some(_) => None,
+                })
+                .collect()
+        };
+
+        let mut changed = HashSet::new();
+        for (url, first) in due {
+            let result = fetch(&self.client, &url).await;
+            let mut cache = self.cache.write().await;
+            let cached = cache.entry(url.clone()).or_insert(CachedFeed {
+                entries: None,
+                fetched: Instant::now(),
+            });
+            cached.fetched = Instant::now();
+            match result {
+                Ok(entries) => {
+                    if cached.entries.as_ref() != Some(&entries) {
+                        info!("[Feed] Fetched {} entries from {url}", entries.len());
+                        cached.entries = Some(entries);
+                        changed.insert(url);
+                    }
+                }
+                Err(e) if first => warn!("[Feed] Could not fetch {url} ({e})"),
+                Err(e) => warn!("[Feed] Could not fetch {url}, keeping the last good copy ({e})"),
+            }
+        }
+        changed
+    }
+
+    /// Replaces Feed items with the Text items made from their cached entries
+    ///
+    /// Feeds which have not been fetched yet give no items.
+    pub async fn expand(&self, items: Vec<PlaylistItem>) -> Vec<PlaylistItem> {
+        let cache = self.cache.read().await;
+        items
+            .into_iter()
+            .flat_map(|item| match item {
+                PlaylistItem::Feed { id, settings } => {
+                    match cache.get(&settings.url).and_then(|c| c.entries.as_ref()) {
+                        Some(entries) => expand_feed(&id, &settings, entries),
+                        None => vec![],
+                    }
+                }
+                item => vec![item],
+            })
+            .collect()
+    }
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<Vec<FeedEntry>, String> {
+    let bytes = pdf::download(client, url, MAX_FEED_SIZE).await?;
+    // Detects RSS, Atom and JSON Feed
+    let feed = feed_rs::parser::parse(bytes.as_slice()).map_err(|e| e.to_string())?;
+    Ok(feed.entries.into_iter().map(FeedEntry::from).collect())
+}
+
+/// Id of the Feed item the Text item with the given id was made from, if it was made from a feed
+pub fn feed_id(id: &str) -> Option<&str> {
+    let (feed, n) = id.rsplit_once('/')?;
+    n.parse::<usize>().is_ok().then_some(feed)
+}
+
+/// Text items made from the newest entries, one per entry or a single one with all of them
+pub fn expand_feed(id: &str, feed: &FeedData, entries: &[FeedEntry]) -> Vec<PlaylistItem> {
+    let text = |n: usize, text: String| PlaylistItem::Text {
+        id: format!("{id}/{n}"),
+        settings: TextData {
+            text,
+            duration: feed.duration,
+            format: feed.format,
+        },
+    };
+    let entries = entries
+        .iter()
+        .take(feed.entries as usize)
+        .map(|e| fill_template(&feed.template, e));
+    if feed.combined {
+        let entries: Vec<String> = entries.collect();
+        if entries.is_empty() {
+            return vec![];
+        }
+        vec![text(0, entries.join("\n"))]
+    } else {
+        entries.enumerate().map(|(n, t)| text(n, t)).collect()
+    }
+}
+
+fn fill_template(template: &str, entry: &FeedEntry) -> String {
+    ENTRY_REGEX
+        .replace_all(template, |c: &Captures| match &c[1] {
+            "title" => entry.title.clone(),
+            "summary" => entry.summary.clone(),
+            "link" => entry.link.clone(),
+            "date" => entry
+                .date
+                .map(|d| d.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default(),
+            // Left as is, so a mistake shows up on the display
+            _ => c[0].to_string(),
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{Router, extract::State, http::StatusCode, routing::get};
+    use tokio::net::TcpListener;
+
+    use crate::store::store::{FeedData, PlaylistItem, TextData, TextFormat};
+
+    use super::{FeedEntry, Feeds, expand_feed};
+
+    const TEMPLATE: &str = "{{entry.title}}|{{entry.summary}}|{{entry.link}}";
+
+    const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0"><channel><title>News</title>
+<item><title>First</title><description>&lt;p&gt;Some &lt;b&gt;news&lt;/b&gt;&lt;/p&gt;</description><link>https://example.com/1</link></item>
+<item><title>Second</title><description>More news</description><link>https://example.com/2</link></item>
+</channel></rss>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom"><title>News</title><id>urn:news</id><updated>2025-01-01T00:00:00Z</updated>
+<entry><title>Atom entry</title><id>urn:1</id><updated>2025-01-01T00:00:00Z</updated><summary>Summary</summary></entry>
+</feed>"#;
+
+    const JSON: &str = r#"{"version": "https://jsonfeed.org/version/1.1", "title": "News",
+"items": [{"id": "1", "title": "Json entry", "content_text": "Text"}]}"#;
+
+    /// Serves the body on a local port, or an error while it is None
+    async fn stand_in(body: Arc<Mutex<Option<&'static str>>>) -> String {
+        let app = Router::new()
+            .route(
+                "/feed",
+                get(
+                    |State(body): State<Arc<Mutex<Option<&'static str>>>>| async move {
+                        match *body.lock().unwrap() {
+                            Some(b) => Ok(b),
+                            None => Err(StatusCode::INTERNAL_SERVER_ERROR),
+                        }
+                    },
+                ),
+            )
+            .with_state(body);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}/feed")
+    }
+
+    fn feed(url: &str, combined: bool) -> FeedData {
+        FeedData {
+            url: url.to_string(),
+            template: TEMPLATE.to_string(),
+            format: None,
+            entries: 5,
+            combined,
+            duration: 10,
+            refresh: None,
+        }
+    }
+
+    fn texts(items: &[PlaylistItem]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|i| match i {
+                PlaylistItem::Text { id, settings } => (id.clone(), settings.text.clone()),
+                i => panic!("Expected a Text item, got {i:?}"),
+            })
+            .collect()
+    }
+
+    fn items(url: &str) -> Vec<PlaylistItem> {
+        vec![
+            PlaylistItem::Feed {
+                id: "news".to_string(),
+                settings: feed(url, false),
+            },
+            PlaylistItem::Text {
+                id: "text".to_string(),
+                settings: TextData {
+                    text: "Not a feed".to_string(),
+                    duration: 10,
+                    format: None,
+                },
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_fetch_falls_back_to_last_good_copy() {
+        let body = Arc::new(Mutex::new(None));
+        let url = stand_in(body.clone()).await;
+        let feeds = Feeds::new();
+        let interval = HashMap::from([(url.clone(), Duration::ZERO)]);
+        let expanded = vec![
+            (
+                "news/0".to_string(),
+                "First|Some news|https://example.com/1".to_string(),
+            ),
+            (
+                "news/1".to_string(),
+                "Second|More news|https://example.com/2".to_string(),
+            ),
+            ("text".to_string(), "Not a feed".to_string()),
+        ];
+
+        // Nothing to show before the first fetch
+        assert!(feeds.refresh(interval.clone()).await.is_empty());
+        assert_eq!(1, feeds.expand(items(&url)).await.len());
+
+        *body.lock().unwrap() = Some(RSS);
+        assert!(feeds.refresh(interval.clone()).await.contains(&url));
+        assert_eq!(expanded, texts(&feeds.expand(items(&url)).await));
+
+        // Unchanged
+        assert!(feeds.refresh(interval.clone()).await.is_empty());
+
+        *body.lock().unwrap() = None;
+        assert!(feeds.refresh(interval.clone()).await.is_empty());
+        assert_eq!(expanded, texts(&feeds.expand(items(&url)).await));
+
+        // Not due yet
+        *body.lock().unwrap() = Some(ATOM);
+        let hourly = HashMap::from([(url.clone(), Duration::from_secs(3600))]);
+        assert!(feeds.refresh(hourly).await.is_empty());
+
+        // Dropped once no longer referenced
+        feeds.refresh(HashMap::new()).await;
+        assert_eq!(1, feeds.expand(items(&url)).await.len());
+    }
+
+    #[tokio::test]
+    async fn test_atom_and_json_feeds() {
+        for (body, text) in [(ATOM, "Atom entry|Summary|"), (JSON, "Json entry||")] {
+            let url = stand_in(Arc::new(Mutex::new(Some(body)))).await;
+            let feeds = Feeds::new();
+            feeds
+                .refresh(HashMap::from([(url.clone(), Duration::ZERO)]))
+                .await;
+            assert_eq!(text, texts(&feeds.expand(items(&url)).await)[0].1);
+        }
+    }
+
+    #[test]
+    fn test_expand_feed_into_single_slide() {
+        let entries: Vec<FeedEntry> = ["a", "b", "c"]
+            .iter()
+            .map(|t| FeedEntry {
+                title: t.to_string(),
+                summary: String::new(),
+                link: String::new(),
+                date: None,
+            })
+            .collect();
+        let feed = FeedData {
+            template: "- {{ entry.title }} {{entry.unknown}}".to_string(),
+            format: Some(TextFormat::Markdown),
+            entries: 2,
+            ..feed("", true)
+        };
+        assert_eq!(
+            vec![(
+                "id/0".to_string(),
+                "- a {{entry.unknown}}\n- b {{entry.unknown}}".to_string()
+            )],
+            texts(&expand_feed("id", &feed, &entries))
+        );
+        assert!(expand_feed("id", &feed, &[]).is_empty());
+    }
+}
@@ -1,2 +1,7 @@
-pub mod store;
+pub mod calendar;
+pub mod clock;
+pub mod explain;
+pub mod feed;
+pub mod playback;
 pub mod schedule;
+pub mod store;
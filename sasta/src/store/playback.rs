@@ -0,0 +1,653 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use super::{feed::feed_id, store::PlaylistItem};
+
+/// Most times an item is played in one cycle of a weighted Playlist
+const MAX_REPEATS: u64 = 100;
+
+/// Most items in one cycle of a weighted Playlist
+const MAX_CYCLE_LENGTH: u64 = 10_000;
+
+/// Order in which the items of a Playlist are played
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/update/")]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type", content = "weights")]
+pub enum PlaybackMode {
+    /// Items are played in the order they are defined in the Playlist
+    #[default]
+    Sequential,
+    /// Items are played in a random order, reshuffled each cycle
+    Shuffle,
+    /// Items are played in order, but repeated according to their weight.
+    ///
+    /// Weights are keyed by the id of the PlaylistItem, items without a weight are played once per cycle.
+    /// The weight of a Feed item applies to the items of its entries together.
+    Weighted(HashMap<String, Weight>),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/update/")]
+#[serde(rename_all = "snake_case")]
+pub enum Weight {
+    /// Amount of times the item is played each cycle
+    Repeat(u32),
+    /// Share of the total airtime of a cycle given to the item, between 0 and 1
+    Share(f64),
+}
+
+impl PlaybackMode {
+    /// Checks that the weights are usable with the given items
+    pub fn validate(&self, items: &[PlaylistItem]) -> Result<(), String> {
+        let PlaybackMode::Weighted(weights) = self else {
+            return Ok(());
+        };
+
+        let mut total_share = 0.0;
+        for (id, weight) in weights {
+            let Some(item) = items.iter().find(|i| i.id() == id) else {
+                return Err(format!("Weight given for unknown PlaylistItem '{id}'"));
+            };
+            match weight {
+                Weight::Repeat(0) => {
+                    return Err(format!(
+                        "PlaylistItem '{id}' must be repeated at least once"
+                    ));
+                }
+                Weight::Repeat(n) if *n as u64 > MAX_REPEATS => {
+                    return Err(format!(
+                        "PlaylistItem '{id}' may be repeated at most {MAX_REPEATS} times (got {n})"
+                    ));
+                }
+                Weight::Repeat(_) => (),
+                Weight::Share(share) => {
+                    if !(*share > 0.0 && *share < 1.0) {
+                        return Err(format!(
+                            "Share of PlaylistItem '{id}' must be between 0 and 1 (got {share})"
+                        ));
+                    }
+                    if item.duration() == 0 {
+                        return Err(format!(
+                            "PlaylistItem '{id}' has no duration and cannot be given a share of the airtime"
+                        ));
+                    }
+                    total_share += share;
+                }
+            }
+        }
+
+        if total_share >= 1.0 {
+            return Err(format!(
+                "Sum of all shares must be less than 1 (got {total_share})"
+            ));
+        }
+
+        let repeats = Self::repeats(items, &Self::item_weights(items, weights));
+        if let Some((item, n)) = items.iter().zip(&repeats).find(|(_, n)| **n > MAX_REPEATS) {
+            return Err(format!(
+                "PlaylistItem '{}' would be played {n} times each cycle, at most {MAX_REPEATS} are allowed",
+                item.id()
+            ));
+        }
+        let length = repeats.iter().sum::<u64>();
+        if length > MAX_CYCLE_LENGTH {
+            return Err(format!(
+                "A cycle would play {length} items, at most {MAX_CYCLE_LENGTH} are allowed"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the items of one cycle, before any shuffling
+    pub fn cycle(&self, items: Vec<PlaylistItem>) -> Vec<PlaylistItem> {
+        match self {
+            PlaybackMode::Weighted(weights) => Self::weighted_cycle(items, weights),
+            _ => items,
+        }
+    }
+
+    /// Returns an endless iterator over the items played in the given mode
+    pub fn play(&self, items: Vec<PlaylistItem>) -> Playback {
+        let mut playback = Playback {
+            shuffle: *self == PlaybackMode::Shuffle,
+            cycle: self.cycle(items),
+            pos: 0,
+        };
+        if playback.shuffle {
+            playback.reshuffle();
+        }
+        playback
+    }
+
+    /// Returns an endless iterator over the items played by a group of synchronised displays
+    ///
+    /// The item shown is derived from the time passed since the epoch, so every display using the
+    /// same epoch shows the same item at the same time. A shuffled Playlist is shuffled once, using
+    /// the epoch as seed, and then repeated in that order.
+    pub fn play_synced(
+        &self,
+        items: Vec<PlaylistItem>,
+        epoch: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> SyncedPlayback {
+        let cycle = match self {
+            PlaybackMode::Sequential => items,
+            PlaybackMode::Shuffle => {
+                let mut items = items;
+                items.shuffle(&mut StdRng::seed_from_u64(epoch.timestamp_millis() as u64));
+                items
+            }
+            PlaybackMode::Weighted(weights) => Self::weighted_cycle(items, weights),
+        };
+
+        let mut start = epoch;
+        // An item without a duration is shown forever once reached, so the cycle is never repeated
+        if cycle.iter().all(|i| i.duration() != 0) {
+            let cycle_length = cycle.iter().map(|i| i.duration() as i64).sum::<i64>();
+            if cycle_length > 0 {
+                let cycles = (now - epoch).num_seconds().max(0) / cycle_length;
+                start += TimeDelta::seconds(cycles * cycle_length);
+            }
+        }
+
+        let mut index = 0;
+        for (i, item) in cycle.iter().enumerate() {
+            let end = start + TimeDelta::seconds(item.duration() as i64);
+            index = i;
+            if item.duration() == 0 || end > now {
+                break;
+            }
+            start = end;
+        }
+
+        SyncedPlayback {
+            cycle,
+            index,
+            start,
+        }
+    }
+
+    /// Weight of each item, where the items of the entries of a Feed share the weight of the Feed
+    ///
+    /// Each entry is repeated as often as the Feed would be, and the entries split its share of the airtime.
+    fn item_weights(
+        items: &[PlaylistItem],
+        weights: &HashMap<String, Weight>,
+    ) -> Vec<Option<Weight>> {
+        items
+            .iter()
+            .map(|item| {
+                if let Some(weight) = weights.get(item.id()) {
+                    return Some(weight.clone());
+                }
+                let feed = feed_id(item.id()).filter(|f| items.iter().all(|i| i.id() != *f))?;
+                match weights.get(feed)? {
+                    Weight::Repeat(n) => Some(Weight::Repeat(*n)),
+                    Weight::Share(s) => {
+                        let entries = items
+                            .iter()
+                            .filter(|i| feed_id(i.id()) == Some(feed))
+                            .count();
+                        Some(Weight::Share(s / entries as f64))
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Amount of times each item is played in one cycle
+    fn repeats(items: &[PlaylistItem], weights: &[Option<Weight>]) -> Vec<u64> {
+        // Airtime of the items not given a share, which the shared items are relative to
+        let unshared_airtime: u64 = items
+            .iter()
+            .zip(weights)
+            .map(|(i, w)| match w {
+                Some(Weight::Share(_)) => 0,
+                Some(Weight::Repeat(n)) => (*n as u64).saturating_mul(i.duration()),
+                None => i.duration(),
+            })
+            .fold(0, u64::saturating_add);
+        let total_share: f64 = weights
+            .iter()
+            .filter_map(|w| match w {
+                Some(Weight::Share(s)) => Some(*s),
+                _ => None,
+            })
+            .sum();
+        let total_airtime = unshared_airtime as f64 / (1.0 - total_share).max(f64::EPSILON);
+
+        items
+            .iter()
+            .zip(weights)
+            .map(|(item, weight)| match weight {
+                Some(Weight::Repeat(n)) => *n as u64,
+                // Items without a duration are not valid for a share, fall back to playing it once
+                Some(Weight::Share(_)) if item.duration() == 0 => 1,
+                Some(Weight::Share(s)) => {
+                    ((s * total_airtime / item.duration() as f64).round() as u64).max(1)
+                }
+                None => 1,
+            })
+            .collect()
+    }
+
+    /// Builds one cycle of items where weighted items are repeated and spread out as evenly as possible
+    ///
+    /// Repeats are limited to `MAX_REPEATS`, since entries of Feeds are only known after the weights were validated.
+    fn weighted_cycle(
+        items: Vec<PlaylistItem>,
+        weights: &HashMap<String, Weight>,
+    ) -> Vec<PlaylistItem> {
+        let weights = Self::item_weights(&items, weights);
+        let mut slots = Self::repeats(&items, &weights)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(index, count)| {
+                let count = count.min(MAX_REPEATS);
+                // Place each repetition in the middle of its equally sized part of the cycle
+                (0..count).map(move |n| ((n as f64 + 0.5) / count as f64, index))
+            })
+            .collect::<Vec<_>>();
+
+        // Stable sort keeps the Playlist order for items placed at the same position
+        slots.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        slots
+            .into_iter()
+            .map(|(_, index)| items[index].clone())
+            .collect()
+    }
+}
+
+/// Last PlaylistItem sent to a Display, which its playback is resumed after
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub id: String,
+    /// Position of the item in the cycle, telling apart the repetitions of a weighted item
+    pub pos: Option<usize>,
+}
+
+/// Endless iterator over the items of a Playlist
+pub struct Playback {
+    shuffle: bool,
+    cycle: Vec<PlaylistItem>,
+    pos: usize,
+}
+
+impl Playback {
+    /// Continues playback from the item after the one at the cursor.
+    ///
+    /// The item is found at the position of the cursor, or else by its id. Starts from the beginning
+    /// if no item with the id is found. Since the order of a shuffled cycle is random, the item is
+    /// instead moved to the end of it.
+    pub fn resume_after(mut self, cursor: &Cursor) -> Self {
+        let id = cursor.id.as_str();
+        let at_pos = cursor
+            .pos
+            .filter(|pos| self.cycle.get(*pos).is_some_and(|i| i.id() == id));
+        let pos = at_pos
+            .or_else(|| self.cycle.iter().position(|i| i.id() == id))
+            .or_else(|| {
+                // Entries of a feed change when it is fetched, so continue after what is left of the feed
+                let feed = feed_id(id)?;
+                self.cycle
+                    .iter()
+                    .rposition(|i| feed_id(i.id()) == Some(feed))
+            });
+        if let Some(pos) = pos {
+            if self.shuffle {
+                let last = self.cycle.len() - 1;
+                self.cycle.swap(pos, last);
+            } else {
+                self.pos = (pos + 1) % self.cycle.len();
+            }
+        }
+        self
+    }
+
+    /// Yields each item together with its position in the cycle
+    pub fn with_positions(mut self) -> impl Iterator<Item = (usize, PlaylistItem)> {
+        std::iter::from_fn(move || {
+            let pos = self.pos;
+            self.next().map(|item| (pos, item))
+        })
+    }
+
+    fn reshuffle(&mut self) {
+        let last = self.cycle.last().map(|i| i.id().to_string());
+        self.cycle.shuffle(&mut rand::rng());
+        // Avoid showing the same item twice in a row when a new cycle starts
+        if self.cycle.len() > 1 && self.cycle.first().map(|i| i.id()) == last.as_deref() {
+            let last = self.cycle.len() - 1;
+            self.cycle.swap(0, last);
+        }
+    }
+}
+
+impl Iterator for Playback {
+    type Item = PlaylistItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.cycle.get(self.pos)?.clone();
+        self.pos += 1;
+        if self.pos == self.cycle.len() {
+            self.pos = 0;
+            if self.shuffle {
+                self.reshuffle();
+            }
+        }
+        Some(item)
+    }
+}
+
+/// Endless iterator over the items of a Playlist shared by a group of displays,
+/// yielding each item together with the time it should be shown
+pub struct SyncedPlayback {
+    cycle: Vec<PlaylistItem>,
+    index: usize,
+    start: DateTime<Utc>,
+}
+
+impl Iterator for SyncedPlayback {
+    type Item = (PlaylistItem, DateTime<Utc>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.cycle.get(self.index)?.clone();
+        let start = self.start;
+        self.start += TimeDelta::seconds(item.duration() as i64);
+        self.index = (self.index + 1) % self.cycle.len();
+        Some((item, start))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use chrono::{TimeDelta, TimeZone, Utc};
+
+    use crate::store::store::{PlaylistItem, TextData};
+
+    use super::{Cursor, MAX_REPEATS, PlaybackMode, Weight};
+
+    fn text(id: &str, duration: u64) -> PlaylistItem {
+        PlaylistItem::Text {
+            id: id.to_string(),
+            settings: TextData {
+                text: id.to_string(),
+                duration,
+                format: None,
+            },
+        }
+    }
+
+    fn cursor(id: &str, pos: Option<usize>) -> Cursor {
+        Cursor {
+            id: id.to_string(),
+            pos,
+        }
+    }
+
+    fn ids(items: impl Iterator<Item = PlaylistItem>) -> Vec<String> {
+        items.map(|i| i.id().to_string()).collect()
+    }
+
+    #[test]
+    fn test_sequential_cycles_in_order() {
+        let items = vec![text("a", 10), text("b", 10), text("c", 10)];
+        assert_eq!(
+            vec!["a", "b", "c", "a", "b", "c", "a"],
+            ids(PlaybackMode::Sequential.play(items).take(7))
+        );
+    }
+
+    #[test]
+    fn test_shuffle_plays_every_item_each_cycle() {
+        let items = vec![text("a", 10), text("b", 10), text("c", 10), text("d", 10)];
+        let played = ids(PlaybackMode::Shuffle.play(items).take(40));
+
+        for cycle in played.chunks(4) {
+            let mut cycle = cycle.to_vec();
+            cycle.sort();
+            assert_eq!(vec!["a", "b", "c", "d"], cycle);
+        }
+        assert!(played.windows(2).all(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn test_resume_after_item() {
+        let items = vec![text("a", 10), text("b", 10), text("c", 10)];
+        let mode = PlaybackMode::Sequential;
+
+        assert_eq!(
+            vec!["c", "a", "b"],
+            ids(mode
+                .play(items.clone())
+                .resume_after(&cursor("b", None))
+                .take(3))
+        );
+        assert_eq!(
+            vec!["a", "b", "c"],
+            ids(mode
+                .play(items.clone())
+                .resume_after(&cursor("c", None))
+                .take(3))
+        );
+        // Item removed from the Playlist while it was being shown
+        assert_eq!(
+            vec!["a", "b", "c"],
+            ids(mode
+                .play(items)
+                .resume_after(&cursor("removed", None))
+                .take(3))
+        );
+    }
+
+    #[test]
+    fn test_resume_after_repeated_item() {
+        let items = vec![text("ad", 10), text("b", 10), text("c", 10)];
+        let mode = PlaybackMode::Weighted(HashMap::from([("ad".to_string(), Weight::Repeat(2))]));
+        let played = mode.play(items.clone()).with_positions().take(4);
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            played.map(|(pos, _)| pos).collect::<Vec<_>>()
+        );
+
+        // The second repetition of "ad" is at the end of the cycle
+        assert_eq!(
+            vec!["ad", "b"],
+            ids(mode
+                .play(items.clone())
+                .resume_after(&cursor("ad", Some(3)))
+                .take(2))
+        );
+        // Playlist changed since, so the position holds another item
+        assert_eq!(
+            vec!["b", "c"],
+            ids(mode
+                .play(items)
+                .resume_after(&cursor("ad", Some(2)))
+                .take(2))
+        );
+    }
+
+    #[test]
+    fn test_resume_after_item_shuffled() {
+        let items = vec![text("a", 10), text("b", 10), text("c", 10)];
+        let played = ids(PlaybackMode::Shuffle
+            .play(items)
+            .resume_after(&cursor("b", None))
+            .take(3));
+
+        assert_eq!("b", played[2]);
+    }
+
+    #[test]
+    fn test_synced_playback_follows_epoch() {
+        let items = vec![text("a", 10), text("b", 20), text("c", 30)];
+        let epoch = Utc.with_ymd_and_hms(2023, 4, 18, 10, 0, 0).unwrap();
+        let at = |s| epoch + TimeDelta::seconds(s);
+
+        // Two full cycles and 15 seconds into the third, in the middle of "b"
+        let mut playback = PlaybackMode::Sequential.play_synced(items.clone(), epoch, at(135));
+        let (item, start) = playback.next().unwrap();
+        assert_eq!(("b", at(130)), (item.id(), start));
+        let (item, start) = playback.next().unwrap();
+        assert_eq!(("c", at(150)), (item.id(), start));
+        let (item, start) = playback.next().unwrap();
+        assert_eq!(("a", at(180)), (item.id(), start));
+
+        // Displays joining at different times agree on the order of a shuffled Playlist
+        let synced = |now| {
+            PlaybackMode::Shuffle
+                .play_synced(items.clone(), epoch, now)
+                .take(3)
+                .map(|(i, _)| i.id().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(synced(at(60)), synced(at(120)));
+    }
+
+    #[test]
+    fn test_synced_playback_stops_at_item_without_duration() {
+        let items = vec![text("a", 10), text("forever", 0), text("c", 30)];
+        let epoch = Utc.with_ymd_and_hms(2023, 4, 18, 10, 0, 0).unwrap();
+
+        let mut playback =
+            PlaybackMode::Sequential.play_synced(items, epoch, epoch + TimeDelta::days(3));
+        let (item, start) = playback.next().unwrap();
+        assert_eq!(
+            ("forever", epoch + TimeDelta::seconds(10)),
+            (item.id(), start)
+        );
+    }
+
+    #[test]
+    fn test_weighted_repeat_spreads_items() {
+        let items = vec![text("ad", 10), text("b", 10), text("c", 10)];
+        let mode = PlaybackMode::Weighted(HashMap::from([("ad".to_string(), Weight::Repeat(2))]));
+
+        assert_eq!(vec!["ad", "b", "c", "ad"], ids(mode.play(items).take(4)));
+    }
+
+    #[test]
+    fn test_weighted_share_of_airtime() {
+        // 40 seconds of unshared content, so a 50% share of a 10 second slide needs 4 repetitions
+        let items = vec![text("a", 20), text("b", 20), text("sponsor", 10)];
+        let mode =
+            PlaybackMode::Weighted(HashMap::from([("sponsor".to_string(), Weight::Share(0.5))]));
+
+        let cycle = ids(mode.play(items).take(6));
+        assert_eq!(4, cycle.iter().filter(|i| *i == "sponsor").count());
+        assert_eq!(1, cycle.iter().filter(|i| *i == "a").count());
+        assert_eq!(1, cycle.iter().filter(|i| *i == "b").count());
+    }
+
+    #[test]
+    fn test_weighted_feed_entries() {
+        let items = vec![text("news/0", 10), text("news/1", 10), text("a", 60)];
+
+        let mode = PlaybackMode::Weighted(HashMap::from([("news".to_string(), Weight::Repeat(2))]));
+        let cycle = ids(mode.play(items.clone()).take(5));
+        assert_eq!(2, cycle.iter().filter(|i| *i == "news/0").count());
+        assert_eq!(2, cycle.iter().filter(|i| *i == "news/1").count());
+
+        // The entries together get half of the airtime, 60 of 120 seconds
+        let mode =
+            PlaybackMode::Weighted(HashMap::from([("news".to_string(), Weight::Share(0.5))]));
+        let cycle = ids(mode.play(items).take(7));
+        assert_eq!(3, cycle.iter().filter(|i| *i == "news/0").count());
+        assert_eq!(3, cycle.iter().filter(|i| *i == "news/1").count());
+        assert_eq!(1, cycle.iter().filter(|i| *i == "a").count());
+    }
+
+    #[test]
+    fn test_resume_after_feed_entry() {
+        let items = vec![text("news/0", 10), text("news/1", 10), text("a", 10)];
+        let mode = PlaybackMode::Sequential;
+
+        assert_eq!(
+            vec!["a", "news/0"],
+            ids(mode
+                .play(items.clone())
+                .resume_after(&cursor("news/1", None))
+                .take(2))
+        );
+        // The feed has fewer entries after it was fetched again
+        assert_eq!(
+            vec!["a", "news/0"],
+            ids(mode
+                .play(items)
+                .resume_after(&cursor("news/4", None))
+                .take(2))
+        );
+    }
+
+    #[test]
+    fn test_validate_weights() {
+        let items = vec![text("a", 20), text("b", 0)];
+
+        let weights = |w: Vec<(&str, Weight)>| {
+            PlaybackMode::Weighted(w.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+        };
+
+        assert!(
+            weights(vec![("a", Weight::Repeat(3))])
+                .validate(&items)
+                .is_ok()
+        );
+        assert!(
+            weights(vec![("a", Weight::Share(0.3))])
+                .validate(&items)
+                .is_ok()
+        );
+        assert!(
+            weights(vec![("c", Weight::Repeat(3))])
+                .validate(&items)
+                .is_err()
+        );
+        assert!(
+            weights(vec![("a", Weight::Repeat(0))])
+                .validate(&items)
+                .is_err()
+        );
+        assert!(
+            weights(vec![("a", Weight::Share(1.0))])
+                .validate(&items)
+                .is_err()
+        );
+        assert!(
+            weights(vec![("b", Weight::Share(0.2))])
+                .validate(&items)
+                .is_err()
+        );
+        assert!(
+            weights(vec![("a", Weight::Repeat(u32::MAX))])
+                .validate(&items)
+                .is_err()
+        );
+
+        // A 1 second item needs millions of repeats to get most of the airtime beside a day long one
+        let items = vec![text("a", 86_400), text("b", 1)];
+        assert!(
+            weights(vec![("b", Weight::Share(0.99))])
+                .validate(&items)
+                .is_err()
+        );
+        // Each item is within the limit, but the cycle is too long
+        let items = (0..200)
+            .map(|i| text(&i.to_string(), 10))
+            .collect::<Vec<_>>();
+        let many = (0..200).map(|i| (i.to_string(), Weight::Repeat(MAX_REPEATS as u32)));
+        assert!(
+            PlaybackMode::Weighted(many.collect())
+                .validate(&items)
+                .is_err()
+        );
+    }
+}
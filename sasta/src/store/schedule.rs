@@ -1,15 +1,27 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr, sync::Arc};
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, TimeDelta};
 use cron::Schedule as CronSchedule;
 use serde::{Deserialize, Serialize};
-use tracing::{error, warn};
+use tracing::warn;
 use ts_rs::TS;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use super::calendar::{CalendarInput, Occurrence};
+
+/// How far ahead the start and end of scheduled playlists are checked for firing at the same moment
+pub const CONFLICT_HORIZON: TimeDelta = TimeDelta::days(366);
+/// Fire times stepped through at most while checking, so rules firing every second stay cheap to check
+const CONFLICT_MAX_STEPS: usize = 20_000;
+
 #[derive(Debug, Clone)]
 enum ScheduledItem {
+    /// Playlists of the events in a calendar, None until the calendar has been fetched
+    Calendar {
+        calendar: CalendarInput,
+        occurrences: Option<Arc<Vec<Occurrence>>>,
+    },
     Schedule {
         start: CronSchedule,
         end: CronSchedule,
@@ -26,6 +38,61 @@ enum NextMoment {
     Continue(DateTime<Local>),
     /// No future scheduled times exists, a moment will not be found
     Exhausted,
+    /// The scheduled times can not decide the active playlist
+    Error(ScheduleError),
+}
+
+/// Reason a Schedule can not be created or stopped deciding its active playlist
+#[derive(Serialize, Debug, Clone, PartialEq, TS, ToSchema)]
+#[serde(tag = "type", content = "content")]
+#[ts(export, export_to = "api_bindings/read/")]
+pub enum ScheduleError {
+    InvalidCron {
+        expression: String,
+        message: String,
+    },
+    /// Start and end of a scheduled playlist fire at the same moment, so it is undecided if the playlist is active
+    Conflict {
+        #[ts(type = "string")]
+        playlist: Uuid,
+        start: String,
+        end: String,
+        /// First moment both fire, in RFC 3339
+        time: String,
+    },
+    InvalidCalendar(String),
+}
+
+impl ScheduleError {
+    /// Code of the error in Api payloads
+    pub fn code(&self) -> u8 {
+        match self {
+            ScheduleError::InvalidCalendar(_) => 6,
+            ScheduleError::InvalidCron { .. } => 7,
+            ScheduleError::Conflict { .. } => 8,
+        }
+    }
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::InvalidCron {
+                expression,
+                message,
+            } => write!(f, "invalid cron expression '{expression}' ({message})"),
+            ScheduleError::Conflict {
+                playlist,
+                start,
+                end,
+                time,
+            } => write!(
+                f,
+                "start '{start}' and end '{end}' of playlist {playlist} both fire at {time}"
+            ),
+            ScheduleError::InvalidCalendar(e) => write!(f, "invalid calendar ({e})"),
+        }
+    }
 }
 
 impl From<&NextMoment> for bool {
@@ -45,11 +112,26 @@ pub struct Schedule {
 }
 
 impl Schedule {
+    /// Creates a Schedule, rejecting scheduled playlists whose start and end fire at the same moment within a year from `now`
     pub fn new(
         name: String,
         schedules_input: Vec<ScheduledPlaylistInput>,
         playlist: Uuid,
-    ) -> Result<Self, String> {
+        now: &DateTime<Local>,
+    ) -> Result<Self, ScheduleError> {
+        let schedule = Self::build(name, schedules_input, playlist)?;
+        match schedule.conflict(now) {
+            Some(e) => Err(e),
+            None => Ok(schedule),
+        }
+    }
+
+    /// Creates a Schedule without checking for conflicts, which stored Schedules may already have
+    fn build(
+        name: String,
+        schedules_input: Vec<ScheduledPlaylistInput>,
+        playlist: Uuid,
+    ) -> Result<Self, ScheduleError> {
         let mut schedules = Vec::with_capacity(schedules_input.len());
 
         for s in schedules_input {
@@ -68,11 +150,82 @@ impl Schedule {
         })
     }
 
-    fn str_to_cron(s: &str) -> Result<CronSchedule, String> {
-        match CronSchedule::from_str(s) {
-            Ok(s) => Ok(s),
-            Err(_) => Err(format!("invalid cron expression '{}'", s)),
+    /// Adds the calendar to the Schedule, taking priority over the cron scheduled playlists
+    pub fn with_calendar(mut self, calendar: Option<CalendarInput>) -> Result<Self, ScheduleError> {
+        if let Some(calendar) = calendar {
+            calendar
+                .validate()
+                .map_err(ScheduleError::InvalidCalendar)?;
+            self.schedules.insert(
+                0,
+                ScheduledItem::Calendar {
+                    calendar,
+                    occurrences: None,
+                },
+            );
         }
+        Ok(self)
+    }
+
+    pub fn calendar(&self) -> Option<&CalendarInput> {
+        self.schedules.iter().find_map(|s| match s {
+            ScheduledItem::Calendar { calendar, .. } => Some(calendar),
+            _ => None,
+        })
+    }
+
+    /// True if the Schedule has a calendar whose occurrences have not been loaded yet
+    pub fn calendar_pending(&self) -> bool {
+        self.schedules.iter().any(|s| {
+            matches!(
+                s,
+                ScheduledItem::Calendar {
+                    occurrences: None,
+                    ..
+                }
+            )
+        })
+    }
+
+    /// Sets the occurrences of the calendar's events, returns true if they changed
+    pub fn set_occurrences(&mut self, new: Vec<Occurrence>) -> bool {
+        self.schedules.iter_mut().any(|s| match s {
+            ScheduledItem::Calendar { occurrences, .. } if occurrences.as_deref() != Some(&new) => {
+                *occurrences = Some(Arc::new(new.clone()));
+                true
+            }
+            _ => false,
+        })
+    }
+
+    fn str_to_cron(s: &str) -> Result<CronSchedule, ScheduleError> {
+        CronSchedule::from_str(s).map_err(|e| {
+            let hint = match s.split_whitespace().count() {
+                5 => ", expected seconds as the first of 6 or 7 fields",
+                _ => "",
+            };
+            ScheduleError::InvalidCron {
+                expression: s.to_string(),
+                message: format!("{e}{hint}"),
+            }
+        })
+    }
+
+    /// First scheduled playlist whose start and end fire at the same moment within the horizon from the given time
+    pub fn conflict(&self, from: &DateTime<Local>) -> Option<ScheduleError> {
+        self.schedules.iter().find_map(|s| match s {
+            ScheduledItem::Schedule {
+                start,
+                end,
+                playlist,
+            } => first_shared_time(start, end, from).map(|time| ScheduleError::Conflict {
+                playlist: *playlist,
+                start: start.to_string(),
+                end: end.to_string(),
+                time: time.to_rfc3339(),
+            }),
+            _ => None,
+        })
     }
 
     /// Provides last scheduled time from given `DateTime` including the current time if applicable.
@@ -125,6 +278,11 @@ impl Schedule {
                             last_start.and_then(|_| Some(playlist.clone()))
                         }
                     }
+                    ScheduledItem::Calendar { occurrences, .. } => occurrences
+                        .as_ref()?
+                        .iter()
+                        .find(|o| &o.start <= time && time < &o.end)
+                        .map(|o| o.playlist),
                     ScheduledItem::Fallback(uuid) => Some(uuid.clone()),
                 }
             })
@@ -142,7 +300,8 @@ impl Schedule {
     /// Returns next scheduled moment if any
     ///
     /// Does not return scheduled moments at the exact time passed as argument
-    pub fn next_schedule(&self, from: &DateTime<Local>) -> Option<Moment> {
+    /// Returns an error if a scheduled playlist's start and end fire at the same moment before the next moment
+    pub fn next_schedule(&self, from: &DateTime<Local>) -> Result<Option<Moment>, ScheduleError> {
         // Vector with closures returning the next scheduled time
         let mut future_moments = self
             .schedules
@@ -159,7 +318,7 @@ impl Schedule {
 
                         let current_playlist = self.current_playlist(&from);
 
-                        Some(move || {
+                        Some(Box::new(move || {
                             let time_opt = if &current_playlist == playlist {
                                 // Only use the end scheduled times if current schedule's playlist is already active
                                 next_end_iter.next()
@@ -175,9 +334,12 @@ impl Schedule {
                                         std::cmp::Ordering::Less => next_start_iter.next(),
                                         std::cmp::Ordering::Greater => next_end_iter.next(),
                                         std::cmp::Ordering::Equal => {
-                                            //TODO: Actual error handling here; extend enum with error type
-                                            error!("Schedule {} has a start and end time at the same timestamp with the scheduled playlist {}. Marking playlist schedule as exhausted to avoid issues. TODO: Better error handling here", self.name, playlist);
-                                            return NextMoment::Exhausted;
+                                            return NextMoment::Error(ScheduleError::Conflict {
+                                                playlist: *playlist,
+                                                start: start.to_string(),
+                                                end: end.to_string(),
+                                                time: s.to_rfc3339(),
+                                            });
                                         }
                                     },
                                 }
@@ -197,7 +359,34 @@ impl Schedule {
                             }
                             // Return if no future schedules times exists for schedule
                             NextMoment::Exhausted
-                        })
+                        }) as Box<dyn FnMut() -> NextMoment>)
+                    }
+                    ScheduledItem::Calendar { occurrences, .. } => {
+                        let current_playlist = self.current_playlist(from);
+                        // The active playlist may change whenever an event starts or ends
+                        let mut times = occurrences
+                            .iter()
+                            .flat_map(|o| o.iter())
+                            .flat_map(|o| [o.start, o.end])
+                            .filter(|t| t > from)
+                            .collect::<Vec<_>>();
+                        times.sort();
+                        times.dedup();
+                        let mut times = times.into_iter();
+
+                        Some(Box::new(move || match times.next() {
+                            Some(time) => {
+                                let playlist_at_moment = self.current_playlist(&time);
+                                if playlist_at_moment != current_playlist {
+                                    return NextMoment::Moment(Moment {
+                                        time,
+                                        playlist: playlist_at_moment,
+                                    });
+                                }
+                                NextMoment::Continue(time)
+                            }
+                            None => NextMoment::Exhausted,
+                        }) as Box<dyn FnMut() -> NextMoment>)
                     }
                     // Fallback is not used in this function, ignore
                     ScheduledItem::Fallback(_) => None,
@@ -230,9 +419,16 @@ impl Schedule {
                 }
             }
 
+            if let Some((NextMoment::Error(e), _)) = future_moments
+                .iter()
+                .find(|(res, _)| matches!(res, NextMoment::Error(_)))
+            {
+                return Err(e.clone());
+            }
+
             // Check if all results are exhausted and exits if that is the case
             if let None = future_moments.iter().find(|(res, _)| res.into()) {
-                return None;
+                return Ok(None);
             }
 
             // Checks the lowest timestamp of all results (trying to exclude Exhausted) and returns the result if
@@ -244,32 +440,52 @@ impl Schedule {
                 future_moments.iter().min_by_key(|(res, _)| match res {
                     NextMoment::Moment(m) => m.time,
                     NextMoment::Continue(t) => *t,
-                    NextMoment::Exhausted => max_date,
+                    NextMoment::Exhausted | NextMoment::Error(_) => max_date,
                 })
             {
-                return Some(m.to_owned());
+                return Ok(Some(m.to_owned()));
             }
         }
     }
 
-    /// True if the Schedule has any scheduled playlists
-    pub fn has_scheduled_playlists(&self) -> bool {
-        return !self.schedules.is_empty();
-    }
-
     /// Returns Vec of the Uuids of all playlists (fallback + any scheduled) which the schedule contains
     pub fn all_playlists(&self) -> Vec<&Uuid> {
         self.schedules
             .iter()
-            .map(|s| match s {
+            .flat_map(|s| match s {
                 ScheduledItem::Schedule { playlist, .. } | ScheduledItem::Fallback(playlist) => {
-                    playlist
+                    vec![playlist]
+                }
+                ScheduledItem::Calendar { calendar, .. } => {
+                    calendar.rules.iter().map(|r| &r.playlist).collect()
                 }
             })
             .collect()
     }
 }
 
+/// First time within the conflict horizon from the given time that both expressions fire
+pub fn first_shared_time(
+    start: &CronSchedule,
+    end: &CronSchedule,
+    from: &DateTime<Local>,
+) -> Option<DateTime<Local>> {
+    let until = *from + CONFLICT_HORIZON;
+    let (mut starts, mut ends) = (start.after(from).peekable(), end.after(from).peekable());
+    for _ in 0..CONFLICT_MAX_STEPS {
+        match (starts.peek(), ends.peek()) {
+            (Some(s), Some(e)) if s > &until || e > &until => return None,
+            (Some(s), Some(e)) => match s.cmp(e) {
+                std::cmp::Ordering::Less => starts.next(),
+                std::cmp::Ordering::Greater => ends.next(),
+                std::cmp::Ordering::Equal => return Some(*s),
+            },
+            _ => return None,
+        };
+    }
+    None
+}
+
 #[derive(Debug, Clone)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct Moment {
@@ -286,6 +502,8 @@ pub struct ScheduleInput {
     pub name: String,
     pub scheduled: Option<Vec<ScheduledPlaylistInput>>,
     pub playlist: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calendar: Option<CalendarInput>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, TS, ToSchema)]
@@ -303,11 +521,13 @@ impl<'de> Deserialize<'de> for Schedule {
         D: serde::Deserializer<'de>,
     {
         let input = ScheduleInput::deserialize(deserializer)?;
-        match Schedule::new(
+        match Schedule::build(
             input.name,
             input.scheduled.unwrap_or(vec![]),
             input.playlist,
-        ) {
+        )
+        .and_then(|s| s.with_calendar(input.calendar))
+        {
             Ok(s) => Ok(s),
             Err(s) => Err(serde::de::Error::custom(s)),
         }
@@ -346,7 +566,7 @@ impl From<Schedule> for ScheduleInput {
                         start: start.to_string(),
                         end: end.to_string(),
                     }),
-                    ScheduledItem::Fallback(_) => None,
+                    ScheduledItem::Calendar { .. } | ScheduledItem::Fallback(_) => None,
                 })
                 .collect::<Vec<_>>();
 
@@ -357,18 +577,27 @@ impl From<Schedule> for ScheduleInput {
             name: value.name.clone(),
             scheduled,
             playlist: *fallback,
+            calendar: value.calendar().cloned(),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use chrono::{Local, TimeZone};
+    use chrono::{DateTime, Local, TimeZone};
     use uuid::Uuid;
 
-    use crate::store::schedule::Moment;
+    use crate::store::{
+        calendar::{CalendarInput, Occurrence},
+        schedule::Moment,
+    };
 
-    use super::{Schedule, ScheduledPlaylistInput};
+    use super::{Schedule, ScheduleError, ScheduledPlaylistInput};
+
+    /// Time the Schedules are created at, checked for conflicts from
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2023, 4, 17, 9, 0, 0).unwrap()
+    }
 
     #[test]
     fn test_next_schedule_many_schedules_with_wildcards() {
@@ -403,7 +632,7 @@ mod test {
             },
         ];
         let schedule: Schedule =
-            Schedule::new("test".to_string(), schedules, default_uuid).unwrap();
+            Schedule::new("test".to_string(), schedules, default_uuid, &now()).unwrap();
 
         let first_schedule_start = Moment {
             time: Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 0).unwrap(),
@@ -420,18 +649,21 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 9, 59, 59).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 0).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 1).unwrap())
                 .unwrap()
+                .unwrap()
         );
 
         let second_schedule_end = Moment {
@@ -444,18 +676,21 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 13, 59, 59).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             second_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 0, 0).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             second_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 0, 1).unwrap())
                 .unwrap()
+                .unwrap()
         );
 
         let forth_schedule_end = Moment {
@@ -473,18 +708,21 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 16, 59, 59).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_start_next_day,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 17, 0, 0).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_start_next_day,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 17, 0, 1).unwrap())
                 .unwrap()
+                .unwrap()
         );
     }
 
@@ -506,7 +744,7 @@ mod test {
             },
         ];
         let schedule: Schedule =
-            Schedule::new("test".to_string(), schedules, default_uuid).unwrap();
+            Schedule::new("test".to_string(), schedules, default_uuid, &now()).unwrap();
 
         assert_eq!(
             Moment {
@@ -516,6 +754,7 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 9, 59, 59).unwrap())
                 .unwrap()
+                .unwrap()
         );
 
         let first_schedule_end = Moment {
@@ -528,12 +767,14 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 0).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 1).unwrap())
                 .unwrap()
+                .unwrap()
         );
     }
 
@@ -555,7 +796,7 @@ mod test {
             },
         ];
         let schedule: Schedule =
-            Schedule::new("test".to_string(), schedules, default_uuid).unwrap();
+            Schedule::new("test".to_string(), schedules, default_uuid, &now()).unwrap();
 
         assert_eq!(
             Moment {
@@ -565,6 +806,7 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 9, 59, 59).unwrap())
                 .unwrap()
+                .unwrap()
         );
 
         let first_schedule_end = Moment {
@@ -577,12 +819,14 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 0).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 1).unwrap())
                 .unwrap()
+                .unwrap()
         );
 
         assert_eq!(
@@ -590,18 +834,21 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 59, 59).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 11, 0, 0).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 11, 0, 1).unwrap())
                 .unwrap()
+                .unwrap()
         );
 
         let second_schedule_end = Moment {
@@ -614,18 +861,21 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 13, 59, 59).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             second_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 0, 0).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             second_schedule_end,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 0, 1).unwrap())
                 .unwrap()
+                .unwrap()
         );
 
         let first_schedule_start = Moment {
@@ -638,18 +888,21 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 59, 59).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_start,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 15, 0, 0).unwrap())
                 .unwrap()
+                .unwrap()
         );
         assert_eq!(
             first_schedule_start,
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 15, 0, 1).unwrap())
                 .unwrap()
+                .unwrap()
         );
     }
 
@@ -671,7 +924,7 @@ mod test {
             },
         ];
         let schedule: Schedule =
-            Schedule::new("test".to_string(), schedules, default_uuid).unwrap();
+            Schedule::new("test".to_string(), schedules, default_uuid, &now()).unwrap();
 
         assert_eq!(
             Moment {
@@ -681,6 +934,7 @@ mod test {
             schedule
                 .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 9, 59, 59).unwrap())
                 .unwrap()
+                .unwrap()
         );
 
         let first_schedule_end = Some(Moment {
@@ -690,24 +944,34 @@ mod test {
 
         assert_eq!(
             first_schedule_end,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 0).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 0).unwrap())
+                .unwrap()
         );
         assert_eq!(
             first_schedule_end,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 1).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 0, 1).unwrap())
+                .unwrap()
         );
 
         assert_eq!(
             first_schedule_end,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 59, 59).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 10, 59, 59).unwrap())
+                .unwrap()
         );
         assert_eq!(
             first_schedule_end,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 11, 0, 0).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 11, 0, 0).unwrap())
+                .unwrap()
         );
         assert_eq!(
             first_schedule_end,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 11, 0, 1).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 11, 0, 1).unwrap())
+                .unwrap()
         );
 
         let second_schedule_end = Some(Moment {
@@ -717,28 +981,40 @@ mod test {
 
         assert_eq!(
             first_schedule_end,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 13, 59, 59).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 13, 59, 59).unwrap())
+                .unwrap()
         );
         assert_eq!(
             second_schedule_end,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 0, 0).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 0, 0).unwrap())
+                .unwrap()
         );
         assert_eq!(
             second_schedule_end,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 0, 1).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 0, 1).unwrap())
+                .unwrap()
         );
 
         assert_eq!(
             second_schedule_end,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 59, 59).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 14, 59, 59).unwrap())
+                .unwrap()
         );
         assert_eq!(
             None,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 15, 0, 0).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 15, 0, 0).unwrap())
+                .unwrap()
         );
         assert_eq!(
             None,
-            schedule.next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 15, 0, 1).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2023, 4, 18, 15, 0, 1).unwrap())
+                .unwrap()
         );
     }
 
@@ -752,7 +1028,7 @@ mod test {
             end: "0 0 14 18 4 * 2023".to_string(),
         }];
         let schedule: Schedule =
-            Schedule::new("test".to_string(), schedules, default_uuid).unwrap();
+            Schedule::new("test".to_string(), schedules, default_uuid, &now()).unwrap();
 
         assert_eq!(
             default_uuid,
@@ -792,7 +1068,7 @@ mod test {
             end: "0 0 11 * * * 2025/1".to_string(),
         }];
         let schedule: Schedule =
-            Schedule::new("test".to_string(), schedules, default_uuid).unwrap();
+            Schedule::new("test".to_string(), schedules, default_uuid, &now()).unwrap();
 
         assert_eq!(
             default_uuid,
@@ -812,7 +1088,9 @@ mod test {
                 time: Local.with_ymd_and_hms(2025, 1, 1, 10, 0, 0).unwrap(),
                 playlist: scheduled_uuid
             }),
-            schedule.next_schedule(&Local.with_ymd_and_hms(2024, 8, 8, 13, 42, 00).unwrap())
+            schedule
+                .next_schedule(&Local.with_ymd_and_hms(2024, 8, 8, 13, 42, 00).unwrap())
+                .unwrap()
         );
     }
 
@@ -825,7 +1103,8 @@ mod test {
             start: "0 * 10 * * * *".to_string(),
             end: "0 0 14 * * * *".to_string(),
         }];
-        let schedule: Schedule = Schedule::new("test".to_string(), playlist, default_uuid).unwrap();
+        let schedule: Schedule =
+            Schedule::new("test".to_string(), playlist, default_uuid, &now()).unwrap();
 
         assert_eq!(
             default_uuid,
@@ -863,7 +1142,7 @@ mod test {
             start: "0 * 10 32 10 * *".to_string(),
             end: "0 0 14 32 10 * *".to_string(),
         }];
-        assert!(Schedule::new("test".into(), playlist, default_uuid).is_err());
+        assert!(Schedule::new("test".into(), playlist, default_uuid, &now()).is_err());
     }
 
     #[test]
@@ -884,7 +1163,7 @@ mod test {
             },
         ];
         let schedule: Schedule =
-            Schedule::new("test".to_string(), playlist.clone(), default_uuid).unwrap();
+            Schedule::new("test".to_string(), playlist.clone(), default_uuid, &now()).unwrap();
 
         assert_eq!(
             scheduled_uuid,
@@ -900,7 +1179,8 @@ mod test {
         );
 
         playlist.reverse();
-        let schedule: Schedule = Schedule::new("test".to_string(), playlist, default_uuid).unwrap();
+        let schedule: Schedule =
+            Schedule::new("test".to_string(), playlist, default_uuid, &now()).unwrap();
 
         assert_eq!(
             scheduled_uuid,
@@ -915,4 +1195,134 @@ mod test {
             schedule.current_playlist(&Local.with_ymd_and_hms(2023, 4, 18, 14, 0, 1).unwrap())
         );
     }
+
+    #[test]
+    fn test_calendar_takes_priority_over_cron_schedules() {
+        let event_uuid = Uuid::parse_str("8626f6e1-df7c-48d9-83c8-d7845b774ecd").unwrap();
+        let scheduled_uuid = Uuid::parse_str("d125a360-4e41-45d5-b6c7-ea471c542510").unwrap();
+        let default_uuid = Uuid::parse_str("25cd63df-1f10-4c3f-afdb-58156ca47ebd").unwrap();
+        let time = |h, m| Local.with_ymd_and_hms(2023, 4, 18, h, m, 0).unwrap();
+
+        let mut schedule = Schedule::new(
+            "test".to_string(),
+            vec![ScheduledPlaylistInput {
+                playlist: scheduled_uuid,
+                start: "0 0 12 * * * *".to_string(),
+                end: "0 0 16 * * * *".to_string(),
+            }],
+            default_uuid,
+            &time(9, 0),
+        )
+        .unwrap()
+        .with_calendar(Some(CalendarInput {
+            source: "rooms.ics".to_string(),
+            rules: vec![],
+        }))
+        .unwrap();
+        assert!(schedule.calendar_pending());
+        assert!(schedule.set_occurrences(vec![
+            Occurrence {
+                start: time(10, 0),
+                end: time(11, 0),
+                playlist: event_uuid,
+            },
+            Occurrence {
+                start: time(13, 0),
+                end: time(14, 30),
+                playlist: event_uuid,
+            },
+        ]));
+        assert!(!schedule.calendar_pending());
+
+        let moments = std::iter::successors(schedule.next_schedule(&time(9, 0)).unwrap(), |m| {
+            schedule.next_schedule(&m.time).unwrap()
+        })
+        .take(6)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Moment {
+                    time: time(10, 0),
+                    playlist: event_uuid
+                },
+                Moment {
+                    time: time(11, 0),
+                    playlist: default_uuid
+                },
+                Moment {
+                    time: time(12, 0),
+                    playlist: scheduled_uuid
+                },
+                Moment {
+                    time: time(13, 0),
+                    playlist: event_uuid
+                },
+                Moment {
+                    time: time(14, 30),
+                    playlist: scheduled_uuid
+                },
+                Moment {
+                    time: time(16, 0),
+                    playlist: default_uuid
+                },
+            ],
+            moments
+        );
+    }
+
+    #[test]
+    fn test_conflicting_start_and_end() {
+        let scheduled_uuid = Uuid::parse_str("8626f6e1-df7c-48d9-83c8-d7845b774ecd").unwrap();
+        let default_uuid = Uuid::parse_str("25cd63df-1f10-4c3f-afdb-58156ca47ebd").unwrap();
+        let scheduled = |start: &str, end: &str| ScheduledPlaylistInput {
+            playlist: scheduled_uuid,
+            start: start.to_string(),
+            end: end.to_string(),
+        };
+
+        // Every monday at 10 both start and end fire
+        let conflicting = vec![scheduled("0 0 10 * * *", "0 0 */2 * * Mon")];
+        let Err(ScheduleError::Conflict { playlist, time, .. }) = Schedule::new(
+            "test".to_string(),
+            conflicting.clone(),
+            default_uuid,
+            &now(),
+        ) else {
+            panic!("Conflict was not detected");
+        };
+        assert_eq!(scheduled_uuid, playlist);
+        assert!(time.contains("T10:00:00"));
+
+        assert!(matches!(
+            Schedule::new(
+                "test".to_string(),
+                vec![scheduled("0 10 * * *", "0 0 14 * * *")],
+                default_uuid,
+                &now()
+            ),
+            Err(ScheduleError::InvalidCron { .. })
+        ));
+        assert!(
+            Schedule::new(
+                "test".to_string(),
+                vec![scheduled("0 0 10 * * Mon", "0 0 14 * * Mon")],
+                default_uuid,
+                &now()
+            )
+            .is_ok()
+        );
+
+        // Stored Schedules are loaded as is, the conflict shows up once it is reached
+        let stored: Schedule = serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "scheduled": conflicting,
+            "playlist": default_uuid,
+        }))
+        .unwrap();
+        let from = Local.with_ymd_and_hms(2023, 4, 17, 9, 0, 0).unwrap();
+        assert!(matches!(
+            stored.next_schedule(&from),
+            Err(ScheduleError::Conflict { .. })
+        ));
+    }
 }
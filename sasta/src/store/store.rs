@@ -1,9 +1,15 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use chrono::Local;
+use chrono::{DateTime, Local, NaiveTime, TimeDelta, Utc};
 use redis::RedisError;
 #[cfg(not(test))]
-use redis::{Client, JsonAsyncCommands, aio::ConnectionManager};
+use redis::{AsyncCommands, Client, JsonAsyncCommands, aio::ConnectionManager};
 use serde::{Deserialize, Deserializer, Serialize};
 
 #[cfg(not(test))]
@@ -11,24 +17,42 @@ use tokio::sync::Mutex;
 use tokio::{
     sync::{
         RwLock, RwLockReadGuard, RwLockWriteGuard,
-        broadcast::{self, Receiver, Sender},
+        broadcast::{self, Receiver, Sender, error::RecvError},
         oneshot,
     },
     time::{Instant, sleep_until},
 };
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 #[cfg(not(test))]
-use tracing::{error_span, warn, warn_span};
+use tracing::{error_span, warn_span};
 use ts_rs::TS;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-use super::schedule::{self, Moment, Schedule};
+use super::{
+    calendar::{self, CalendarInput},
+    clock::Clock,
+    feed::{self, Feeds},
+    playback::{Cursor, PlaybackMode},
+    schedule::Schedule,
+};
+
+/// Longest time the scheduler waits before checking the active playlists against the clock again
+const SCHEDULE_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the feeds of Feed items are checked for being due to be fetched
+const FEED_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(Serialize, Debug, Clone)]
 pub struct Display {
     pub name: String,
     pub display_material: DisplayMaterial,
+    /// Displays in the same sync group showing the same Playlist change items at the same moment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_group: Option<String>,
+    #[serde(default)]
+    pub transition: Transition,
+    #[serde(default)]
+    pub settings: DisplaySettings,
 }
 
 // Explicit implementation to cover for the new format to cover backward compatibility
@@ -42,6 +66,12 @@ impl<'de> Deserialize<'de> for Display {
         pub struct NewDisplay {
             pub name: String,
             pub display_material: DisplayMaterial,
+            #[serde(default)]
+            pub sync_group: Option<String>,
+            #[serde(default)]
+            pub transition: Transition,
+            #[serde(default)]
+            pub settings: DisplaySettings,
         }
 
         #[derive(Deserialize)]
@@ -61,13 +91,22 @@ impl<'de> Deserialize<'de> for Display {
             TempDisplay::New(NewDisplay {
                 name,
                 display_material,
+                sync_group,
+                transition,
+                settings,
             }) => Ok(Display {
                 name,
                 display_material,
+                sync_group,
+                transition,
+                settings,
             }),
             TempDisplay::Old(OldDisplay { name, schedule }) => Ok(Display {
                 name,
                 display_material: DisplayMaterial::Schedule(schedule),
+                sync_group: None,
+                transition: Transition::default(),
+                settings: DisplaySettings::default(),
             }),
         }
     }
@@ -83,10 +122,172 @@ pub enum DisplayMaterial {
     Playlist(#[ts(type = "string")] Uuid),
 }
 
+/// How a display switches from one item to the next
+#[derive(Deserialize, Serialize, Debug, ToSchema, TS, Clone, Copy, Default, PartialEq)]
+#[ts(export, export_to = "api_bindings/create/")]
+#[serde(rename_all = "snake_case", tag = "type", content = "duration")]
+pub enum Transition {
+    #[default]
+    Cut,
+    /// Fade the next item in over the given amount of milliseconds
+    Crossfade(u32),
+}
+
+impl From<Transition> for casta_protocol::Transition {
+    fn from(t: Transition) -> Self {
+        match t {
+            Transition::Cut => casta_protocol::Transition::Cut,
+            Transition::Crossfade(ms) => casta_protocol::Transition::Crossfade(ms),
+        }
+    }
+}
+
+/// How a display presents its content, independent of what it shows
+#[derive(Deserialize, Serialize, Debug, ToSchema, TS, Clone, PartialEq)]
+#[ts(export, export_to = "api_bindings/create/")]
+#[serde(default)]
+pub struct DisplaySettings {
+    /// Clockwise rotation of the content in degrees, one of 0, 90, 180 and 270
+    pub rotation: u16,
+    pub scaling: Scaling,
+    /// Volume of media in percent
+    pub volume: u8,
+    /// CSS colour shown behind the content
+    pub background: String,
+    /// Margin kept around the content for screens cutting off the edges,
+    /// in percent of the shorter side of the screen
+    pub overscan: u8,
+    #[ts(optional)]
+    pub night_mode: Option<NightMode>,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        casta_protocol::DisplaySettings::default().into()
+    }
+}
+
+impl DisplaySettings {
+    /// Checks that every setting is within its range, returning what is wrong otherwise
+    pub fn validate(&self) -> Result<(), String> {
+        if ![0, 90, 180, 270].contains(&self.rotation) {
+            return Err(format!(
+                "Rotation must be one of 0, 90, 180 and 270, not {}",
+                self.rotation
+            ));
+        }
+        if self.volume > 100 {
+            return Err(format!("Volume must be at most 100, not {}", self.volume));
+        }
+        // Any more than this and there would be nothing left to show
+        if self.overscan > 25 {
+            return Err(format!(
+                "Overscan must be at most 25, not {}",
+                self.overscan
+            ));
+        }
+        // Only allow what can be put in a CSS declaration
+        if self.background.is_empty()
+            || !self
+                .background
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "#(),.% ".contains(c))
+        {
+            return Err(format!(
+                "Background {:?} is not a valid colour",
+                self.background
+            ));
+        }
+        if let Some(night_mode) = &self.night_mode {
+            for time in [&night_mode.start, &night_mode.end] {
+                // Clients compare the times as strings, so they must be zero-padded
+                if time.len() != 5 || NaiveTime::parse_from_str(time, "%H:%M").is_err() {
+                    return Err(format!(
+                        "Night mode time {time:?} must be formatted as HH:MM"
+                    ));
+                }
+            }
+            if night_mode.brightness > 100 {
+                return Err(format!(
+                    "Night mode brightness must be at most 100, not {}",
+                    night_mode.brightness
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<casta_protocol::DisplaySettings> for DisplaySettings {
+    fn from(s: casta_protocol::DisplaySettings) -> Self {
+        DisplaySettings {
+            rotation: s.rotation,
+            scaling: match s.scaling {
+                casta_protocol::Scaling::Contain => Scaling::Contain,
+                casta_protocol::Scaling::Cover => Scaling::Cover,
+            },
+            volume: s.volume,
+            background: s.background,
+            overscan: s.overscan,
+            night_mode: s.night_mode.map(|n| NightMode {
+                start: n.start,
+                end: n.end,
+                brightness: n.brightness,
+            }),
+        }
+    }
+}
+
+impl From<DisplaySettings> for casta_protocol::DisplaySettings {
+    fn from(s: DisplaySettings) -> Self {
+        casta_protocol::DisplaySettings {
+            rotation: s.rotation,
+            scaling: match s.scaling {
+                Scaling::Contain => casta_protocol::Scaling::Contain,
+                Scaling::Cover => casta_protocol::Scaling::Cover,
+            },
+            volume: s.volume,
+            background: s.background,
+            overscan: s.overscan,
+            night_mode: s.night_mode.map(|n| casta_protocol::NightMode {
+                start: n.start,
+                end: n.end,
+                brightness: n.brightness,
+            }),
+        }
+    }
+}
+
+/// How images are scaled to the screen
+#[derive(Deserialize, Serialize, Debug, ToSchema, TS, Clone, Copy, Default, PartialEq)]
+#[ts(export, export_to = "api_bindings/create/")]
+#[serde(rename_all = "snake_case")]
+pub enum Scaling {
+    /// Show the whole image, leaving bars where the aspect ratio differs
+    #[default]
+    Contain,
+    /// Fill the screen, cutting off what does not fit
+    Cover,
+}
+
+/// Dims the display during a part of the day
+#[derive(Deserialize, Serialize, Debug, ToSchema, TS, Clone, PartialEq)]
+#[ts(export, export_to = "api_bindings/create/")]
+pub struct NightMode {
+    /// Local time of the display formatted as HH:MM at which night mode starts
+    pub start: String,
+    /// Local time of the display formatted as HH:MM at which night mode ends, may be before `start`
+    pub end: String,
+    /// Brightness in percent during night mode
+    pub brightness: u8,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Playlist {
     pub name: String,
     pub items: Vec<PlaylistItem>,
+    #[serde(default)]
+    pub playback: PlaybackMode,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, ToSchema, TS)]
@@ -121,8 +322,63 @@ pub enum PlaylistItem {
     PortableDocumentFormat {
         #[serde(alias = "name")]
         id: String,
-        settings: ImageData,
+        settings: PdfData,
     },
+    /// Expanded into Text items by `Store::get_display_playlist`
+    #[serde(rename = "FEED")]
+    Feed { id: String, settings: FeedData },
+}
+
+impl PlaylistItem {
+    pub fn id(&self) -> &str {
+        match self {
+            PlaylistItem::Website { id, .. }
+            | PlaylistItem::Text { id, .. }
+            | PlaylistItem::Image { id, .. }
+            | PlaylistItem::BackgroundAudio { id, .. }
+            | PlaylistItem::PortableDocumentFormat { id, .. }
+            | PlaylistItem::Feed { id, .. } => id,
+        }
+    }
+
+    /// Duration in seconds the item is displayed, zero meaning forever
+    pub fn duration(&self) -> u64 {
+        match self {
+            PlaylistItem::Website { settings, .. } => settings.duration,
+            PlaylistItem::Text { settings, .. } => settings.duration,
+            PlaylistItem::Feed { settings, .. } => settings.duration,
+            PlaylistItem::Image { settings, .. }
+            | PlaylistItem::BackgroundAudio { settings, .. } => settings.duration,
+            PlaylistItem::PortableDocumentFormat { settings, .. } => {
+                settings.duration * settings.shown_pages().count() as u64
+            }
+        }
+    }
+
+    /// Splits a PDF item paging through its document into an item per page, each shown for the duration of a page
+    ///
+    /// The pages keep the id of the item, so playback continues after the document once it has been shown.
+    /// Other items are returned as they are.
+    pub fn pages(self) -> Vec<PlaylistItem> {
+        match self {
+            PlaylistItem::PortableDocumentFormat { id, settings } if settings.pages.is_some() => {
+                settings
+                    .shown_pages()
+                    .map(|page| PlaylistItem::PortableDocumentFormat {
+                        id: id.clone(),
+                        settings: PdfData {
+                            // Standard way to open a PDF document at a page, which the clients follow
+                            src: format!("{}#page={page}", settings.src),
+                            duration: settings.duration,
+                            pages: None,
+                            page_count: settings.page_count,
+                        },
+                    })
+                    .collect()
+            }
+            item => vec![item],
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, ToSchema, TS)]
@@ -135,8 +391,50 @@ pub struct WebsiteData {
 #[derive(Deserialize, Serialize, Debug, Clone, ToSchema, TS)]
 #[ts(export, export_to = "api_bindings/update/")]
 pub struct TextData {
+    /// May contain the template variables `{{display.name}}`, `{{date}}`, `{{time}}`
+    /// and `{{countdown:<RFC 3339 timestamp>}}`
     pub text: String,
     pub duration: u64,
+    /// Plain text if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub format: Option<TextFormat>,
+}
+
+/// Slides made from the entries of an RSS, Atom or JSON feed
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/update/")]
+pub struct FeedData {
+    pub url: String,
+    /// Text of a slide, where `{{entry.title}}`, `{{entry.summary}}`, `{{entry.link}}` and `{{entry.date}}`
+    /// are replaced with the values of an entry, besides the variables of a Text item
+    pub template: String,
+    /// Plain text if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub format: Option<TextFormat>,
+    /// Amount of entries shown, the first ones in the feed
+    pub entries: u32,
+    /// Show the entries on a single slide, separated by newlines, instead of one slide per entry
+    #[serde(default)]
+    pub combined: bool,
+    /// Seconds each slide is shown
+    pub duration: u64,
+    /// Seconds between fetches of the feed, defaults to 300
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub refresh: Option<u64>,
+}
+
+/// How the text of a Text item is written
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/update/")]
+#[serde(rename_all = "snake_case")]
+pub enum TextFormat {
+    #[default]
+    Plain,
+    /// Markdown, where raw HTML is shown as text
+    Markdown,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, ToSchema, TS)]
@@ -146,6 +444,82 @@ pub struct ImageData {
     pub duration: u64,
 }
 
+/// PDF document showing its first page, or paging through a range of its pages
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/update/")]
+pub struct PdfData {
+    pub src: String,
+    /// Seconds the document is shown, or each of its pages when paging through them
+    pub duration: u64,
+    /// Pages shown one after another, only the first page is shown if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub pages: Option<PageRange>,
+    /// Amount of pages in the document, found by Sasta when the Playlist is updated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub page_count: Option<u32>,
+}
+
+/// Pages of a PDF document, counting from 1
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema, TS)]
+#[ts(export, export_to = "api_bindings/update/")]
+pub struct PageRange {
+    pub first: u32,
+    /// The last page of the document if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub last: Option<u32>,
+}
+
+impl PdfData {
+    /// Pages shown one after another, ending at the last page of the document
+    pub fn shown_pages(&self) -> RangeInclusive<u32> {
+        let Some(range) = &self.pages else {
+            return 1..=1;
+        };
+        let last = match (range.last, self.page_count) {
+            (Some(last), Some(count)) => last.min(count),
+            (Some(last), None) => last,
+            (None, Some(count)) => count,
+            (None, None) => range.first,
+        };
+        range.first.min(last)..=last
+    }
+
+    /// Checks that the pages can be paged through, returning what is wrong otherwise
+    pub fn validate(&self) -> Result<(), String> {
+        let Some(range) = &self.pages else {
+            return Ok(());
+        };
+        if range.first == 0 {
+            return Err("Pages are counted from 1, so the first page cannot be 0".to_string());
+        }
+        if let Some(last) = range.last.filter(|last| *last < range.first) {
+            return Err(format!(
+                "Last page {last} comes before the first page {}",
+                range.first
+            ));
+        }
+        if self.duration == 0 {
+            return Err(
+                "Pages must be shown for more than 0 seconds to page through them".to_string(),
+            );
+        }
+        match (self.page_count, range.last) {
+            (Some(count), _) if range.first > count => Err(format!(
+                "{} has {count} pages, so it cannot start at page {}",
+                self.src, range.first
+            )),
+            (None, None) => Err(format!(
+                "The amount of pages in {} is not known, so the last page must be given",
+                self.src
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
 // TODO: Replace Content, and use redis as only storage
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Content {
@@ -159,25 +533,39 @@ pub struct Store {
     con: Mutex<ConnectionManager>,
     sender: Sender<Change>,
     content: RwLock<Content>,
+    /// Last PlaylistItem sent to each Display
+    ///
+    /// Kept outside of Content since it changes with every sent item
+    cursors: RwLock<HashMap<Uuid, Cursor>>,
+    /// Time each sync group started playing a Playlist, shared by all displays in the group
+    sync_epochs: RwLock<HashMap<(String, Uuid), DateTime<Utc>>>,
+    feeds: Feeds,
+    /// Time Schedules are evaluated at and items are shown by
+    clock: Arc<dyn Clock>,
 }
 
 impl Store {
     #[cfg(not(test))]
-    pub async fn new(redis_url: &str) -> Self {
+    pub async fn new(redis_url: &str, clock: Arc<dyn Clock>) -> Self {
         let client = Client::open(redis_url).unwrap();
         let mut con = ConnectionManager::new(client).await.unwrap();
         let (sender, _) = broadcast::channel(5);
         let content = RwLock::new(Self::read_file(&mut con).await);
+        let cursors = RwLock::new(Self::read_cursors(&mut con).await);
 
         Store {
             con: Mutex::new(con),
             sender,
             content,
+            cursors,
+            sync_epochs: RwLock::new(HashMap::new()),
+            feeds: Feeds::new(),
+            clock,
         }
     }
 
     #[cfg(test)]
-    pub async fn new(_redis_url: &str) -> Self {
+    pub async fn new(_redis_url: &str, clock: Arc<dyn Clock>) -> Self {
         let (sender, _) = broadcast::channel(5);
         let content = RwLock::new(Content {
             displays: HashMap::new(),
@@ -185,7 +573,14 @@ impl Store {
             schedules: HashMap::new(),
         });
 
-        Store { sender, content }
+        Store {
+            sender,
+            content,
+            cursors: RwLock::new(HashMap::new()),
+            sync_epochs: RwLock::new(HashMap::new()),
+            feeds: Feeds::new(),
+            clock,
+        }
     }
 
     #[cfg(not(test))]
@@ -206,176 +601,267 @@ impl Store {
         }
     }
 
-    /// Updates all Schedules' Playlists to the (schedule_uuid, active_playlist_uuid) pairs
-    async fn update_schedule_active_playlist(
+    #[cfg(not(test))]
+    async fn read_cursors(con: &mut ConnectionManager) -> HashMap<Uuid, Cursor> {
+        match con.hgetall::<_, HashMap<String, String>>("cursors").await {
+            Ok(cursors) => cursors
+                .into_iter()
+                .filter_map(|(uuid, cursor)| {
+                    // Cursors used to be only the id of the item
+                    let cursor = serde_json::from_str(&cursor).unwrap_or(Cursor {
+                        id: cursor,
+                        pos: None,
+                    });
+                    Some((Uuid::parse_str(&uuid).ok()?, cursor))
+                })
+                .collect(),
+            Err(e) => {
+                warn!(
+                    "[Store] could not read playback cursors, starting all displays from the beginning ({e})"
+                );
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Sets the active playlist of every Schedule to the one scheduled at the given time
+    ///
+    /// Notifies a change in the Schedules whose active playlist changed, along with the given updated Schedules
+    async fn sync_active_playlists(
         &self,
-        vec: Vec<(Uuid, Uuid)>,
+        now: &DateTime<Local>,
+        updated: HashSet<Uuid>,
     ) -> Result<(), RedisError> {
-        // Exit if schedule does not exists or if it is already set to the given playlist
-        // if !self.read().await.schedules.contains_key(&schedule) || self.read().await.schedules.get(&schedule).unwrap().playlist == active_playlist {
-        //     return;
-        // }
+        // Checked before taking the write lock, since it is done on every check of the clock
+        let drifted = self
+            .read()
+            .await
+            .schedules
+            .values()
+            .any(|s| s.current_playlist(now) != s.playlist);
+        if !drifted && updated.is_empty() {
+            return Ok(());
+        }
 
         self.write(|mut c| {
-            vec.iter().for_each(|(schedule, playlist)| {
-                c.schedules
-                    .entry(*schedule)
-                    .and_modify(|s| s.playlist = *playlist);
-            });
-            Some(Change::Schedule(HashSet::from_iter(
-                vec.iter().map(|v| v.0),
-            )))
+            let mut changed = updated;
+            for (uuid, schedule) in c.schedules.iter_mut() {
+                let playlist = schedule.current_playlist(now);
+                if playlist != schedule.playlist {
+                    info!("[Scheduler] Updating Schedule {uuid} active playlist to {playlist}");
+                    schedule.playlist = playlist;
+                    changed.insert(*uuid);
+                }
+            }
+            changed.retain(|uuid| c.schedules.contains_key(uuid));
+            (!changed.is_empty()).then_some(Change::Schedule(changed))
         })
         .await
     }
 
     /// Starts scheduling loop updating the active playlists when necessary
     ///
+    /// Instead of trusting a single sleep until the next scheduled moment, the active playlists are checked
+    /// against the clock at least every `SCHEDULE_RECHECK_INTERVAL`, correcting them after the clock jumps
+    /// or the host is suspended.
+    ///
     /// Cancels sent token when state has been updated to the active scheduled playlists
     pub async fn schedule_loop(&self, tx: oneshot::Sender<()>) {
-        let mut current_moment = Local::now();
         let mut receiver = self.receiver();
+        let mut tx = Some(tx);
+        // Schedules updated from the Api, notified even if their active playlist stays the same
+        let mut updated = HashSet::new();
+        // Schedules whose error has been logged, to only log it once
+        let mut failing = HashSet::new();
 
-        let schedules: Vec<(Uuid, Schedule)> = self
-            .read()
-            .await
-            .schedules
-            .iter()
-            .map(|(schedule_uuid, schedule)| (schedule_uuid.clone(), schedule.clone()))
-            .collect();
-
-        // Updates all schedules to their current active scheduled playlist
-        if !schedules.is_empty() {
-            // Does not care about redis error, since only changes internal state.
-            // TODO: Make new method which only changes internal state and does not write
-            // to db to make this clearer?
+        loop {
+            let now = self.now();
             let _ = self
-                .write(|mut c| {
-                    schedules.iter().for_each(|(uuid, schedule)| {
-                        c.schedules.entry(*uuid).and_modify(|s| {
-                            s.playlist = schedule.current_playlist(&current_moment)
-                        });
-                    });
-                    // Change notice not needed since main thread waits on oneshot notice before continuing
-                    None
-                })
+                .sync_active_playlists(&now, std::mem::take(&mut updated))
                 .await;
-            info!("[Scheduler] Updated Schedules to current active playlist");
-        }
 
-        // Notify oneshot channel that schedules have been updated to active playlists
-        if let Err(_) = tx.send(()) {
-            error!("[Scheduler] Could not notify listener, sender dropped");
-        }
+            // Notify oneshot channel that schedules have been updated to active playlists
+            if let Some(tx) = tx.take() {
+                info!("[Scheduler] Updated Schedules to current active playlist");
+                if tx.send(()).is_err() {
+                    error!("[Scheduler] Could not notify listener, sender dropped");
+                }
+            }
 
-        'main: loop {
-            let instant = Instant::now();
-            let schedules: Vec<(Uuid, Schedule)> = self
+            let next_moment = self
                 .read()
                 .await
                 .schedules
                 .iter()
-                .map(|(schedule_uuid, schedule)| (schedule_uuid.clone(), schedule.clone()))
-                .collect();
-
-            let mut moments: Vec<(Uuid, Moment)> = schedules
-                .iter()
-                .filter_map(|(schedule_uuid, schedule)| {
-                    match schedule.next_schedule(&current_moment) {
-                        Some(m) => Some((schedule_uuid.clone(), m)),
-                        None => None,
+                .filter_map(|(uuid, schedule)| match schedule.next_schedule(&now) {
+                    Ok(moment) => {
+                        failing.remove(uuid);
+                        moment
+                    }
+                    Err(e) => {
+                        if failing.insert(*uuid) {
+                            error!(
+                                "[Scheduler] Schedule {uuid} stopped changing its active playlist ({e})"
+                            );
+                        }
+                        None
                     }
                 })
-                .collect();
+                .min_by_key(|m| m.time);
 
-            if moments.is_empty() {
-                info!(
-                    "[Scheduler] No loaded Schedule has any scheduled playlists, waiting on an update to a Schedule..."
-                );
-                loop {
-                    match receiver.recv().await {
+            let wait = match &next_moment {
+                Some(m) => (m.time - now)
+                    .to_std()
+                    .unwrap_or_default()
+                    .min(SCHEDULE_RECHECK_INTERVAL),
+                None => SCHEDULE_RECHECK_INTERVAL,
+            };
+            match &next_moment {
+                Some(m) => trace!(
+                    "[Scheduler] Sleeping for {wait:?}, next change is to {} at {}",
+                    m.playlist, m.time
+                ),
+                None => trace!("[Scheduler] Sleeping for {wait:?}, no change is scheduled"),
+            }
+
+            let deadline = Instant::now() + wait;
+            loop {
+                tokio::select! {
+                    _ = sleep_until(deadline) => break,
+                    change = receiver.recv() => match change {
                         Ok(Change::ScheduleInput(uuids)) => {
-                            let read = self.read().await;
-                            if uuids.iter().any(|u| {
-                                read.schedules
-                                    .get(u)
-                                    .is_some_and(|s| s.has_scheduled_playlists())
-                            }) {
-                                info!(
-                                    "[Scheduler] An updated Schedule has scheduled playlists, rerunning loop"
-                                );
-                                break;
-                            }
+                            info!("[Scheduler] Schedules updated, rerunning loop");
+                            updated.extend(uuids);
+                            break;
                         }
-                        Err(e) => error!("[Scheduler] RecvError: {e}"),
-                        _ => info!("[Scheduler] Non relevant change received, continue waiting"),
-                    }
+                        // Missed changes may have included updated Schedules
+                        Err(RecvError::Lagged(_)) => break,
+                        Err(RecvError::Closed) => {
+                            error!("[Scheduler] Change channel closed, stopping");
+                            return;
+                        }
+                        _ => trace!("[Scheduler] Non relevant change received, continue waiting"),
+                    },
                 }
-                continue;
             }
+        }
+    }
 
-            let closest_time = moments.iter().min_by_key(|(_, m)| m.time).unwrap().1.time;
+    /// Keeps the feeds of Feed items up to date, notifying a change in the Playlists using a feed that changed
+    pub async fn feed_loop(&self) {
+        let mut interval = tokio::time::interval(FEED_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut refresh: HashMap<String, Duration> = HashMap::new();
+            let mut used_by: HashMap<String, HashSet<Uuid>> = HashMap::new();
+            for (uuid, playlist) in &self.read().await.playlists {
+                for item in &playlist.items {
+                    if let PlaylistItem::Feed { settings, .. } = item {
+                        let every =
+                            Duration::from_secs(settings.refresh.unwrap_or(feed::DEFAULT_REFRESH));
+                        refresh
+                            .entry(settings.url.clone())
+                            .and_modify(|d| *d = (*d).min(every))
+                            .or_insert(every);
+                        used_by
+                            .entry(settings.url.clone())
+                            .or_default()
+                            .insert(*uuid);
+                    }
+                }
+            }
 
-            moments = moments
-                .into_iter()
-                .filter(|(_, m)| m.time == closest_time)
+            let changed: HashSet<Uuid> = self
+                .feeds
+                .refresh(refresh)
+                .await
+                .iter()
+                .filter_map(|url| used_by.get(url))
+                .flatten()
+                .copied()
                 .collect();
+            if !changed.is_empty() {
+                info!("[Store] Feeds changed in Playlists {changed:?}");
+                if let Err(e) = self.sender.send(Change::Playlist(changed)) {
+                    trace!("[Store] No active channels to listen in ({})", e)
+                }
+            }
+        }
+    }
 
-            let sleep = match (closest_time - Local::now()).to_std() {
-                Ok(d) => instant + d,
-                Err(_) => instant,
+    /// Keeps the occurrences of the Schedules' calendars up to date, notifying a change in the Schedules whose occurrences changed
+    ///
+    /// Calendars of new or updated Schedules are fetched once the Schedule is changed, the rest on an interval.
+    /// Calendar files are only read from within `calendar_path`, none are read if it is not given.
+    pub async fn calendar_loop(&self, calendar_path: Option<PathBuf>) {
+        let client = reqwest::Client::new();
+        let mut interval = tokio::time::interval(calendar::REFRESH_INTERVAL);
+        let mut receiver = self.receiver();
+        loop {
+            let only_pending = tokio::select! {
+                _ = interval.tick() => false,
+                change = receiver.recv() => match change {
+                    Ok(Change::ScheduleInput(_)) => true,
+                    _ => continue,
+                },
             };
+            let calendars: Vec<(Uuid, CalendarInput)> = self
+                .read()
+                .await
+                .schedules
+                .iter()
+                .filter(|(_, s)| !only_pending || s.calendar_pending())
+                .filter_map(|(uuid, s)| Some((*uuid, s.calendar()?.clone())))
+                .collect();
+            if calendars.is_empty() {
+                continue;
+            }
 
-            info!(
-                "[Scheduler] Sleeping for {:?} until {} to change active playlists",
-                sleep.duration_since(instant),
-                closest_time.to_string()
-            );
-
-            loop {
-                tokio::select! {
-                    _ = sleep_until(sleep) => {
-                        info!("[Scheduler] Breaking");
-                        break
-                    },
-                    change = receiver.recv() => {
-                        match change {
-                            //TODO: When updating a schedule, the new schedule is overridden in the API, and since the 'set current block' lies before the loop, they are never reverted to the present version
-                            Ok(Change::ScheduleInput(uuids)) => {
-                                info!("[Scheduler] Schedules updated, rerunning loop");
-                                let _ = self.write(|mut c| {
-                                    uuids.iter().for_each(|uuid| {
-                                        c.schedules
-                                            .entry(*uuid)
-                                            .and_modify(|s| s.playlist = s.current_playlist(&current_moment));
-                                    });
-                                    Some(Change::Schedule(uuids))
-                                }).await;
-                                continue 'main
-                            },
-                            Err(e) => error!("[Scheduler] RecvError: {e}"),
-                            _ => trace!("[Scheduler] Non relevant change received, continue waiting"),
-                        }
-                    },
+            let now = self.now();
+            let mut fetched = vec![];
+            for (uuid, calendar) in calendars {
+                match calendar::fetch(&client, &calendar.source, calendar_path.as_deref())
+                    .await
+                    .and_then(|ics| {
+                        calendar::occurrences(
+                            &ics,
+                            &calendar.rules,
+                            now - TimeDelta::days(1),
+                            now + calendar::HORIZON,
+                        )
+                    }) {
+                    Ok(occurrences) => fetched.push((uuid, calendar, occurrences)),
+                    Err(e) => warn!(
+                        "[Calendar] Could not load the calendar of Schedule {uuid}, keeping the last good copy ({e})"
+                    ),
                 }
             }
-            info!("[Scheduler] Sleep done, updating active playlists");
 
             let _ = self
-                .update_schedule_active_playlist(
-                    moments
-                        .iter()
-                        .map(|(u, m)| (*u, m.playlist))
-                        .inspect(|(uuid, _)| {
-                            info!("[Scheduler] Updating Schedule {uuid} active playlist")
-                        })
-                        .collect::<Vec<_>>(),
-                )
+                .write(|mut c| {
+                    let mut changed = HashSet::new();
+                    for (uuid, calendar, occurrences) in fetched {
+                        // Skips Schedules whose calendar was changed while it was fetched
+                        if c.schedules
+                            .get_mut(&uuid)
+                            .filter(|s| s.calendar() == Some(&calendar))
+                            .is_some_and(|s| s.set_occurrences(occurrences))
+                        {
+                            info!("[Calendar] Occurrences of Schedule {uuid} changed");
+                            changed.insert(uuid);
+                        }
+                    }
+                    (!changed.is_empty()).then_some(Change::ScheduleInput(changed))
+                })
                 .await;
-            current_moment = closest_time;
         }
     }
 
+    /// Current time according to the clock of the Store
+    pub fn now(&self) -> DateTime<Local> {
+        self.clock.now()
+    }
+
     /// Returns receiver handle to a watch channel which gets notified if store has been updated
     pub fn receiver(&self) -> Receiver<Change> {
         self.sender.subscribe()
@@ -414,10 +900,13 @@ impl Store {
         }
     }
     #[cfg(test)]
-    async fn write<F>(&self, _fun: F) -> Result<(), RedisError>
+    async fn write<F>(&self, fun: F) -> Result<(), RedisError>
     where
         F: FnOnce(RwLockWriteGuard<Content>) -> Option<Change>,
     {
+        if let Some(c) = fun(self.content.write().await) {
+            let _ = self.sender.send(c);
+        }
         Ok(())
     }
 
@@ -429,6 +918,9 @@ impl Store {
         uuid: Uuid,
         name: String,
         display_material: DisplayMaterial,
+        sync_group: Option<String>,
+        transition: Transition,
+        settings: DisplaySettings,
     ) -> Result<(), RedisError> {
         self.write(|mut c| {
             c.displays.insert(
@@ -436,6 +928,9 @@ impl Store {
                 Display {
                     name,
                     display_material,
+                    sync_group,
+                    transition,
+                    settings,
                 },
             );
             Some(Change::Display(HashSet::from([uuid])))
@@ -453,6 +948,7 @@ impl Store {
                 Playlist {
                     name,
                     items: vec![],
+                    playback: PlaybackMode::default(),
                 },
             );
             Some(Change::Playlist(HashSet::from([uuid])))
@@ -470,8 +966,10 @@ impl Store {
         playlist: Uuid,
     ) -> Result<(), RedisError> {
         self.write(|mut c| {
-            c.schedules
-                .insert(uuid, Schedule::new(name, vec![], playlist).unwrap());
+            c.schedules.insert(
+                uuid,
+                Schedule::new(name, vec![], playlist, &self.now()).unwrap(),
+            );
             Some(Change::Schedule(HashSet::from([uuid])))
         })
         .await
@@ -485,12 +983,18 @@ impl Store {
         uuid: Uuid,
         name: String,
         display_material: DisplayMaterial,
+        sync_group: Option<String>,
+        transition: Transition,
+        settings: DisplaySettings,
     ) -> Result<(), RedisError> {
         self.write(|mut c| {
             c.displays.entry(uuid).and_modify(|d| {
                 *d = Display {
                     name,
                     display_material,
+                    sync_group,
+                    transition,
+                    settings,
                 }
             });
             Some(Change::Display(HashSet::from([uuid])))
@@ -506,11 +1010,21 @@ impl Store {
         uuid: Uuid,
         name: String,
         items: Vec<PlaylistItem>,
+        playback: PlaybackMode,
     ) -> Result<(), RedisError> {
+        // Restart synchronised playback of the Playlist, since the old epoch does not match the new items
+        self.sync_epochs
+            .write()
+            .await
+            .retain(|(_, playlist), _| *playlist != uuid);
         self.write(|mut c| {
-            c.playlists
-                .entry(uuid)
-                .and_modify(|p| *p = Playlist { name, items });
+            c.playlists.entry(uuid).and_modify(|p| {
+                *p = Playlist {
+                    name,
+                    items,
+                    playback,
+                }
+            });
             Some(Change::Playlist(HashSet::from([uuid])))
         })
         .await
@@ -519,29 +1033,25 @@ impl Store {
     /// Updates the Schedule with the given Uuid
     ///
     /// Does nothing if no such Schedule is found
-    pub async fn update_schedule(
-        &self,
-        uuid: Uuid,
-        name: String,
-        playlist: Uuid,
-        schedules: Vec<schedule::ScheduledPlaylistInput>,
-    ) -> Result<(), String> {
-        let schedule = match Schedule::new(name, schedules, playlist) {
-            Ok(s) => s,
-            Err(e) => return Err(e),
-        };
+    pub async fn update_schedule(&self, uuid: Uuid, schedule: Schedule) -> Result<(), RedisError> {
         self.write(|mut c| {
             c.schedules.entry(uuid).and_modify(|s| *s = schedule);
             Some(Change::ScheduleInput(HashSet::from([uuid])))
         })
         .await
-        .map_err(|e| e.to_string())
     }
 
     /// Deletes the display with the given Uuid
     ///
     /// Does nothing if no such display is found
     pub async fn delete_display(&self, uuid: Uuid) -> Result<(), RedisError> {
+        #[cfg(not(test))]
+        self.con
+            .lock()
+            .await
+            .hdel::<_, _, ()>("cursors", uuid.to_string())
+            .await?;
+        self.cursors.write().await.remove(&uuid);
         self.write(|mut c| {
             c.displays.remove(&uuid);
             Some(Change::Display(HashSet::from([uuid])))
@@ -571,19 +1081,57 @@ impl Store {
         .await
     }
 
-    /// Get all PlaylistItem(s) from the active playlist in the display, or the playlist currently active in the display's schedule.
-    pub async fn get_display_playlist_items(&self, display: &Uuid) -> Option<Vec<PlaylistItem>> {
+    /// Get the active playlist in the display, or the playlist currently active in the display's schedule.
+    ///
+    /// Feed items are replaced by the Text items made from their feed.
+    pub async fn get_display_playlist(&self, display: &Uuid) -> Option<Playlist> {
         let content = self.read().await;
-        match &content.displays.get(display)?.display_material {
-            DisplayMaterial::Schedule(uuid) => Some(
-                content
-                    .playlists
-                    .get(&content.schedules.get(uuid)?.playlist)?
-                    .items
-                    .clone(),
-            ),
-            DisplayMaterial::Playlist(uuid) => Some(content.playlists.get(uuid)?.items.clone()),
+        let mut playlist = match &content.displays.get(display)?.display_material {
+            DisplayMaterial::Schedule(uuid) => content
+                .playlists
+                .get(&content.schedules.get(uuid)?.playlist)?
+                .clone(),
+            DisplayMaterial::Playlist(uuid) => content.playlists.get(uuid)?.clone(),
+        };
+        drop(content);
+        playlist.items = self.feeds.expand(playlist.items).await;
+        Some(playlist)
+    }
+
+    /// Last PlaylistItem sent to the Display, if any
+    pub async fn get_cursor(&self, display: &Uuid) -> Option<Cursor> {
+        self.cursors.read().await.get(display).cloned()
+    }
+
+    /// Saves the PlaylistItem last sent to the Display, so playback can be resumed after it
+    pub async fn set_cursor(&self, display: Uuid, cursor: Cursor) {
+        #[cfg(not(test))]
+        if let Err(error) = self
+            .con
+            .lock()
+            .await
+            .hset::<_, _, _, ()>(
+                "cursors",
+                display.to_string(),
+                serde_json::to_string(&cursor).unwrap(),
+            )
+            .await
+        {
+            error_span!("Redis Error", ?error);
         }
+        self.cursors.write().await.insert(display, cursor);
+    }
+
+    /// Time the sync group started playing the Playlist
+    ///
+    /// The first display of the group to ask for the Playlist sets it to the current time.
+    pub async fn sync_epoch(&self, group: &str, playlist: Uuid) -> DateTime<Utc> {
+        *self
+            .sync_epochs
+            .write()
+            .await
+            .entry((group.to_string(), playlist))
+            .or_insert_with(|| self.now().to_utc())
     }
 
     /// Get Uuids of schedule and playlist connected to Display of given Uuid
@@ -621,3 +1169,218 @@ pub enum Change {
     /// and made sure the correct playlist is set
     Schedule(HashSet<Uuid>),
 }
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use chrono::{DateTime, Local, TimeZone};
+    use tokio::sync::oneshot;
+    use uuid::Uuid;
+
+    use crate::store::{
+        clock::ManualClock,
+        schedule::{Schedule, ScheduledPlaylistInput},
+    };
+
+    use super::{Change, DisplaySettings, NightMode, PageRange, PdfData, PlaylistItem, Store};
+
+    const MORNING: Uuid = Uuid::from_u128(1);
+    const NOON: Uuid = Uuid::from_u128(2);
+    const DEFAULT: Uuid = Uuid::from_u128(3);
+
+    fn time(h: u32, m: u32, s: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2025, 3, 10, h, m, s).unwrap()
+    }
+
+    fn schedule(morning_end: &str) -> Schedule {
+        let scheduled = |playlist, start: &str, end: &str| ScheduledPlaylistInput {
+            playlist,
+            start: start.to_string(),
+            end: end.to_string(),
+        };
+        Schedule::new(
+            "test".to_string(),
+            vec![
+                scheduled(MORNING, "0 0 10 * * *", morning_end),
+                scheduled(NOON, "0 0 12 * * *", "0 0 13 * * *"),
+            ],
+            DEFAULT,
+            &time(9, 0, 0),
+        )
+        .unwrap()
+    }
+
+    /// Starts the scheduler with a Schedule playing MORNING from 10 to 11 and NOON from 12 to 13
+    async fn start(clock: Arc<ManualClock>) -> (Arc<Store>, Uuid) {
+        let store = Arc::new(Store::new("not used in test environment", clock).await);
+        let uuid = Uuid::new_v4();
+        store
+            .create_schedule(uuid, "test".to_string(), DEFAULT)
+            .await
+            .unwrap();
+        store
+            .update_schedule(uuid, schedule("0 0 11 * * *"))
+            .await
+            .unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let store_copy = store.clone();
+        tokio::spawn(async move { store_copy.schedule_loop(tx).await });
+        rx.await.unwrap();
+        (store, uuid)
+    }
+
+    async fn active(store: &Store, uuid: Uuid) -> Uuid {
+        store.read().await.schedules[&uuid].playlist
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_schedule_loop_follows_scheduled_moments() {
+        let clock = Arc::new(ManualClock::new(time(9, 59, 30)));
+        let (store, uuid) = start(clock.clone()).await;
+        assert_eq!(DEFAULT, active(&store, uuid).await);
+
+        clock.set(time(10, 0, 0));
+        tokio::time::sleep(Duration::from_secs(31)).await;
+        assert_eq!(MORNING, active(&store, uuid).await);
+
+        // Updated Schedules are applied right away
+        store
+            .update_schedule(uuid, schedule("0 15 10 * * *"))
+            .await
+            .unwrap();
+        clock.set(time(10, 30, 0));
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(DEFAULT, active(&store, uuid).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_schedule_loop_corrects_clock_jumps() {
+        let clock = Arc::new(ManualClock::new(time(9, 0, 0)));
+        let (store, uuid) = start(clock.clone()).await;
+        let mut receiver = store.receiver();
+        assert_eq!(DEFAULT, active(&store, uuid).await);
+
+        // The host wakes up from a suspend, long after MORNING started
+        clock.set(time(12, 30, 0));
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert_eq!(NOON, active(&store, uuid).await);
+        assert!(matches!(receiver.try_recv(), Ok(Change::Schedule(s)) if s.contains(&uuid)));
+
+        // The clock is stepped back
+        clock.set(time(10, 30, 0));
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert_eq!(MORNING, active(&store, uuid).await);
+
+        // Only the change to MORNING is sent, nothing while the active playlist stays correct
+        tokio::time::sleep(Duration::from_secs(600)).await;
+        assert!(matches!(receiver.try_recv(), Ok(Change::Schedule(_))));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_pdf_pages() {
+        let pdf = |first, last, page_count| PdfData {
+            src: "ASTA://slides.pdf".to_string(),
+            duration: 10,
+            pages: Some(PageRange { first, last }),
+            page_count,
+        };
+        let pages = |settings: PdfData| {
+            PlaylistItem::PortableDocumentFormat {
+                id: "slides".to_string(),
+                settings,
+            }
+            .pages()
+            .into_iter()
+            .map(|page| match page {
+                PlaylistItem::PortableDocumentFormat { id, settings } => {
+                    assert_eq!("slides", id);
+                    (settings.src, settings.duration)
+                }
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>()
+        };
+
+        let item = PlaylistItem::PortableDocumentFormat {
+            id: "slides".to_string(),
+            settings: pdf(2, None, Some(4)),
+        };
+        assert_eq!(30, item.duration());
+        assert_eq!(
+            vec![
+                ("ASTA://slides.pdf#page=2".to_string(), 10),
+                ("ASTA://slides.pdf#page=3".to_string(), 10),
+                ("ASTA://slides.pdf#page=4".to_string(), 10),
+            ],
+            pages(pdf(2, None, Some(4)))
+        );
+        // The range is cut off at the end of the document
+        assert_eq!(2, pages(pdf(1, Some(5), Some(2))).len());
+        assert_eq!(
+            vec![("ASTA://slides.pdf".to_string(), 10)],
+            pages(PdfData {
+                pages: None,
+                ..pdf(1, None, None)
+            })
+        );
+
+        assert!(pdf(1, Some(3), None).validate().is_ok());
+        assert!(pdf(0, Some(3), Some(4)).validate().is_err());
+        assert!(pdf(3, Some(2), Some(4)).validate().is_err());
+        assert!(pdf(5, None, Some(4)).validate().is_err());
+        assert!(pdf(1, None, None).validate().is_err());
+    }
+
+    #[test]
+    fn test_display_settings_validation() {
+        assert!(DisplaySettings::default().validate().is_ok());
+        let with_night_mode = |start: &str, end: &str, brightness| DisplaySettings {
+            night_mode: Some(NightMode {
+                start: start.to_string(),
+                end: end.to_string(),
+                brightness,
+            }),
+            ..DisplaySettings::default()
+        };
+        assert!(with_night_mode("22:00", "06:30", 20).validate().is_ok());
+
+        for rotation in [90, 180, 270] {
+            let settings = DisplaySettings {
+                rotation,
+                ..DisplaySettings::default()
+            };
+            assert!(settings.validate().is_ok());
+        }
+        for rotation in [45, 360] {
+            let settings = DisplaySettings {
+                rotation,
+                ..DisplaySettings::default()
+            };
+            assert!(settings.validate().is_err());
+        }
+        let volume = |volume| DisplaySettings {
+            volume,
+            ..DisplaySettings::default()
+        };
+        assert!(volume(100).validate().is_ok());
+        assert!(volume(101).validate().is_err());
+        let overscan = |overscan| DisplaySettings {
+            overscan,
+            ..DisplaySettings::default()
+        };
+        assert!(overscan(25).validate().is_ok());
+        assert!(overscan(26).validate().is_err());
+
+        // Malformed times
+        assert!(with_night_mode("22", "06:30", 20).validate().is_err());
+        assert!(with_night_mode("22:00", "6:30", 20).validate().is_err());
+        assert!(with_night_mode("22:00", "06:30:00", 20).validate().is_err());
+        // Out of range times
+        assert!(with_night_mode("24:00", "06:30", 20).validate().is_err());
+        assert!(with_night_mode("22:00", "06:60", 20).validate().is_err());
+        assert!(with_night_mode("22:00", "06:30", 101).validate().is_err());
+    }
+}